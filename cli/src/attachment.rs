@@ -0,0 +1,265 @@
+use pkarr::{Client, PublicKey, SignedPacket};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tokio::task::JoinSet;
+
+use crate::capabilities::{peer_lacks, FEATURE_CHUNKS};
+use crate::crypto::{
+    chunk_seed, from_base64_url, generate_key, to_base64_url, PayloadKey, SecretSeed, SharedKey,
+};
+use crate::pkarr::{
+    add_txt, keypair_from_seed, open_record, pubkey_from_seed, seal_record, txt_value,
+};
+use crate::types::{Attachment, CompactMessage, FetchFileOutput, SendFileOutput};
+use crate::GhostClient;
+
+/// Largest chunk whose sealed record still fits in a packet.
+pub const ATTACHMENT_CHUNK_SIZE: usize = 400;
+pub const MAX_ATTACHMENT_SIZE: usize = 256 * 1024;
+const MAX_NAME_LENGTH: usize = 64;
+const CHUNK_CONCURRENCY: usize = 8;
+
+/// Text carried by attachment messages, shown as-is by clients that predate them.
+pub fn attachment_text(attachment: &Attachment) -> String {
+    let size = match attachment.size {
+        size if size < 1024 => format!("{} B", size),
+        size => format!("{:.1} KB", size as f64 / 1024.0),
+    };
+    format!("📎 {} ({})", attachment.name, size)
+}
+
+fn file_hash(data: &[u8]) -> String {
+    to_base64_url(&Sha256::digest(data))
+}
+
+fn chunk_length(attachment: &Attachment, index: u32) -> usize {
+    let offset = index as u64 * ATTACHMENT_CHUNK_SIZE as u64;
+    (attachment.size - offset).min(ATTACHMENT_CHUNK_SIZE as u64) as usize
+}
+
+/// Where chunks of an unfinished fetch of `output` are kept.
+fn part_dir(output: &Path) -> PathBuf {
+    let mut dir = output.as_os_str().to_owned();
+    dir.push(".part");
+    PathBuf::from(dir)
+}
+
+async fn publish_chunk(
+    client: &Client,
+    key: &SharedKey,
+    index: u32,
+    chunk: &[u8],
+) -> Result<(), String> {
+    let keypair = keypair_from_seed(&chunk_seed(key, index));
+    let sealed = seal_record(
+        "_chunk",
+        &to_base64_url(chunk),
+        &PayloadKey::shared(key),
+        &keypair.to_z32(),
+    )?;
+    let signed_packet = add_txt(SignedPacket::builder(), "_chunk", &sealed)?
+        .sign(&keypair)
+        .map_err(|e| format!("Sign error: {}", e))?;
+
+    client
+        .publish(&signed_packet, None)
+        .await
+        .map_err(|e| format!("Publish error for chunk {}: {}", index, e))
+}
+
+async fn fetch_chunk(
+    client: &Client,
+    key: &SharedKey,
+    index: u32,
+) -> Result<Option<Vec<u8>>, String> {
+    let chunk_z32 = pubkey_from_seed(&chunk_seed(key, index));
+    let public_key: PublicKey = chunk_z32
+        .as_str()
+        .try_into()
+        .map_err(|e| format!("Invalid public key: {}", e))?;
+
+    let Some(value) = client
+        .resolve_most_recent(&public_key)
+        .await
+        .and_then(|packet| txt_value(&packet, "_chunk"))
+    else {
+        return Ok(None);
+    };
+    let decrypted = open_record("_chunk", &value, &PayloadKey::shared(key), &chunk_z32)
+        .map_err(|_| format!("Chunk {} failed authentication", index))?;
+    from_base64_url(&decrypted.plaintext).map(Some)
+}
+
+impl GhostClient {
+    /// Publishes `path` as encrypted chunks under a fresh key, then sends the
+    /// peer a message carrying the manifest.
+    pub async fn send_file(
+        &self,
+        seed: &SecretSeed,
+        peer_pubkey: &str,
+        shared_key: &SharedKey,
+        path: &Path,
+        nick: Option<&str>,
+    ) -> Result<SendFileOutput, String> {
+        let data = std::fs::read(path).map_err(|e| format!("Read error: {}", e))?;
+        if data.len() > MAX_ATTACHMENT_SIZE {
+            return Err(format!(
+                "File too large: {} bytes (max {})",
+                data.len(),
+                MAX_ATTACHMENT_SIZE
+            ));
+        }
+        let peer_caps = self.peer_capabilities(peer_pubkey).await?;
+        if peer_lacks(peer_caps.as_ref(), FEATURE_CHUNKS) {
+            return Err("Peer's client can't fetch attachments (no chunks support)".to_string());
+        }
+
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().chars().take(MAX_NAME_LENGTH).collect())
+            .unwrap_or_else(|| "file".to_string());
+
+        let key = generate_key();
+        let mut tasks = JoinSet::new();
+        let mut chunks = data.chunks(ATTACHMENT_CHUNK_SIZE).zip(0u32..);
+        loop {
+            while tasks.len() < CHUNK_CONCURRENCY {
+                let Some((chunk, index)) = chunks.next() else {
+                    break;
+                };
+                let client = self.client.clone();
+                let key = key.clone();
+                let chunk = chunk.to_vec();
+                tasks.spawn(async move { publish_chunk(&client, &key, index, &chunk).await });
+            }
+            let Some(joined) = tasks.join_next().await else {
+                break;
+            };
+            joined.map_err(|e| format!("Publish task failed: {}", e))??;
+        }
+
+        let attachment = Attachment {
+            name,
+            size: data.len() as u64,
+            hash: file_hash(&data),
+            chunks: data.len().div_ceil(ATTACHMENT_CHUNK_SIZE) as u32,
+            key,
+        };
+        let draft = CompactMessage {
+            m: attachment_text(&attachment),
+            a: Some(attachment.clone()),
+            ..Default::default()
+        };
+        let sent = self
+            .send_draft(seed, peer_pubkey, shared_key, draft, nick)
+            .await?;
+
+        Ok(SendFileOutput {
+            ok: sent.ok,
+            timestamp: sent.timestamp,
+            id: sent.id,
+            seq: sent.seq,
+            messages_kept: sent.messages_kept,
+            attachment,
+        })
+    }
+
+    /// Downloads and verifies an attachment into `output`. Chunks fetched so
+    /// far are kept next to it, so a failed fetch can simply be run again.
+    pub async fn fetch_file(
+        &self,
+        attachment: &Attachment,
+        output: &Path,
+    ) -> Result<FetchFileOutput, String> {
+        let expected_chunks = attachment.size.div_ceil(ATTACHMENT_CHUNK_SIZE as u64);
+        if attachment.size > MAX_ATTACHMENT_SIZE as u64
+            || attachment.chunks as u64 != expected_chunks
+        {
+            return Err("Invalid attachment manifest: bad size or chunk count".to_string());
+        }
+
+        let parts = part_dir(output);
+        std::fs::create_dir_all(&parts).map_err(|e| format!("Write error: {}", e))?;
+        let missing: Vec<u32> = (0..attachment.chunks)
+            .filter(|index| !parts.join(index.to_string()).exists())
+            .collect();
+        let resumed_chunks = attachment.chunks - missing.len() as u32;
+
+        let mut tasks = JoinSet::new();
+        let mut pending = missing.into_iter();
+        let mut failed = 0;
+        let mut last_error = None;
+        loop {
+            while tasks.len() < CHUNK_CONCURRENCY {
+                let Some(index) = pending.next() else {
+                    break;
+                };
+                let client = self.client.clone();
+                let key = attachment.key.clone();
+                tasks.spawn(async move { (index, fetch_chunk(&client, &key, index).await) });
+            }
+            let Some(joined) = tasks.join_next().await else {
+                break;
+            };
+            let (index, fetched) = joined.map_err(|e| format!("Fetch task failed: {}", e))?;
+            match fetched {
+                Ok(Some(chunk)) if chunk.len() == chunk_length(attachment, index) => {
+                    let tmp = parts.join(format!("{}.tmp", index));
+                    std::fs::write(&tmp, &chunk)
+                        .and_then(|_| std::fs::rename(&tmp, parts.join(index.to_string())))
+                        .map_err(|e| format!("Write error: {}", e))?;
+                }
+                Ok(Some(_)) => {
+                    failed += 1;
+                    last_error = Some(format!("Chunk {} has the wrong length", index));
+                }
+                Ok(None) => failed += 1,
+                Err(e) => {
+                    failed += 1;
+                    last_error = Some(e);
+                }
+            }
+        }
+        if failed > 0 {
+            let mut error = format!(
+                "Fetched {} of {} chunks; run fetch-file again to resume",
+                attachment.chunks - failed,
+                attachment.chunks
+            );
+            if let Some(last_error) = last_error {
+                error.push_str(&format!(" ({})", last_error));
+            }
+            return Err(error);
+        }
+
+        let mut data = Vec::with_capacity(attachment.size as usize);
+        for index in 0..attachment.chunks {
+            let chunk = std::fs::read(parts.join(index.to_string()))
+                .map_err(|e| format!("Read error: {}", e))?;
+            data.extend_from_slice(&chunk);
+        }
+        if data.len() as u64 != attachment.size || file_hash(&data) != attachment.hash {
+            let _ = std::fs::remove_dir_all(&parts);
+            return Err("Integrity check failed: file does not match the manifest".to_string());
+        }
+
+        std::fs::write(output, &data).map_err(|e| format!("Write error: {}", e))?;
+        let _ = std::fs::remove_dir_all(&parts);
+
+        Ok(FetchFileOutput {
+            ok: true,
+            path: output.display().to_string(),
+            size: attachment.size,
+            chunks: attachment.chunks,
+            resumed_chunks,
+        })
+    }
+}
+
+/// File name to save an attachment under, stripped of any directories.
+pub fn attachment_file_name(attachment: &Attachment) -> String {
+    Path::new(&attachment.name)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "attachment".to_string())
+}
@@ -0,0 +1,163 @@
+use pkarr::PublicKey;
+
+use crate::crypto::{PayloadKey, SharedKey};
+use crate::pkarr::txt_value;
+use crate::types::{PeerCapabilities, ResolvedBatch};
+use crate::GhostClient;
+
+/// Highest payload sealing version we write (v2: per-direction keys).
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Sealing version for peers whose packet has no `_v`: released clients from
+/// before it only open the unbound secretbox.
+pub const LEGACY_VERSION: u32 = 0;
+/// Sealing version for peers we haven't seen a packet from yet. The outbox is
+/// resealed on every publish, so an old client gets v0 once it shows up.
+pub const BASELINE_VERSION: u32 = 1;
+
+pub const FEATURE_KINDS: &str = "kinds";
+pub const FEATURE_CHUNKS: &str = "chunks";
+pub const FEATURE_PRESENCE: &str = "presence";
+pub const FEATURE_PROFILE: &str = "profile";
+pub const FEATURE_CALLS: &str = "calls";
+pub const FEATURE_COMPRESSION: &str = "compression";
+pub const FEATURE_RATCHET: &str = "ratchet";
+
+/// Features this client understands, advertised in `_v`.
+pub const CLIENT_FEATURES: &[&str] = &[
+    FEATURE_KINDS,
+    FEATURE_CHUNKS,
+    FEATURE_PRESENCE,
+    FEATURE_PROFILE,
+];
+
+impl PeerCapabilities {
+    pub fn ours() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            features: CLIENT_FEATURES.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /// Parses a `_v` value: the version, then space-separated feature names.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split_whitespace();
+        let version = parts.next()?.parse().ok()?;
+        Some(Self {
+            version,
+            features: parts.map(str::to_string).collect(),
+        })
+    }
+
+    pub fn to_record(&self) -> String {
+        let mut value = self.version.to_string();
+        for feature in &self.features {
+            value.push(' ');
+            value.push_str(feature);
+        }
+        value
+    }
+}
+
+/// Whether `peer` is known to lack `feature`. Peers that predate `_v`
+/// advertise nothing, so nothing is ruled out for them.
+pub fn peer_lacks(peer: Option<&PeerCapabilities>, feature: &str) -> bool {
+    peer.is_some_and(|caps| !caps.supports(feature))
+}
+
+/// Best sealing version both sides understand, given the peer's packet.
+pub fn negotiated_version(peer: Option<&ResolvedBatch>) -> u32 {
+    match peer {
+        None => BASELINE_VERSION,
+        Some(batch) => batch
+            .capabilities
+            .as_ref()
+            .map_or(LEGACY_VERSION, |caps| caps.version.min(PROTOCOL_VERSION)),
+    }
+}
+
+/// Key to seal for `peer_z32` under: the v2 direction key, the static shared
+/// key (v1), or the unbound v0 secretbox, whichever the peer can open.
+pub fn negotiated_send_key(
+    shared_key: &SharedKey,
+    my_z32: &str,
+    peer_z32: &str,
+    peer: Option<&ResolvedBatch>,
+) -> PayloadKey {
+    match negotiated_version(peer) {
+        LEGACY_VERSION => PayloadKey::legacy(shared_key),
+        BASELINE_VERSION => PayloadKey::shared(shared_key),
+        _ => PayloadKey::directed(shared_key, my_z32, peer_z32),
+    }
+}
+
+impl GhostClient {
+    /// What `peer_pubkey` advertises in `_v`. No key is needed: `_v` is
+    /// plaintext.
+    pub async fn peer_capabilities(
+        &self,
+        peer_pubkey: &str,
+    ) -> Result<Option<PeerCapabilities>, String> {
+        let public_key: PublicKey = peer_pubkey
+            .try_into()
+            .map_err(|e| format!("Invalid public key: {}", e))?;
+        Ok(self
+            .client
+            .resolve_most_recent(&public_key)
+            .await
+            .and_then(|packet| txt_value(&packet, "_v"))
+            .and_then(|value| PeerCapabilities::parse(&value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(version: Option<u32>) -> ResolvedBatch {
+        ResolvedBatch {
+            capabilities: version.map(|version| PeerCapabilities {
+                version,
+                features: vec![],
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn negotiates_lowest_common_version() {
+        assert_eq!(negotiated_version(None), BASELINE_VERSION);
+        assert_eq!(negotiated_version(Some(&peer(None))), LEGACY_VERSION);
+        assert_eq!(negotiated_version(Some(&peer(Some(1)))), 1);
+        assert_eq!(negotiated_version(Some(&peer(Some(2)))), 2);
+        assert_eq!(negotiated_version(Some(&peer(Some(7)))), PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn v0_peers_can_open_payloads_when_v_is_absent() {
+        let shared = crate::crypto::generate_key();
+        let key = negotiated_send_key(&shared, "me", "peer", Some(&peer(None)));
+        let sealed = key.encrypt("hi", b"ad").unwrap();
+        assert_eq!(crate::crypto::decrypt(&sealed, &shared).unwrap(), "hi");
+    }
+
+    #[test]
+    fn v1_peers_can_open_negotiated_payloads() {
+        let shared = crate::crypto::generate_key();
+        let ad = b"ad";
+        let peer_recv = PayloadKey::shared(&shared);
+
+        let v1 = negotiated_send_key(&shared, "me", "peer", Some(&peer(Some(1))));
+        let sealed = v1.encrypt("hi", ad).unwrap();
+        assert_eq!(peer_recv.decrypt(&sealed, ad).unwrap().plaintext, "hi");
+
+        let v2 = negotiated_send_key(&shared, "me", "peer", Some(&peer(Some(2))));
+        let sealed = v2.encrypt("hi", ad).unwrap();
+        assert!(peer_recv.decrypt(&sealed, ad).is_err());
+        let v2_recv = PayloadKey::directed(&shared, "me", "peer");
+        assert_eq!(v2_recv.decrypt(&sealed, ad).unwrap().plaintext, "hi");
+    }
+}
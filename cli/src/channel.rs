@@ -0,0 +1,124 @@
+use crate::crypto::{generate_key, generate_message_id, PayloadKey, SharedKey};
+use crate::pkarr::{create_keypair, keypair_from_seed, outbox_from, publish_messages};
+use crate::types::{Ack, Channel, ChannelInfo, CompactMessage};
+use crate::watch::WatchTarget;
+use crate::GhostClient;
+
+const CHANNEL_URL_PREFIX: &str = "ghost-channel://";
+pub const DEFAULT_CHANNEL_WINDOW: usize = 10;
+
+pub fn new_channel(name: &str, window: usize) -> Channel {
+    let (_, seed, pubkey) = create_keypair();
+    Channel {
+        name: name.to_string(),
+        pubkey,
+        key: generate_key(),
+        seed: Some(seed),
+        window: window.max(1),
+        last_seq: 0,
+    }
+}
+
+pub fn channel_url(channel: &Channel) -> String {
+    format!(
+        "{}{}#{}",
+        CHANNEL_URL_PREFIX,
+        channel.pubkey,
+        channel.key.to_base64_url()
+    )
+}
+
+pub fn parse_channel_url(name: &str, url: &str) -> Result<Channel, String> {
+    let rest = url
+        .strip_prefix(CHANNEL_URL_PREFIX)
+        .ok_or("Invalid channel URL: must start with ghost-channel://")?;
+
+    let (pubkey, key) = rest
+        .split_once('#')
+        .ok_or("Invalid channel URL: missing # separator")?;
+
+    Ok(Channel {
+        name: name.to_string(),
+        pubkey: pubkey.to_string(),
+        key: SharedKey::from_base64_url(key)?,
+        seed: None,
+        window: DEFAULT_CHANNEL_WINDOW,
+        last_seq: 0,
+    })
+}
+
+pub fn channel_info(channel: &Channel) -> ChannelInfo {
+    ChannelInfo {
+        name: channel.name.clone(),
+        pubkey: channel.pubkey.clone(),
+        follow_url: channel_url(channel),
+        publisher: channel.seed.is_some(),
+    }
+}
+
+impl From<&Channel> for WatchTarget {
+    fn from(c: &Channel) -> Self {
+        Self {
+            session: Some(c.name.clone()),
+            seed: None,
+            peer: c.pubkey.clone(),
+            key: c.key.clone(),
+            nick: None,
+            rotation: None,
+            migration: None,
+        }
+    }
+}
+
+impl GhostClient {
+    /// Records the new post's seq in `channel.last_seq`; save it back to the store.
+    pub async fn channel_post(
+        &self,
+        channel: &mut Channel,
+        message: &str,
+    ) -> Result<(usize, CompactMessage), String> {
+        let seed = channel
+            .seed
+            .as_ref()
+            .ok_or("Only the channel publisher can post")?;
+        let keypair = keypair_from_seed(seed);
+        let key = PayloadKey::shared(&channel.key);
+
+        let own_batch = self.resolve_own(&keypair, &key).await?;
+        let mut posts = own_batch.as_ref().map(outbox_from).unwrap_or_default();
+        let last_seq = own_batch.map_or(0, |b| b.last_seq).max(channel.last_seq) + 1;
+        channel.last_seq = last_seq;
+
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| format!("Time error: {}", e))?
+            .as_millis() as i64;
+        let post = CompactMessage {
+            t: timestamp,
+            m: message.to_string(),
+            i: Some(generate_message_id()),
+            s: Some(last_seq),
+            r: None,
+            a: None,
+            k: None,
+        };
+        posts.push(post.clone());
+
+        let start = posts.len().saturating_sub(channel.window.max(1));
+        let kept = publish_messages(
+            &self.client,
+            &keypair,
+            &posts[start..],
+            &key,
+            last_seq,
+            &Ack::default(),
+            None,
+            None,
+            None,
+            self.padding,
+        )
+        .await?;
+
+        Ok((kept, post))
+    }
+}
@@ -1,23 +1,140 @@
+use argon2::{Algorithm, Argon2, Params, Version};
 use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
 use base64::Engine;
+use chacha20poly1305::aead::Payload;
+use chacha20poly1305::XChaCha20Poly1305;
 use crypto_secretbox::aead::{Aead, KeyInit};
 use crypto_secretbox::XSalsa20Poly1305;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
 use rand::RngCore;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256, Sha512};
+use std::fmt;
+use zeroize::{Zeroize, Zeroizing};
 
 const NONCE_LENGTH: usize = 24;
+const TAG_LENGTH: usize = 16;
+pub const SEAL_OVERHEAD: usize = 1 + NONCE_LENGTH + TAG_LENGTH;
+const BOUND_VERSION: u8 = 1;
+const DIRECTED_VERSION: u8 = 2;
+const DIRECTION_SALT: &[u8] = b"ghostly/v2/direction";
+const PASSPHRASE_SALT_LENGTH: usize = 16;
+pub const WRAPPED_KEY_LENGTH: usize = PASSPHRASE_SALT_LENGTH + NONCE_LENGTH + 32 + TAG_LENGTH;
+const ARGON2_MEMORY_KIB: u32 = 19_456;
+const ARGON2_ITERATIONS: u32 = 2;
+const ARGON2_PARALLELISM: u32 = 1;
+const SAFETY_NUMBER_DOMAIN: &[u8] = b"ghostly/safety-number";
+const SAFETY_EMOJI: [&str; 64] = [
+    "🐶", "🐱", "🦊", "🐻", "🐼", "🐨", "🐯", "🦁", "🐮", "🐷", "🐸", "🐵", "🐔", "🐧", "🐦", "🦉",
+    "🐝", "🦋", "🐌", "🐢", "🐍", "🐙", "🦀", "🐬", "🐳", "🦈", "🐘", "🦒", "🦓", "🐪", "🦔", "🦇",
+    "🌵", "🌲", "🌻", "🌹", "🍄", "🍀", "🌙", "⭐", "🔥", "🌈", "🍒", "⚡", "🍎", "🍋", "🍇", "🍉",
+    "🍓", "🥕", "🌽", "🍕", "🍩", "🎈", "🎸", "🎲", "🚀", "⚓", "🔑", "🔔", "💎", "🧲", "🎩", "👻",
+];
+const MESSAGE_ID_LENGTH: usize = 8;
+pub const INVITE_TOKEN_LENGTH: usize = 16;
+const RENDEZVOUS_LABEL: &[u8] = b"ghostly/invite/rendezvous";
+const CHUNK_LABEL: &[u8] = b"ghostly/attachment/chunk";
 
-pub fn generate_key() -> [u8; 32] {
-    let mut key = [0u8; 32];
-    rand::rngs::OsRng.fill_bytes(&mut key);
+macro_rules! secret_bytes {
+    ($name:ident, $what:literal) => {
+        /// 32 secret bytes, wiped on drop and redacted in `Debug`; (de)serializes
+        /// as base64url.
+        #[derive(Clone)]
+        pub struct $name([u8; 32]);
+
+        impl $name {
+            pub fn from_bytes(bytes: [u8; 32]) -> Self {
+                Self(bytes)
+            }
+
+            pub fn from_base64_url(s: &str) -> Result<Self, String> {
+                let bytes = Zeroizing::new(from_base64_url(s)?);
+                let arr: [u8; 32] = bytes.as_slice().try_into().map_err(|_| {
+                    format!(
+                        "Invalid {} length: expected 32 bytes, got {}",
+                        $what,
+                        bytes.len()
+                    )
+                })?;
+                Ok(Self(arr))
+            }
+
+            pub fn as_bytes(&self) -> &[u8; 32] {
+                &self.0
+            }
+
+            pub fn to_base64_url(&self) -> String {
+                to_base64_url(&self.0)
+            }
+        }
+
+        impl Drop for $name {
+            fn drop(&mut self) {
+                self.0.zeroize();
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(concat!(stringify!($name), "(<redacted>)"))
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&self.to_base64_url())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let encoded = Zeroizing::new(String::deserialize(deserializer)?);
+                Self::from_base64_url(&encoded).map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+secret_bytes!(SecretSeed, "seed");
+secret_bytes!(SharedKey, "key");
+
+pub fn generate_key() -> SharedKey {
+    let mut key = SharedKey([0u8; 32]);
+    rand::rngs::OsRng.fill_bytes(&mut key.0);
     key
 }
 
-pub fn encrypt(plaintext: &str, key: &[u8]) -> Result<String, String> {
-    let key_arr: &[u8; 32] = key
-        .try_into()
-        .map_err(|_| "Invalid key length: expected 32 bytes")?;
+pub fn generate_message_id() -> String {
+    let mut id = [0u8; MESSAGE_ID_LENGTH];
+    rand::rngs::OsRng.fill_bytes(&mut id);
+    to_base64_url(&id)
+}
 
-    let cipher = XSalsa20Poly1305::new(key_arr.into());
+pub fn generate_invite_token() -> String {
+    let mut token = [0u8; INVITE_TOKEN_LENGTH];
+    rand::rngs::OsRng.fill_bytes(&mut token);
+    to_base64_url(&token)
+}
+
+/// Seed of the keypair an invitee publishes its join under; anyone holding
+/// the invite can derive it, so the record is sealed under the shared key.
+pub fn rendezvous_seed(shared_key: &SharedKey, token: &str) -> SecretSeed {
+    let key = hmac_sha256(shared_key.as_bytes(), &[RENDEZVOUS_LABEL, token.as_bytes()]);
+    SecretSeed::from_bytes(*key)
+}
+
+/// Seed of the keypair attachment chunk `index` is published under.
+pub fn chunk_seed(attachment_key: &SharedKey, index: u32) -> SecretSeed {
+    let key = hmac_sha256(
+        attachment_key.as_bytes(),
+        &[CHUNK_LABEL, &index.to_be_bytes()],
+    );
+    SecretSeed::from_bytes(*key)
+}
+
+pub fn encrypt(plaintext: &str, key: &SharedKey) -> Result<String, String> {
+    let cipher = XSalsa20Poly1305::new(key.as_bytes().into());
 
     let mut nonce_bytes = [0u8; NONCE_LENGTH];
     rand::rngs::OsRng.fill_bytes(&mut nonce_bytes);
@@ -33,12 +150,8 @@ pub fn encrypt(plaintext: &str, key: &[u8]) -> Result<String, String> {
     Ok(STANDARD.encode(&combined))
 }
 
-pub fn decrypt(encoded: &str, key: &[u8]) -> Result<String, String> {
-    let key_arr: &[u8; 32] = key
-        .try_into()
-        .map_err(|_| "Invalid key length: expected 32 bytes")?;
-
-    let cipher = XSalsa20Poly1305::new(key_arr.into());
+pub fn decrypt(encoded: &str, key: &SharedKey) -> Result<String, String> {
+    let cipher = XSalsa20Poly1305::new(key.as_bytes().into());
 
     let combined = STANDARD
         .decode(encoded)
@@ -61,6 +174,343 @@ pub fn decrypt(encoded: &str, key: &[u8]) -> Result<String, String> {
     String::from_utf8(plaintext).map_err(|e| format!("Invalid UTF-8: {}", e))
 }
 
+pub struct Decrypted {
+    pub plaintext: String,
+    pub bound: bool,
+    pub fallback: bool,
+}
+
+pub fn record_ad(label: &str, sender_z32: &str) -> Vec<u8> {
+    format!("ghostly/v{}/{}/{}", BOUND_VERSION, label, sender_z32).into_bytes()
+}
+
+/// HMAC-SHA256 over the concatenation of `parts`.
+fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> Zeroizing<[u8; 32]> {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    Zeroizing::new(mac.finalize().into_bytes().into())
+}
+
+/// HKDF-SHA256 (RFC 5869) expanded to a single 32-byte output.
+fn hkdf_sha256(salt: &[u8], ikm: &[u8], info: &[u8]) -> Zeroizing<[u8; 32]> {
+    let mut okm = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(Some(salt), ikm)
+        .expand(info, okm.as_mut_slice())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    okm
+}
+
+/// HKDF subkey for one direction of a conversation, so the two peers never
+/// encrypt under the same key.
+pub fn direction_key(shared_key: &SharedKey, sender_z32: &str, receiver_z32: &str) -> SharedKey {
+    let info = format!("{}>{}", sender_z32, receiver_z32);
+    SharedKey::from_bytes(*hkdf_sha256(
+        DIRECTION_SALT,
+        shared_key.as_bytes(),
+        info.as_bytes(),
+    ))
+}
+
+/// Safety number for a conversation: the same on both sides, and different if
+/// either pubkey or the shared key differs. Returns 60 digits in groups of
+/// five and an 8-emoji rendering of the same hash.
+pub fn safety_number(shared_key: &SharedKey, a_z32: &str, b_z32: &str) -> (String, String) {
+    let (first, second) = if a_z32 <= b_z32 {
+        (a_z32, b_z32)
+    } else {
+        (b_z32, a_z32)
+    };
+    let mut hasher = Sha512::new();
+    hasher.update(SAFETY_NUMBER_DOMAIN);
+    hasher.update(first.as_bytes());
+    hasher.update([0]);
+    hasher.update(second.as_bytes());
+    hasher.update([0]);
+    hasher.update(shared_key.as_bytes());
+    let hash = hasher.finalize();
+
+    let digits = hash[..60]
+        .chunks(5)
+        .map(|chunk| {
+            let n = chunk.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+            format!("{:05}", n % 100_000)
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    let emoji = hash[..8]
+        .iter()
+        .map(|b| SAFETY_EMOJI[(b % 64) as usize])
+        .collect::<Vec<_>>()
+        .join(" ");
+    (digits, emoji)
+}
+
+fn passphrase_key(passphrase: &str, salt: &[u8]) -> Result<SharedKey, String> {
+    let params = Params::new(
+        ARGON2_MEMORY_KIB,
+        ARGON2_ITERATIONS,
+        ARGON2_PARALLELISM,
+        Some(32),
+    )
+    .map_err(|e| format!("Argon2 error: {}", e))?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut_slice())
+        .map_err(|e| format!("Argon2 error: {}", e))?;
+    Ok(SharedKey::from_bytes(*key))
+}
+
+/// Wraps `key` under an Argon2id key derived from `passphrase`, bound to
+/// `context` (the inviter's pubkey). Returns base64url salt, nonce and sealed key.
+pub fn wrap_key(key: &SharedKey, passphrase: &str, context: &str) -> Result<String, String> {
+    let mut salt = [0u8; PASSPHRASE_SALT_LENGTH];
+    rand::rngs::OsRng.fill_bytes(&mut salt);
+    let mut nonce = [0u8; NONCE_LENGTH];
+    rand::rngs::OsRng.fill_bytes(&mut nonce);
+
+    let wrapping_key = passphrase_key(passphrase, &salt)?;
+    let cipher = XChaCha20Poly1305::new(wrapping_key.as_bytes().into());
+    let payload = Payload {
+        msg: key.as_bytes(),
+        aad: context.as_bytes(),
+    };
+    let ciphertext = cipher
+        .encrypt((&nonce).into(), payload)
+        .map_err(|e| format!("Encryption failed: {}", e))?;
+
+    let mut combined = Vec::with_capacity(salt.len() + nonce.len() + ciphertext.len());
+    combined.extend_from_slice(&salt);
+    combined.extend_from_slice(&nonce);
+    combined.extend_from_slice(&ciphertext);
+    Ok(to_base64_url(&combined))
+}
+
+/// Opens a key sealed by [`wrap_key`]; a wrong passphrase fails authentication
+/// instead of yielding a garbage key.
+pub fn unwrap_key(wrapped: &str, passphrase: &str, context: &str) -> Result<SharedKey, String> {
+    let combined = from_base64_url(wrapped)?;
+    if combined.len() != WRAPPED_KEY_LENGTH {
+        return Err("Invalid wrapped key length".to_string());
+    }
+    let (salt, rest) = combined.split_at(PASSPHRASE_SALT_LENGTH);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+
+    let wrapping_key = passphrase_key(passphrase, salt)?;
+    let cipher = XChaCha20Poly1305::new(wrapping_key.as_bytes().into());
+    let payload = Payload {
+        msg: ciphertext,
+        aad: context.as_bytes(),
+    };
+    let plaintext = Zeroizing::new(
+        cipher
+            .decrypt(nonce.into(), payload)
+            .map_err(|_| "Wrong passphrase".to_string())?,
+    );
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(&plaintext);
+    Ok(SharedKey::from_bytes(bytes))
+}
+
+fn seal(version: u8, plaintext: &str, key: &SharedKey, ad: &[u8]) -> Result<String, String> {
+    let cipher = XChaCha20Poly1305::new(key.as_bytes().into());
+
+    let mut nonce_bytes = [0u8; NONCE_LENGTH];
+    rand::rngs::OsRng.fill_bytes(&mut nonce_bytes);
+
+    let payload = Payload {
+        msg: plaintext.as_bytes(),
+        aad: ad,
+    };
+    let ciphertext = cipher
+        .encrypt((&nonce_bytes).into(), payload)
+        .map_err(|e| format!("Encryption failed: {}", e))?;
+
+    let mut combined = Vec::with_capacity(1 + NONCE_LENGTH + ciphertext.len());
+    combined.push(version);
+    combined.extend_from_slice(&nonce_bytes);
+    combined.extend_from_slice(&ciphertext);
+
+    Ok(STANDARD.encode(&combined))
+}
+
+fn open(version: u8, encoded: &str, key: &SharedKey, ad: &[u8]) -> Result<Option<String>, String> {
+    let combined = STANDARD
+        .decode(encoded)
+        .map_err(|e| format!("Base64 decode failed: {}", e))?;
+
+    if combined.len() <= 1 + NONCE_LENGTH || combined[0] != version {
+        return Ok(None);
+    }
+
+    let cipher = XChaCha20Poly1305::new(key.as_bytes().into());
+    let nonce_bytes: &[u8; NONCE_LENGTH] = combined[1..1 + NONCE_LENGTH]
+        .try_into()
+        .map_err(|_| "Invalid nonce")?;
+    let payload = Payload {
+        msg: &combined[1 + NONCE_LENGTH..],
+        aad: ad,
+    };
+    match cipher.decrypt(nonce_bytes.into(), payload) {
+        Ok(plaintext) => String::from_utf8(plaintext)
+            .map(Some)
+            .map_err(|e| format!("Invalid UTF-8: {}", e)),
+        Err(_) => Ok(None),
+    }
+}
+
+pub fn encrypt_bound(plaintext: &str, key: &SharedKey, ad: &[u8]) -> Result<String, String> {
+    seal(BOUND_VERSION, plaintext, key, ad)
+}
+
+pub fn decrypt_bound(encoded: &str, key: &SharedKey, ad: &[u8]) -> Result<Decrypted, String> {
+    let plaintext = open(BOUND_VERSION, encoded, key, ad)?.ok_or_else(|| {
+        "Decryption failed — wrong key, tampered data or not sent by this peer".to_string()
+    })?;
+    Ok(Decrypted {
+        plaintext,
+        bound: true,
+        fallback: false,
+    })
+}
+
+#[derive(Clone)]
+pub struct PayloadKey {
+    shared: SharedKey,
+    directed: Option<SharedKey>,
+    unbound: bool,
+    fallback: Option<Box<PayloadKey>>,
+}
+
+impl PayloadKey {
+    pub fn shared(shared_key: &SharedKey) -> Self {
+        Self {
+            shared: shared_key.clone(),
+            directed: None,
+            unbound: false,
+            fallback: None,
+        }
+    }
+
+    /// Seals v0 (unbound secretbox) payloads for clients that predate `_v`,
+    /// which can't open anything newer, and opens them in return.
+    pub fn legacy(shared_key: &SharedKey) -> Self {
+        Self {
+            unbound: true,
+            ..Self::shared(shared_key)
+        }
+    }
+
+    pub fn directed(shared_key: &SharedKey, sender_z32: &str, receiver_z32: &str) -> Self {
+        Self {
+            shared: shared_key.clone(),
+            directed: Some(direction_key(shared_key, sender_z32, receiver_z32)),
+            unbound: false,
+            fallback: None,
+        }
+    }
+
+    /// Also opens payloads sealed with `fallback` (tried after any earlier
+    /// fallbacks), e.g. the old key while a key rotation is in flight.
+    /// Encryption always uses `self`.
+    pub fn with_fallback(mut self, fallback: PayloadKey) -> Self {
+        let fallback = match self.fallback.take() {
+            Some(existing) => existing.with_fallback(fallback),
+            None => fallback,
+        };
+        self.fallback = Some(Box::new(fallback));
+        self
+    }
+
+    pub fn shared_key(&self) -> &SharedKey {
+        &self.shared
+    }
+
+    pub fn encrypt(&self, plaintext: &str, ad: &[u8]) -> Result<String, String> {
+        match &self.directed {
+            Some(key) => seal(DIRECTED_VERSION, plaintext, key, ad),
+            None if self.unbound => encrypt(plaintext, &self.shared),
+            None => encrypt_bound(plaintext, &self.shared, ad),
+        }
+    }
+
+    /// Opens v2 (direction key) payloads, falling back to v1 payloads sealed
+    /// with the static shared key.
+    pub fn decrypt(&self, encoded: &str, ad: &[u8]) -> Result<Decrypted, String> {
+        self.decrypt_with(encoded, ad, false)
+    }
+
+    /// Like [`Self::decrypt`], but also accepts the unbound secretbox payloads
+    /// of pre-v1 clients, with `bound: false`. A replayed or reflected payload
+    /// opens this way too, so only use it for peers that predate `_v`.
+    pub fn decrypt_allowing_unbound(&self, encoded: &str, ad: &[u8]) -> Result<Decrypted, String> {
+        self.decrypt_with(encoded, ad, true)
+    }
+
+    fn decrypt_with(
+        &self,
+        encoded: &str,
+        ad: &[u8],
+        allow_unbound: bool,
+    ) -> Result<Decrypted, String> {
+        let result = self.decrypt_own(encoded, ad, allow_unbound || self.unbound);
+        match (&self.fallback, result) {
+            (Some(fallback), Err(_)) => {
+                fallback
+                    .decrypt_with(encoded, ad, allow_unbound)
+                    .map(|decrypted| Decrypted {
+                        fallback: true,
+                        ..decrypted
+                    })
+            }
+            (_, result) => result,
+        }
+    }
+
+    fn decrypt_own(
+        &self,
+        encoded: &str,
+        ad: &[u8],
+        allow_unbound: bool,
+    ) -> Result<Decrypted, String> {
+        if let Some(key) = &self.directed {
+            if let Some(plaintext) = open(DIRECTED_VERSION, encoded, key, ad)? {
+                return Ok(Decrypted {
+                    plaintext,
+                    bound: true,
+                    fallback: false,
+                });
+            }
+        }
+        let bound = decrypt_bound(encoded, &self.shared, ad);
+        if bound.is_ok() || !allow_unbound {
+            return bound;
+        }
+        let plaintext = decrypt(encoded, &self.shared).map_err(|_| {
+            "Decryption failed — wrong key, tampered data or not sent by this peer".to_string()
+        })?;
+        Ok(Decrypted {
+            plaintext,
+            bound: false,
+            fallback: false,
+        })
+    }
+}
+
+/// Returns the (send, receive) payload keys for a 1:1 conversation.
+pub fn direction_keys(
+    shared_key: &SharedKey,
+    my_z32: &str,
+    peer_z32: &str,
+) -> (PayloadKey, PayloadKey) {
+    (
+        PayloadKey::directed(shared_key, my_z32, peer_z32),
+        PayloadKey::directed(shared_key, peer_z32, my_z32),
+    )
+}
+
 pub fn to_base64_url(bytes: &[u8]) -> String {
     URL_SAFE_NO_PAD.encode(bytes)
 }
@@ -70,3 +520,111 @@ pub fn from_base64_url(s: &str) -> Result<Vec<u8>, String> {
         .decode(s)
         .map_err(|e| format!("Base64url decode failed: {}", e))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn hmac_matches_rfc4231() {
+        let cases: [(Vec<u8>, &[u8], &str); 4] = [
+            (
+                vec![0x0b; 20],
+                b"Hi There",
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            ),
+            (
+                b"Jefe".to_vec(),
+                b"what do ya want for nothing?",
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                vec![0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+            (
+                vec![0xaa; 131],
+                b"This is a test using a larger than block-size key and a larger than \
+                  block-size data. The key needs to be hashed before being used by the \
+                  HMAC algorithm.",
+                "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+            ),
+        ];
+        for (key, data, expected) in cases {
+            assert_eq!(hmac_sha256(&key, &[data]).to_vec(), hex(expected));
+        }
+        // Split input hashes the same as the concatenation.
+        assert_eq!(
+            hmac_sha256(b"Jefe", &[b"what do ya ", b"want for nothing?"]),
+            hmac_sha256(b"Jefe", &[b"what do ya want for nothing?"])
+        );
+    }
+
+    #[test]
+    fn hkdf_matches_rfc5869() {
+        let ikm = [0x0b; 22];
+        // Test case 1, first 32 bytes of OKM.
+        let okm = hkdf_sha256(
+            &hex("000102030405060708090a0b0c"),
+            &ikm,
+            &hex("f0f1f2f3f4f5f6f7f8f9"),
+        );
+        assert_eq!(
+            okm.to_vec(),
+            hex("3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf")
+        );
+        // Test case 3: empty salt and info.
+        let okm = hkdf_sha256(&[], &ikm, &[]);
+        assert_eq!(
+            okm.to_vec(),
+            hex("8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d")
+        );
+    }
+
+    #[test]
+    fn wrapped_key_needs_passphrase_and_context() {
+        let key = generate_key();
+        let wrapped = wrap_key(&key, "correct horse", "inviter").unwrap();
+        assert_eq!(from_base64_url(&wrapped).unwrap().len(), WRAPPED_KEY_LENGTH);
+
+        let unwrapped = unwrap_key(&wrapped, "correct horse", "inviter").unwrap();
+        assert_eq!(unwrapped.as_bytes(), key.as_bytes());
+        assert_eq!(
+            unwrap_key(&wrapped, "battery staple", "inviter").unwrap_err(),
+            "Wrong passphrase"
+        );
+        assert_eq!(
+            unwrap_key(&wrapped, "correct horse", "someone-else").unwrap_err(),
+            "Wrong passphrase"
+        );
+    }
+
+    #[test]
+    fn direction_keys_mirror_each_other() {
+        let shared = generate_key();
+        let (a_send, a_recv) = direction_keys(&shared, "alice", "bob");
+        let (b_send, b_recv) = direction_keys(&shared, "bob", "alice");
+
+        let to_bob = a_send.encrypt("hi bob", b"ad").unwrap();
+        assert_eq!(b_recv.decrypt(&to_bob, b"ad").unwrap().plaintext, "hi bob");
+        let to_alice = b_send.encrypt("hi alice", b"ad").unwrap();
+        assert_eq!(
+            a_recv.decrypt(&to_alice, b"ad").unwrap().plaintext,
+            "hi alice"
+        );
+
+        assert_ne!(
+            direction_key(&shared, "alice", "bob").as_bytes(),
+            direction_key(&shared, "bob", "alice").as_bytes()
+        );
+        assert!(a_recv.decrypt(&to_bob, b"ad").is_err());
+    }
+}
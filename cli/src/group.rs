@@ -0,0 +1,524 @@
+use std::collections::BTreeMap;
+
+use pkarr::{Client, Keypair, PublicKey, SignedPacket};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+
+use crate::crypto::{self, PayloadKey, SharedKey, INVITE_TOKEN_LENGTH};
+use crate::pkarr::{
+    add_txt, create_keypair, decode_packet, keypair_from_seed, open_record, outbox_from, pad,
+    pubkey_from_seed, seal_record, trim_to_fit, txt_value, MAX_MSGS_PAYLOAD_B64, NICK_PAD_LENGTH,
+    PACKET_BUDGET, RECORD_OVERHEAD,
+};
+use crate::types::{
+    CompactMessage, Group, GroupInviteOutput, GroupMember, GroupMessage, GroupRecvOutput, Padding,
+    PeerCapabilities, ResolvedBatch,
+};
+use crate::GhostClient;
+
+const GROUP_INVITE_PREFIX: &str = "ghost-group://";
+const MAX_ANNOUNCED_INVITES: usize = 2;
+const MEMBER_TAG_LENGTH: usize = 6;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RosterRecord {
+    #[serde(default)]
+    a: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    l: bool,
+}
+
+pub struct MemberPacket {
+    pub pubkey: String,
+    pub batch: ResolvedBatch,
+    pub acks: BTreeMap<String, u64>,
+    pub nick: Option<String>,
+    roster: RosterRecord,
+}
+
+fn member_tag(pubkey: &str) -> &str {
+    &pubkey[..MEMBER_TAG_LENGTH.min(pubkey.len())]
+}
+
+/// Invite carrying the group key, the roster and a one-time join token. The
+/// joiner makes its own keypair and announces it at the token's rendezvous key.
+pub fn group_invite_url(token: &str, key: &SharedKey, members: &[String]) -> String {
+    format!(
+        "{}{}/{}/{}",
+        GROUP_INVITE_PREFIX,
+        token,
+        key.to_base64_url(),
+        members.join(",")
+    )
+}
+
+/// Adds a join token for a new member and returns the invite to hand out.
+pub fn new_group_invite(group: &mut Group) -> GroupInviteOutput {
+    let token = crypto::generate_invite_token();
+    group.pending_joins.push(token.clone());
+    GroupInviteOutput {
+        invite_url: group_invite_url(&token, &group.key, &group.members),
+        token,
+    }
+}
+
+/// Parses a group invite into the joiner's group, under a fresh keypair, and
+/// the join token to announce it with.
+pub fn parse_group_invite(name: &str, invite_url: &str) -> Result<(Group, String), String> {
+    let rest = invite_url
+        .strip_prefix(GROUP_INVITE_PREFIX)
+        .ok_or("Invalid group invite: must start with ghost-group://")?;
+
+    let parts: Vec<&str> = rest.split('/').collect();
+    if parts.len() != 3 {
+        return Err("Invalid group invite: expected token/key/members".to_string());
+    }
+    let token = parts[0];
+    if crypto::from_base64_url(token)?.len() != INVITE_TOKEN_LENGTH {
+        return Err("Invalid group invite: bad join token".to_string());
+    }
+
+    let (_, seed, me) = create_keypair();
+    let mut members: Vec<String> = parts[2]
+        .split(',')
+        .filter(|m| !m.is_empty())
+        .map(str::to_string)
+        .collect();
+    if !members.contains(&me) {
+        members.push(me);
+    }
+
+    let group = Group {
+        name: name.to_string(),
+        seed,
+        key: SharedKey::from_base64_url(parts[1])?,
+        nick: None,
+        members,
+        invited: Vec::new(),
+        pending_joins: Vec::new(),
+    };
+    Ok((group, token.to_string()))
+}
+
+fn decode_member(pubkey: String, signed_packet: &SignedPacket, key: &PayloadKey) -> MemberPacket {
+    let batch = decode_packet(signed_packet, key);
+
+    let acks = txt_value(signed_packet, "_gack")
+        .and_then(|v| open_record("_gack", &v, key, &pubkey).ok())
+        .and_then(|d| serde_json::from_str(&d.plaintext).ok())
+        .unwrap_or_default();
+
+    let roster = txt_value(signed_packet, "_grp")
+        .and_then(|v| open_record("_grp", &v, key, &pubkey).ok())
+        .and_then(|d| serde_json::from_str(&d.plaintext).ok())
+        .unwrap_or_default();
+
+    let nick = txt_value(signed_packet, "_nick")
+        .and_then(|v| open_record("_nick", &v, key, &pubkey).ok())
+        .map(|d| d.plaintext.trim_end().to_string());
+
+    MemberPacket {
+        pubkey,
+        batch,
+        acks,
+        nick,
+        roster,
+    }
+}
+
+pub async fn resolve_group(client: &Client, group: &Group) -> Result<Vec<MemberPacket>, String> {
+    let key = PayloadKey::shared(&group.key);
+    let mut tasks = JoinSet::new();
+
+    for member in &group.members {
+        let public_key: PublicKey = member
+            .as_str()
+            .try_into()
+            .map_err(|e| format!("Invalid member public key {}: {}", member, e))?;
+        let client = client.clone();
+        let pubkey = member.clone();
+        tasks.spawn(async move { (pubkey, client.resolve_most_recent(&public_key).await) });
+    }
+
+    let mut packets = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        let (pubkey, resolved) = joined.map_err(|e| format!("Resolve task failed: {}", e))?;
+        if let Some(signed_packet) = resolved {
+            packets.push(decode_member(pubkey, &signed_packet, &key));
+        }
+    }
+
+    Ok(packets)
+}
+
+pub fn merge_roster(group: &mut Group, packets: &[MemberPacket]) -> bool {
+    let mut changed = false;
+
+    for packet in packets {
+        if !group.members.contains(&packet.pubkey) {
+            continue;
+        }
+        for added in &packet.roster.a {
+            if !group.members.contains(added) && added.parse::<PublicKey>().is_ok() {
+                group.members.push(added.clone());
+                changed = true;
+            }
+        }
+    }
+
+    for packet in packets {
+        if packet.roster.l {
+            group.members.retain(|m| m != &packet.pubkey);
+            changed = true;
+        }
+    }
+
+    changed
+}
+
+pub fn merge_messages(packets: &[MemberPacket]) -> Vec<GroupMessage> {
+    let mut merged: Vec<GroupMessage> = packets
+        .iter()
+        .flat_map(|p| {
+            p.batch.messages.iter().map(|m| GroupMessage {
+                from: p.pubkey.clone(),
+                text: m.text.clone(),
+                timestamp: m.timestamp,
+                nick: m.nick.clone(),
+                id: m.id.clone(),
+                seq: m.seq,
+            })
+        })
+        .collect();
+    merged.sort_by(|a, b| (a.timestamp, &a.from, a.seq).cmp(&(b.timestamp, &b.from, b.seq)));
+    merged
+}
+
+/// Highest seq of ours that every other member has acked. Members without a
+/// packet (not joined yet, or expired) have acked nothing.
+fn acked_by_all(members: &[String], me: &str, packets: &[MemberPacket]) -> Option<u64> {
+    members
+        .iter()
+        .filter(|m| *m != me)
+        .map(|m| {
+            packets
+                .iter()
+                .find(|p| &p.pubkey == m)
+                .and_then(|p| p.acks.get(member_tag(me)).copied())
+                .unwrap_or(0)
+        })
+        .min()
+}
+
+pub fn group_members(group: &Group, packets: &[MemberPacket]) -> Vec<GroupMember> {
+    let me = pubkey_from_seed(&group.seed);
+    group
+        .members
+        .iter()
+        .map(|pubkey| {
+            let packet = packets.iter().find(|p| &p.pubkey == pubkey);
+            GroupMember {
+                pubkey: pubkey.clone(),
+                nick: packet.and_then(|p| p.nick.clone()),
+                is_me: pubkey == &me,
+                packet_ts: packet.map(|p| p.batch.packet_timestamp).unwrap_or(0),
+                acked_seq: packet.and_then(|p| p.acks.get(member_tag(&me)).copied()),
+            }
+        })
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn publish_group(
+    client: &Client,
+    keypair: &Keypair,
+    group: &Group,
+    outbox: &[CompactMessage],
+    last_seq: u64,
+    acks: &BTreeMap<String, u64>,
+    left: bool,
+    padding: Padding,
+) -> Result<usize, String> {
+    let key = PayloadKey::shared(&group.key);
+    let sender_z32 = keypair.to_z32();
+    let mut records: Vec<(&str, String)> = Vec::new();
+
+    if last_seq > 0 {
+        records.push(("_seq", last_seq.to_string()));
+    }
+    if !acks.is_empty() {
+        let json = serde_json::to_string(acks).map_err(|e| format!("JSON serialize: {}", e))?;
+        records.push(("_gack", seal_record("_gack", &json, &key, &sender_z32)?));
+    }
+
+    let announced = group.invited.len().saturating_sub(MAX_ANNOUNCED_INVITES);
+    let roster = RosterRecord {
+        a: group.invited[announced..].to_vec(),
+        l: left,
+    };
+    if !roster.a.is_empty() || roster.l {
+        let json = serde_json::to_string(&roster).map_err(|e| format!("JSON serialize: {}", e))?;
+        records.push(("_grp", seal_record("_grp", &json, &key, &sender_z32)?));
+    }
+    if let Some(nick) = &group.nick {
+        let padded = pad(nick, padding, NICK_PAD_LENGTH);
+        records.push(("_nick", seal_record("_nick", &padded, &key, &sender_z32)?));
+    }
+
+    let mut sorted = outbox.to_vec();
+    sorted.sort_by_key(|m| (m.s, m.t));
+    let latest_ts = sorted.last().map(|m| m.t).unwrap_or(0);
+    records.push(("_ts", latest_ts.to_string()));
+    records.push(("_v", PeerCapabilities::ours().to_record()));
+
+    let used: usize = records
+        .iter()
+        .map(|(name, value)| name.len() + value.len() + RECORD_OVERHEAD)
+        .sum();
+    let max_payload = PACKET_BUDGET
+        .saturating_sub(used + "_msgs".len() + RECORD_OVERHEAD)
+        .min(MAX_MSGS_PAYLOAD_B64);
+    let (payload, kept) = trim_to_fit(&sorted, &key, &sender_z32, max_payload, padding)?;
+
+    let mut builder = add_txt(SignedPacket::builder(), "_msgs", &payload)?;
+    for (name, value) in &records {
+        builder = add_txt(builder, name, value)?;
+    }
+
+    let signed_packet = builder
+        .sign(keypair)
+        .map_err(|e| format!("Sign error: {}", e))?;
+
+    client
+        .publish(&signed_packet, None)
+        .await
+        .map_err(|e| format!("Publish error: {}", e))?;
+
+    Ok(kept)
+}
+
+impl GhostClient {
+    /// Announces our own pubkey at the invite's rendezvous key, then publishes
+    /// our packet so members can read us once the inviter adds us.
+    pub async fn group_join(&self, group: &mut Group, token: &str) -> Result<(), String> {
+        self.publish_join(&group.key, token, &pubkey_from_seed(&group.seed))
+            .await?;
+        self.group_announce(group).await
+    }
+
+    /// Adds members who joined through our pending invites. Each token admits
+    /// one member, so it's dropped once used.
+    async fn accept_group_joins(&self, group: &mut Group) -> Result<(), String> {
+        let mut pending = Vec::new();
+        for token in std::mem::take(&mut group.pending_joins) {
+            match self.resolve_join(&group.key, &token).await? {
+                Some(join) => {
+                    if !group.members.contains(&join.p) {
+                        group.members.push(join.p.clone());
+                        group.invited.push(join.p);
+                    }
+                }
+                None => pending.push(token),
+            }
+        }
+        group.pending_joins = pending;
+        Ok(())
+    }
+
+    pub async fn group_recv(
+        &self,
+        group: &mut Group,
+        ack: bool,
+    ) -> Result<GroupRecvOutput, String> {
+        self.accept_group_joins(group).await?;
+        let packets = resolve_group(&self.client, group).await?;
+        merge_roster(group, &packets);
+
+        let me = pubkey_from_seed(&group.seed);
+        let has_peer_messages = packets
+            .iter()
+            .any(|p| p.pubkey != me && !p.batch.messages.is_empty());
+        if ack && has_peer_messages {
+            self.group_publish(group, &packets, None, false).await?;
+        }
+
+        let others: Vec<MemberPacket> = packets.into_iter().filter(|p| p.pubkey != me).collect();
+        Ok(GroupRecvOutput {
+            messages: merge_messages(&others),
+            members: group_members(group, &others),
+        })
+    }
+
+    pub async fn group_send(
+        &self,
+        group: &mut Group,
+        message: &str,
+    ) -> Result<(usize, CompactMessage), String> {
+        self.accept_group_joins(group).await?;
+        let packets = resolve_group(&self.client, group).await?;
+        merge_roster(group, &packets);
+        let (kept, sent) = self
+            .group_publish(group, &packets, Some(message), false)
+            .await?;
+        Ok((kept, sent.ok_or("Message was not queued")?))
+    }
+
+    pub async fn group_announce(&self, group: &mut Group) -> Result<(), String> {
+        self.accept_group_joins(group).await?;
+        let packets = resolve_group(&self.client, group).await?;
+        merge_roster(group, &packets);
+        self.group_publish(group, &packets, None, false).await?;
+        Ok(())
+    }
+
+    pub async fn group_leave(&self, group: &mut Group) -> Result<(), String> {
+        let packets = resolve_group(&self.client, group).await?;
+        self.group_publish(group, &packets, None, true).await?;
+        Ok(())
+    }
+
+    pub async fn group_members(&self, group: &mut Group) -> Result<Vec<GroupMember>, String> {
+        self.accept_group_joins(group).await?;
+        let packets = resolve_group(&self.client, group).await?;
+        merge_roster(group, &packets);
+        Ok(group_members(group, &packets))
+    }
+
+    async fn group_publish(
+        &self,
+        group: &Group,
+        packets: &[MemberPacket],
+        message: Option<&str>,
+        left: bool,
+    ) -> Result<(usize, Option<CompactMessage>), String> {
+        let keypair = keypair_from_seed(&group.seed);
+        let me = keypair.to_z32();
+        let own = packets.iter().find(|p| p.pubkey == me);
+        let others: Vec<&MemberPacket> = packets
+            .iter()
+            .filter(|p| p.pubkey != me && group.members.contains(&p.pubkey))
+            .collect();
+
+        let acks: BTreeMap<String, u64> = others
+            .iter()
+            .filter_map(|p| {
+                let seq = p.batch.messages.iter().filter_map(|m| m.seq).max()?;
+                Some((member_tag(&p.pubkey).to_string(), seq))
+            })
+            .collect();
+
+        let mut outbox = own.map(|p| outbox_from(&p.batch)).unwrap_or_default();
+        if let Some(acked) = acked_by_all(&group.members, &me, packets) {
+            outbox.retain(|m| m.s.is_none_or(|s| s > acked));
+        }
+
+        // Members' acks outlive our own packet; never reuse a seq they cover.
+        let mut last_seq = others
+            .iter()
+            .filter_map(|p| p.acks.get(member_tag(&me)).copied())
+            .fold(own.map_or(0, |p| p.batch.last_seq), u64::max);
+        let sent = match message {
+            Some(text) => {
+                let timestamp = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_err(|e| format!("Time error: {}", e))?
+                    .as_millis() as i64;
+                last_seq += 1;
+                let msg = CompactMessage {
+                    t: timestamp,
+                    m: text.to_string(),
+                    i: Some(crypto::generate_message_id()),
+                    s: Some(last_seq),
+                    r: None,
+                    a: None,
+                    k: None,
+                };
+                outbox.push(msg.clone());
+                Some(msg)
+            }
+            None => None,
+        };
+
+        let kept = publish_group(
+            &self.client,
+            &keypair,
+            group,
+            &outbox,
+            last_seq,
+            &acks,
+            left,
+            self.padding,
+        )
+        .await?;
+
+        Ok((kept, sent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(pubkey: &str, acked: &[(&str, u64)]) -> MemberPacket {
+        MemberPacket {
+            pubkey: pubkey.to_string(),
+            batch: ResolvedBatch::default(),
+            acks: acked
+                .iter()
+                .map(|(tag, seq)| (member_tag(tag).to_string(), *seq))
+                .collect(),
+            nick: None,
+            roster: RosterRecord::default(),
+        }
+    }
+
+    #[test]
+    fn invites_carry_a_token_not_a_member_seed() {
+        let (_, seed, me) = create_keypair();
+        let mut group = Group {
+            name: "team".to_string(),
+            seed,
+            key: crypto::generate_key(),
+            nick: None,
+            members: vec![me.clone()],
+            invited: Vec::new(),
+            pending_joins: Vec::new(),
+        };
+        let invite = new_group_invite(&mut group);
+        assert_eq!(group.pending_joins, std::slice::from_ref(&invite.token));
+
+        let (first, token) = parse_group_invite("team", &invite.invite_url).unwrap();
+        let (second, _) = parse_group_invite("team", &invite.invite_url).unwrap();
+        assert_eq!(token, invite.token);
+        assert_eq!(first.key.as_bytes(), group.key.as_bytes());
+        let joiner = pubkey_from_seed(&first.seed);
+        assert_ne!(joiner, pubkey_from_seed(&second.seed));
+        assert_eq!(first.members, [me, joiner]);
+
+        let seed_url = group_invite_url(&group.seed.to_base64_url(), &group.key, &[]);
+        assert!(parse_group_invite("team", &seed_url).is_err());
+    }
+
+    #[test]
+    fn members_without_packets_hold_back_pruning() {
+        let me = "mememememe";
+        let members: Vec<String> = [me, "alicealice", "bobbobbob0"]
+            .iter()
+            .map(|m| m.to_string())
+            .collect();
+
+        let both = [
+            packet("alicealice", &[(me, 4)]),
+            packet("bobbobbob0", &[(me, 7)]),
+        ];
+        assert_eq!(acked_by_all(&members, me, &both), Some(4));
+
+        let bob_missing = [packet("alicealice", &[(me, 4)])];
+        assert_eq!(acked_by_all(&members, me, &bob_missing), Some(0));
+
+        let no_ack_from_bob = [packet("alicealice", &[(me, 4)]), packet("bobbobbob0", &[])];
+        assert_eq!(acked_by_all(&members, me, &no_ack_from_bob), Some(0));
+
+        assert_eq!(acked_by_all(&[me.to_string()], me, &[]), None);
+    }
+}
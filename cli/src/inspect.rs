@@ -0,0 +1,86 @@
+use pkarr::PublicKey;
+use simple_dns::rdata::RData;
+
+use crate::crypto::{PayloadKey, SecretSeed, SharedKey};
+use crate::pkarr::{decode_packet, packet_timestamp_ms, pubkey_from_seed};
+use crate::types::{InspectOutput, InspectRecord};
+use crate::GhostClient;
+
+/// Largest encoded DNS packet the DHT accepts.
+pub const MAX_PACKET_SIZE: usize = 1000;
+
+/// Records published in the clear, whose values are shown as-is.
+const PLAINTEXT_RECORDS: &[&str] = &["_ts", "_v", "_ack", "_acki", "_aseq", "_seq"];
+
+impl GhostClient {
+    /// Resolves the peer's packet and describes it record by record,
+    /// decrypting what it can when a key is given.
+    pub async fn inspect(
+        &self,
+        seed: Option<&SecretSeed>,
+        peer_pubkey: &str,
+        shared_key: Option<&SharedKey>,
+    ) -> Result<InspectOutput, String> {
+        let public_key: PublicKey = peer_pubkey
+            .try_into()
+            .map_err(|e| format!("Invalid public key: {}", e))?;
+        let signed_packet = self
+            .client
+            .resolve_most_recent(&public_key)
+            .await
+            .ok_or_else(|| format!("No packet found for peer: {}", peer_pubkey))?;
+
+        let batch = shared_key.map(|shared_key| {
+            let key = match seed {
+                Some(seed) => {
+                    PayloadKey::directed(shared_key, peer_pubkey, &pubkey_from_seed(seed))
+                }
+                None => PayloadKey::shared(shared_key),
+            };
+            decode_packet(&signed_packet, &key)
+        });
+
+        let records = signed_packet
+            .all_resource_records()
+            .map(|record| {
+                let name_str = record.name.to_string();
+                let label = name_str
+                    .trim_end_matches('.')
+                    .split('.')
+                    .next()
+                    .unwrap_or(&name_str)
+                    .to_string();
+                let value = match &record.rdata {
+                    RData::TXT(txt) => String::try_from(txt.clone()).unwrap_or_default(),
+                    _ => String::new(),
+                };
+                InspectRecord {
+                    size: value.len(),
+                    ttl: record.ttl,
+                    value: PLAINTEXT_RECORDS.contains(&label.as_str()).then_some(value),
+                    status: batch
+                        .as_ref()
+                        .and_then(|b| b.record_status.get(&label).copied()),
+                    name: label,
+                }
+            })
+            .collect();
+
+        let packet_ts = packet_timestamp_ms(&signed_packet);
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| format!("Time error: {}", e))?
+            .as_millis() as i64;
+
+        Ok(InspectOutput {
+            peer: peer_pubkey.to_string(),
+            records,
+            encoded_size: signed_packet.encoded_packet().len(),
+            max_size: MAX_PACKET_SIZE,
+            packet_ts,
+            age_ms: now - packet_ts,
+            message_count: batch.as_ref().map(|b| b.message_count),
+            sender_authenticated: batch.as_ref().map(|b| b.sender_authenticated),
+        })
+    }
+}
@@ -0,0 +1,340 @@
+use pkarr::{PublicKey, SignedPacket};
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{
+    generate_invite_token, rendezvous_seed, wrap_key, PayloadKey, SecretSeed, SharedKey,
+};
+use crate::invite_code::is_invite_code;
+use crate::pkarr::{
+    add_txt, keypair_from_seed, open_record, pubkey_from_seed, seal_record, txt_value,
+};
+use crate::types::{InviteOutput, ParsedInvite, PendingInvite};
+use crate::GhostClient;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct JoinRecord {
+    pub p: String,
+    pub t: i64,
+}
+
+pub(crate) fn now_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+/// Parses a TTL such as `90s`, `15m`, `1h` or `7d` (bare numbers are
+/// seconds) into milliseconds.
+pub fn parse_ttl(ttl: &str) -> Result<i64, String> {
+    let ttl = ttl.trim();
+    let (number, unit_ms) = match ttl.char_indices().last() {
+        Some((i, 's')) => (&ttl[..i], 1_000),
+        Some((i, 'm')) => (&ttl[..i], 60_000),
+        Some((i, 'h')) => (&ttl[..i], 3_600_000),
+        Some((i, 'd')) => (&ttl[..i], 86_400_000),
+        _ => (ttl, 1_000),
+    };
+    match number.parse::<i64>() {
+        Ok(n) if n > 0 => Ok(n * unit_ms),
+        _ => Err(format!("Invalid TTL: {}", ttl)),
+    }
+}
+
+/// The parts of a `ghost://<pubkey>?<query>#<key>` invite; `key` is the
+/// wrapped blob when `password_protected`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct InviteUrl {
+    pub pubkey: String,
+    pub key: String,
+    pub token: Option<String>,
+    pub expires_at: Option<i64>,
+    pub single_use: bool,
+    pub password_protected: bool,
+}
+
+impl InviteUrl {
+    /// Accepts both `ghost://` URLs and compact `ghost1...` invite codes.
+    pub fn parse(invite: &str) -> Result<Self, String> {
+        if is_invite_code(invite) {
+            return Self::from_code(invite);
+        }
+        let url = invite
+            .strip_prefix("ghost://")
+            .ok_or("Invalid invite URL: must start with ghost://")?;
+
+        let parts: Vec<&str> = url.split('#').collect();
+        if parts.len() != 2 {
+            return Err("Invalid invite URL: missing # separator".to_string());
+        }
+
+        let (pubkey, query) = match parts[0].split_once('?') {
+            Some((pubkey, query)) => (pubkey, query),
+            None => (parts[0], ""),
+        };
+        let mut parsed = InviteUrl {
+            pubkey: pubkey.to_string(),
+            key: parts[1].to_string(),
+            token: None,
+            expires_at: None,
+            single_use: false,
+            password_protected: false,
+        };
+        for param in query.split('&').filter(|p| !p.is_empty()) {
+            match param.split_once('=') {
+                Some(("t", value)) => parsed.token = Some(value.to_string()),
+                Some(("exp", value)) => {
+                    parsed.expires_at = Some(
+                        value
+                            .parse::<i64>()
+                            .map_err(|_| "Invalid invite URL: bad expiry")?,
+                    )
+                }
+                Some(("once", "1")) => parsed.single_use = true,
+                Some(("pw", "1")) => parsed.password_protected = true,
+                _ => {}
+            }
+        }
+        if parsed.token.is_none() && (parsed.expires_at.is_some() || parsed.single_use) {
+            return Err("Invalid invite URL: missing invite token".to_string());
+        }
+        Ok(parsed)
+    }
+
+    pub fn to_url(&self) -> String {
+        let mut params = Vec::new();
+        if let Some(token) = &self.token {
+            params.push(format!("t={}", token));
+        }
+        if let Some(exp) = self.expires_at {
+            params.push(format!("exp={}", exp));
+        }
+        if self.single_use {
+            params.push("once=1".to_string());
+        }
+        if self.password_protected {
+            params.push("pw=1".to_string());
+        }
+        let query = if params.is_empty() {
+            String::new()
+        } else {
+            format!("?{}", params.join("&"))
+        };
+        format!("ghost://{}{}#{}", self.pubkey, query, self.key)
+    }
+
+    pub fn to_output(&self) -> InviteOutput {
+        InviteOutput {
+            invite_url: self.to_url(),
+            invite_code: self
+                .to_code()
+                .expect("invite built from a valid pubkey and key"),
+            pubkey: self.pubkey.clone(),
+            token: self.token.clone(),
+            expires_at: self.expires_at,
+            single_use: self.single_use,
+            password_protected: self.password_protected,
+        }
+    }
+}
+
+/// Invite that embeds a one-time token and, given a `ttl_ms`, an expiry. The
+/// inviter keeps the returned [`PendingInvite`] to accept the join later.
+pub fn generate_limited_invite(
+    seed: &SecretSeed,
+    shared_key: &SharedKey,
+    ttl_ms: Option<i64>,
+    single_use: bool,
+) -> (InviteOutput, PendingInvite) {
+    let invite = InviteUrl {
+        pubkey: pubkey_from_seed(seed),
+        key: shared_key.to_base64_url(),
+        token: Some(generate_invite_token()),
+        expires_at: ttl_ms.map(|ttl| now_ms() + ttl),
+        single_use,
+        password_protected: false,
+    };
+
+    let pending = PendingInvite {
+        token: invite.token.clone().unwrap_or_default(),
+        seed: seed.clone(),
+        key: shared_key.clone(),
+        expires_at: invite.expires_at,
+        single_use,
+        joined: Vec::new(),
+    };
+    (invite.to_output(), pending)
+}
+
+/// Replaces the shared key in `invite` with one wrapped under `passphrase`,
+/// which the invitee needs to join.
+pub fn protect_invite(
+    invite: &mut InviteOutput,
+    shared_key: &SharedKey,
+    passphrase: &str,
+) -> Result<(), String> {
+    let mut url = InviteUrl::parse(&invite.invite_url)?;
+    url.key = wrap_key(shared_key, passphrase, &url.pubkey)?;
+    url.password_protected = true;
+    *invite = url.to_output();
+    Ok(())
+}
+
+/// Admits `joiner` unless the invite has expired by our clock at `now` (the
+/// joiner's own `_join` timestamp can be backdated) or is single-use and
+/// already taken. Joiners admitted before stay admitted.
+fn admit_join(invite: &mut PendingInvite, joiner: String, now: i64) -> Result<String, String> {
+    if invite.joined.contains(&joiner) {
+        return Ok(joiner);
+    }
+    if invite.expires_at.is_some_and(|exp| now > exp) {
+        return Err("Refused join: invite has expired".to_string());
+    }
+    if invite.single_use && !invite.joined.is_empty() {
+        return Err("Refused join: single-use invite was already used".to_string());
+    }
+
+    invite.joined.push(joiner.clone());
+    Ok(joiner)
+}
+
+pub fn invite_needs_passphrase(invite: &str) -> bool {
+    InviteUrl::parse(invite).is_ok_and(|url| url.password_protected)
+}
+
+impl GhostClient {
+    /// Announces `invite.my_pubkey` to the inviter at the invite's rendezvous
+    /// key. Fails for invites without a token or past their expiry.
+    pub async fn join_invite(&self, invite: &ParsedInvite) -> Result<(), String> {
+        let token = invite
+            .token
+            .as_deref()
+            .ok_or("Invite has no token; share your pubkey with the inviter instead")?;
+        if invite.expired {
+            return Err("Invite has expired".to_string());
+        }
+
+        self.publish_join(&invite.shared_key, token, &invite.my_pubkey)
+            .await
+    }
+
+    /// Publishes `my_pubkey` as the `_join` at `token`'s rendezvous key.
+    pub(crate) async fn publish_join(
+        &self,
+        shared_key: &SharedKey,
+        token: &str,
+        my_pubkey: &str,
+    ) -> Result<(), String> {
+        let rendezvous = keypair_from_seed(&rendezvous_seed(shared_key, token));
+        let rendezvous_z32 = rendezvous.to_z32();
+        let record = JoinRecord {
+            p: my_pubkey.to_string(),
+            t: now_ms(),
+        };
+        let json = serde_json::to_string(&record).map_err(|e| format!("JSON serialize: {}", e))?;
+        let key = PayloadKey::shared(shared_key);
+
+        let builder = add_txt(SignedPacket::builder(), "_ts", &record.t.to_string())?;
+        let builder = add_txt(
+            builder,
+            "_join",
+            &seal_record("_join", &json, &key, &rendezvous_z32)?,
+        )?;
+        let signed_packet = builder
+            .sign(&rendezvous)
+            .map_err(|e| format!("Sign error: {}", e))?;
+
+        self.client
+            .publish(&signed_packet, None)
+            .await
+            .map_err(|e| format!("Publish error: {}", e))
+    }
+
+    /// Returns the pubkey that joined `invite`; see [`admit_join`] for which
+    /// joins are refused. The rendezvous packet holds a single `_join`, so a
+    /// later joiner, or anyone else holding the link, replaces an earlier one
+    /// that hasn't been accepted yet.
+    pub async fn accept_invite(&self, invite: &mut PendingInvite) -> Result<String, String> {
+        let record = self
+            .resolve_join(&invite.key, &invite.token)
+            .await?
+            .ok_or("No one has joined this invite yet")?;
+        admit_join(invite, record.p, now_ms())
+    }
+
+    /// Reads the `_join` at `token`'s rendezvous key, if anyone has joined.
+    pub(crate) async fn resolve_join(
+        &self,
+        shared_key: &SharedKey,
+        token: &str,
+    ) -> Result<Option<JoinRecord>, String> {
+        let rendezvous_z32 = pubkey_from_seed(&rendezvous_seed(shared_key, token));
+        let public_key: PublicKey = rendezvous_z32
+            .as_str()
+            .try_into()
+            .map_err(|e| format!("Invalid public key: {}", e))?;
+
+        Ok(self
+            .client
+            .resolve_most_recent(&public_key)
+            .await
+            .and_then(|packet| txt_value(&packet, "_join"))
+            .and_then(|value| {
+                open_record(
+                    "_join",
+                    &value,
+                    &PayloadKey::shared(shared_key),
+                    &rendezvous_z32,
+                )
+                .ok()
+            })
+            .and_then(|d| serde_json::from_str::<JoinRecord>(&d.plaintext).ok())
+            .filter(|record| record.p.parse::<PublicKey>().is_ok()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_key;
+    use crate::pkarr::create_keypair;
+
+    fn pending(expires_at: Option<i64>, single_use: bool) -> PendingInvite {
+        let (_, seed, _) = create_keypair();
+        let (_, pending) = generate_limited_invite(&seed, &generate_key(), None, single_use);
+        PendingInvite {
+            expires_at,
+            ..pending
+        }
+    }
+
+    #[test]
+    fn refuses_backdated_joins_after_expiry() {
+        let mut invite = pending(Some(1_000), false);
+        // A `_join` claiming t=500 doesn't matter; our clock says it's late.
+        assert_eq!(
+            admit_join(&mut invite, "late".to_string(), 2_000).unwrap_err(),
+            "Refused join: invite has expired"
+        );
+        assert!(invite.joined.is_empty());
+
+        assert_eq!(
+            admit_join(&mut invite, "early".to_string(), 900).unwrap(),
+            "early"
+        );
+        assert_eq!(
+            admit_join(&mut invite, "early".to_string(), 2_000).unwrap(),
+            "early"
+        );
+    }
+
+    #[test]
+    fn single_use_invites_admit_one_joiner() {
+        let mut invite = pending(None, true);
+        assert!(admit_join(&mut invite, "first".to_string(), 0).is_ok());
+        assert_eq!(
+            admit_join(&mut invite, "second".to_string(), 0).unwrap_err(),
+            "Refused join: single-use invite was already used"
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use bech32::primitives::decode::CheckedHrpstring;
+use bech32::{Bech32m, Hrp};
+use pkarr::PublicKey;
+use qrcode::render::unicode;
+use qrcode::QrCode;
+
+use crate::crypto::{from_base64_url, to_base64_url, INVITE_TOKEN_LENGTH, WRAPPED_KEY_LENGTH};
+use crate::invite::InviteUrl;
+
+// Binary layout: version, flags, pubkey (32), key (32, or the wrapped key when
+// FLAG_PASSWORD), then the invitee seed (32), token and big-endian expiry (8)
+// when their flags are set. Rendered as bech32m, so typos fail the checksum.
+const INVITE_CODE_HRP: &str = "ghost";
+const INVITE_CODE_VERSION: u8 = 1;
+const FLAG_SEED: u8 = 1 << 0;
+const FLAG_TOKEN: u8 = 1 << 1;
+const FLAG_EXPIRY: u8 = 1 << 2;
+const FLAG_SINGLE_USE: u8 = 1 << 3;
+const FLAG_PASSWORD: u8 = 1 << 4;
+
+pub fn is_invite_code(invite: &str) -> bool {
+    invite
+        .get(..INVITE_CODE_HRP.len() + 1)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case("ghost1"))
+}
+
+/// Compact `ghost1...` form of a `ghost://` invite URL.
+pub fn encode_invite_code(invite_url: &str) -> Result<String, String> {
+    InviteUrl::parse(invite_url)?.to_code()
+}
+
+/// Expands a compact invite code back into its `ghost://` URL.
+pub fn decode_invite_code(code: &str) -> Result<String, String> {
+    Ok(InviteUrl::from_code(code)?.to_url())
+}
+
+/// Renders an invite code as a QR code of Unicode half blocks, light on dark.
+pub fn invite_qr(code: &str) -> Result<String, String> {
+    // Upper case fits QR's alphanumeric mode, which is denser than bytes.
+    let qr = QrCode::new(code.to_ascii_uppercase()).map_err(|e| format!("QR error: {}", e))?;
+    Ok(qr
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build())
+}
+
+impl InviteUrl {
+    pub fn to_code(&self) -> Result<String, String> {
+        let pubkey = PublicKey::try_from(self.pubkey.as_str())
+            .map_err(|e| format!("Invalid public key: {}", e))?;
+        let key = from_base64_url(&self.key)?;
+        let key_length = if self.password_protected {
+            WRAPPED_KEY_LENGTH
+        } else {
+            32
+        };
+        if key.len() != key_length {
+            return Err("Invalid invite key length".to_string());
+        }
+
+        let mut flags = 0;
+        let mut fields = Vec::new();
+        if let Some(token) = &self.token {
+            let token = from_base64_url(token)?;
+            if token.len() != INVITE_TOKEN_LENGTH {
+                return Err("Invalid invite token length".to_string());
+            }
+            flags |= FLAG_TOKEN;
+            fields.extend_from_slice(&token);
+        }
+        if let Some(exp) = self.expires_at {
+            flags |= FLAG_EXPIRY;
+            fields.extend_from_slice(&exp.to_be_bytes());
+        }
+        if self.single_use {
+            flags |= FLAG_SINGLE_USE;
+        }
+        if self.password_protected {
+            flags |= FLAG_PASSWORD;
+        }
+
+        let mut data = vec![INVITE_CODE_VERSION, flags];
+        data.extend_from_slice(pubkey.as_bytes());
+        data.extend_from_slice(&key);
+        data.extend_from_slice(&fields);
+
+        let hrp = Hrp::parse(INVITE_CODE_HRP).map_err(|e| format!("Bech32 error: {}", e))?;
+        bech32::encode::<Bech32m>(hrp, &data).map_err(|e| format!("Bech32 error: {}", e))
+    }
+
+    pub fn from_code(code: &str) -> Result<Self, String> {
+        let checked = CheckedHrpstring::new::<Bech32m>(code.trim())
+            .map_err(|e| format!("Invalid invite code: {}", e))?;
+        if !checked.hrp().as_str().eq_ignore_ascii_case(INVITE_CODE_HRP) {
+            return Err("Invalid invite code: not a ghostly invite".to_string());
+        }
+        let data: Vec<u8> = checked.byte_iter().collect();
+
+        let (&version, rest) = data.split_first().ok_or("Invalid invite code: empty")?;
+        if version != INVITE_CODE_VERSION {
+            return Err(format!("Unsupported invite code version: {}", version));
+        }
+        let (&flags, mut rest) = rest.split_first().ok_or("Invalid invite code: truncated")?;
+        if flags & FLAG_SEED != 0 {
+            return Err("Invite code carries a desktop keypair; open it in the app".to_string());
+        }
+        let mut take = |n: usize| -> Result<&[u8], String> {
+            if rest.len() < n {
+                return Err("Invalid invite code: truncated".to_string());
+            }
+            let (field, tail) = rest.split_at(n);
+            rest = tail;
+            Ok(field)
+        };
+
+        let pubkey_bytes: [u8; 32] = take(32)?.try_into().unwrap();
+        let pubkey =
+            PublicKey::try_from(&pubkey_bytes).map_err(|e| format!("Invalid public key: {}", e))?;
+        let password_protected = flags & FLAG_PASSWORD != 0;
+        let key = to_base64_url(take(if password_protected {
+            WRAPPED_KEY_LENGTH
+        } else {
+            32
+        })?);
+        let token = match flags & FLAG_TOKEN {
+            0 => None,
+            _ => Some(to_base64_url(take(INVITE_TOKEN_LENGTH)?)),
+        };
+        let expires_at = match flags & FLAG_EXPIRY {
+            0 => None,
+            _ => Some(i64::from_be_bytes(take(8)?.try_into().unwrap())),
+        };
+        if !rest.is_empty() {
+            return Err("Invalid invite code: trailing data".to_string());
+        }
+
+        Ok(InviteUrl {
+            pubkey: pubkey.to_z32(),
+            key,
+            token,
+            expires_at,
+            single_use: flags & FLAG_SINGLE_USE != 0,
+            password_protected,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{generate_invite_token, generate_key, wrap_key};
+    use pkarr::Keypair;
+
+    fn invite() -> InviteUrl {
+        InviteUrl {
+            pubkey: Keypair::random().to_z32(),
+            key: generate_key().to_base64_url(),
+            token: None,
+            expires_at: None,
+            single_use: false,
+            password_protected: false,
+        }
+    }
+
+    #[test]
+    fn roundtrips_every_field() {
+        let plain = invite();
+        let limited = InviteUrl {
+            token: Some(generate_invite_token()),
+            expires_at: Some(1_700_000_000_000),
+            single_use: true,
+            ..invite()
+        };
+        let protected = {
+            let base = invite();
+            let key = wrap_key(&generate_key(), "pass", &base.pubkey).unwrap();
+            InviteUrl {
+                key,
+                password_protected: true,
+                ..base
+            }
+        };
+
+        for invite in [plain, limited, protected] {
+            let code = invite.to_code().unwrap();
+            assert!(is_invite_code(&code));
+            assert_eq!(InviteUrl::from_code(&code).unwrap(), invite);
+            assert_eq!(decode_invite_code(&code).unwrap(), invite.to_url());
+            assert_eq!(encode_invite_code(&invite.to_url()).unwrap(), code);
+        }
+    }
+
+    #[test]
+    fn rejects_typos_and_foreign_codes() {
+        let code = invite().to_code().unwrap();
+        let last = code.chars().last().unwrap();
+        let typo = format!(
+            "{}{}",
+            &code[..code.len() - 1],
+            if last == 'q' { 'p' } else { 'q' }
+        );
+        assert!(InviteUrl::from_code(&typo)
+            .unwrap_err()
+            .starts_with("Invalid invite code"));
+
+        let checked = CheckedHrpstring::new::<Bech32m>(&code).unwrap();
+        let data: Vec<u8> = checked.byte_iter().collect();
+        let foreign = bech32::encode::<Bech32m>(Hrp::parse("bc").unwrap(), &data).unwrap();
+        assert_eq!(
+            InviteUrl::from_code(&foreign).unwrap_err(),
+            "Invalid invite code: not a ghostly invite"
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use crate::crypto::{SecretSeed, SharedKey};
+use crate::types::{CompactMessage, MessageKind, SendOutput};
+use crate::GhostClient;
+
+/// Text carried by delete tombstones, shown as-is by clients that predate kinds.
+pub const DELETED_TEXT: &str = "🗑️ Message deleted";
+
+/// What clients that predate kinds show for a message of `kind` with `text`.
+/// Replies and edits are readable as plain messages, so they keep `text`.
+pub fn fallback_text(kind: &MessageKind, text: &str) -> String {
+    match kind {
+        MessageKind::Reply { .. } | MessageKind::Edit { .. } => text.to_string(),
+        MessageKind::React { emoji, .. } => format!("Reacted {}", emoji),
+        MessageKind::Delete { .. } => DELETED_TEXT.to_string(),
+    }
+}
+
+impl GhostClient {
+    /// Sends a reply, reaction, edit or delete. `text` is ignored for
+    /// reactions and deletes.
+    pub async fn send_kind(
+        &self,
+        seed: &SecretSeed,
+        peer_pubkey: &str,
+        shared_key: &SharedKey,
+        text: &str,
+        kind: MessageKind,
+        nick: Option<&str>,
+    ) -> Result<SendOutput, String> {
+        let draft = CompactMessage {
+            m: fallback_text(&kind, text),
+            k: Some(kind),
+            ..Default::default()
+        };
+        self.send_draft(seed, peer_pubkey, shared_key, draft, nick)
+            .await
+    }
+}
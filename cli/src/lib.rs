@@ -1,17 +1,66 @@
+pub mod attachment;
+pub mod capabilities;
+pub mod channel;
 pub mod crypto;
+pub mod group;
+pub mod inspect;
+pub mod invite;
+pub mod invite_code;
+pub mod kinds;
+pub mod migration;
 pub mod pkarr;
+pub mod presence;
+pub mod profile;
+pub mod rotation;
+pub mod schedule;
+pub mod session;
 pub mod types;
+pub mod watch;
 
-pub use crypto::{from_base64_url, generate_key, to_base64_url};
+pub use attachment::{
+    attachment_file_name, attachment_text, ATTACHMENT_CHUNK_SIZE, MAX_ATTACHMENT_SIZE,
+};
+pub use capabilities::{
+    negotiated_send_key, negotiated_version, peer_lacks, BASELINE_VERSION, CLIENT_FEATURES,
+    FEATURE_CALLS, FEATURE_CHUNKS, FEATURE_COMPRESSION, FEATURE_KINDS, FEATURE_PRESENCE,
+    FEATURE_PROFILE, FEATURE_RATCHET, LEGACY_VERSION, PROTOCOL_VERSION,
+};
+pub use channel::{
+    channel_info, channel_url, new_channel, parse_channel_url, DEFAULT_CHANNEL_WINDOW,
+};
+pub use crypto::{
+    direction_keys, from_base64_url, generate_key, generate_message_id, safety_number,
+    to_base64_url, unwrap_key, wrap_key, PayloadKey, SecretSeed, SharedKey,
+};
+pub use group::{group_invite_url, new_group_invite, parse_group_invite};
+pub use inspect::MAX_PACKET_SIZE;
+pub use invite::{generate_limited_invite, invite_needs_passphrase, parse_ttl, protect_invite};
+pub use invite_code::{decode_invite_code, encode_invite_code, invite_qr, is_invite_code};
+pub use kinds::{fallback_text, DELETED_TEXT};
+pub use migration::{apply_migration, publish_moved, session_keys};
 pub use pkarr::{
-    create_keypair, keypair_from_seed, pubkey_from_seed, publish_messages, resolve_messages,
+    ack_for, auth_failure, create_keypair, decode_packet, keypair_from_seed, last_sent_seq,
+    outbox_from, peer_ack, pubkey_from_seed, publish_messages, resolve_messages,
+    resolve_messages_if_newer, unacked_messages,
 };
+pub use profile::{MAX_PROFILE_LENGTH, PROFILE_VERSION};
+pub use rotation::{apply_rotation, conversation_keys, ROTATION_TEXT};
+pub use schedule::PollSchedule;
+pub use session::SessionStore;
 pub use types::*;
+pub use watch::{spawn_watch, WatchItem, WatchOptions, WatchTarget};
 
-use ::pkarr::Client;
+use ::pkarr::{Client, Keypair};
+use invite::InviteUrl;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
+#[derive(Clone)]
 pub struct GhostClient {
     client: Client,
+    packet_timestamps: Arc<Mutex<HashMap<String, i64>>>,
+    padding: Padding,
+    profile: Option<PeerProfile>,
 }
 
 impl GhostClient {
@@ -20,60 +69,267 @@ impl GhostClient {
             client: Client::builder()
                 .build()
                 .expect("Failed to create pkarr client"),
+            packet_timestamps: Arc::new(Mutex::new(HashMap::new())),
+            padding: Padding::default(),
+            profile: None,
+        }
+    }
+
+    pub fn with_padding(mut self, padding: Padding) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Publishes `profile` in our packets when they have room for it.
+    pub fn with_profile(mut self, profile: Option<PeerProfile>) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    pub fn last_packet_timestamp(&self, peer_pubkey: &str) -> i64 {
+        self.packet_timestamps
+            .lock()
+            .unwrap()
+            .get(peer_pubkey)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Resolves the peer's packet, returning `Ok(None)` when it has not changed
+    /// since the last call for this peer (or when nothing is published).
+    pub async fn resolve_changed(
+        &self,
+        peer_pubkey: &str,
+        key: &PayloadKey,
+    ) -> Result<Option<ResolvedBatch>, String> {
+        let since = self.last_packet_timestamp(peer_pubkey);
+        let batch = resolve_messages_if_newer(&self.client, peer_pubkey, key, since).await?;
+        if let Some(b) = &batch {
+            self.packet_timestamps
+                .lock()
+                .unwrap()
+                .insert(peer_pubkey.to_string(), b.packet_timestamp);
         }
+        Ok(batch)
     }
 
     pub async fn send(
         &self,
-        seed: &str,
+        seed: &SecretSeed,
         peer_pubkey: &str,
-        shared_key: &str,
+        shared_key: &SharedKey,
         message: &str,
         nick: Option<&str>,
     ) -> Result<SendOutput, String> {
-        let keypair = keypair_from_seed(seed)?;
-        let key_bytes = from_base64_url(shared_key)?;
+        let draft = CompactMessage {
+            m: message.to_string(),
+            ..Default::default()
+        };
+        self.send_draft(seed, peer_pubkey, shared_key, draft, nick)
+            .await
+    }
 
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map_err(|e| format!("Time error: {}", e))?
-            .as_millis() as i64;
+    pub(crate) async fn send_draft(
+        &self,
+        seed: &SecretSeed,
+        peer_pubkey: &str,
+        shared_key: &SharedKey,
+        mut draft: CompactMessage,
+        nick: Option<&str>,
+    ) -> Result<SendOutput, String> {
+        let keypair = keypair_from_seed(seed);
+        let (_, recv_key) = direction_keys(shared_key, &keypair.to_z32(), peer_pubkey);
 
-        let messages = vec![CompactMessage {
-            t: timestamp,
-            m: message.to_string(),
-        }];
+        let peer_batch = resolve_messages(&self.client, peer_pubkey, &recv_key).await?;
+        let ack = peer_batch.as_ref().map(ack_for).unwrap_or_default();
 
-        let peer_batch = resolve_messages(&self.client, peer_pubkey, &key_bytes).await?;
-        let ack = peer_batch.map(|b| b.latest_timestamp).unwrap_or(0);
+        let peer_caps = peer_batch.as_ref().and_then(|b| b.capabilities.as_ref());
+        let send_key = negotiated_send_key(
+            shared_key,
+            &keypair.to_z32(),
+            peer_pubkey,
+            peer_batch.as_ref(),
+        );
 
-        let kept =
-            publish_messages(&self.client, &keypair, &messages, &key_bytes, ack, nick).await?;
+        // Peers that can't read kinds get the fallback text alone.
+        if peer_lacks(peer_caps, FEATURE_KINDS) {
+            draft.k = None;
+        }
+
+        let (kept, sent) = self
+            .publish_outbox_with(
+                &keypair,
+                &send_key,
+                peer_batch.as_ref(),
+                &ack,
+                Some(draft),
+                nick,
+                false,
+            )
+            .await?;
+        let sent = sent.ok_or("Message was not queued")?;
 
         Ok(SendOutput {
             ok: true,
-            timestamp,
+            timestamp: sent.t,
+            id: sent.i.unwrap_or_default(),
+            seq: sent.s.unwrap_or_default(),
             messages_kept: kept,
         })
     }
 
-    pub async fn recv(&self, peer_pubkey: &str, shared_key: &str) -> Result<RecvOutput, String> {
-        let key_bytes = from_base64_url(shared_key)?;
+    async fn resolve_own(
+        &self,
+        keypair: &Keypair,
+        key: &PayloadKey,
+    ) -> Result<Option<ResolvedBatch>, String> {
+        resolve_messages(&self.client, &keypair.to_z32(), key).await
+    }
+
+    /// Republishes our still-unacked messages (read back from our own packet),
+    /// optionally appending a new one with the next sequence number.
+    pub async fn publish_outbox(
+        &self,
+        keypair: &Keypair,
+        key: &PayloadKey,
+        peer_batch: Option<&ResolvedBatch>,
+        ack: &Ack,
+        message: Option<&str>,
+        nick: Option<&str>,
+    ) -> Result<(usize, Option<CompactMessage>), String> {
+        self.publish_outbox_with(
+            keypair,
+            key,
+            peer_batch,
+            ack,
+            message.map(|m| CompactMessage {
+                m: m.to_string(),
+                ..Default::default()
+            }),
+            nick,
+            false,
+        )
+        .await
+    }
+
+    /// Like [`Self::publish_outbox`], but takes the new message as a draft
+    /// whose `t`, `i` and `s` are filled in here, and the typing flag to
+    /// publish in our presence.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn publish_outbox_with(
+        &self,
+        keypair: &Keypair,
+        key: &PayloadKey,
+        peer_batch: Option<&ResolvedBatch>,
+        ack: &Ack,
+        message: Option<CompactMessage>,
+        nick: Option<&str>,
+        typing: bool,
+    ) -> Result<(usize, Option<CompactMessage>), String> {
+        let own_batch = self.resolve_own(keypair, key).await?;
 
-        let batch = resolve_messages(&self.client, peer_pubkey, &key_bytes).await?;
+        let mut outbox = own_batch.as_ref().map(outbox_from).unwrap_or_default();
+        if let Some(peer_batch) = peer_batch {
+            outbox = unacked_messages(&outbox, &peer_ack(peer_batch));
+        }
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| format!("Time error: {}", e))?
+            .as_millis() as i64;
+        let mut last_seq = last_sent_seq(own_batch.as_ref(), peer_batch);
+        let sent = match message {
+            Some(draft) => {
+                last_seq += 1;
+                let msg = CompactMessage {
+                    t: now,
+                    i: Some(generate_message_id()),
+                    s: Some(last_seq),
+                    ..draft
+                };
+                outbox.push(msg.clone());
+                Some(msg)
+            }
+            None => None,
+        };
+
+        let kept = publish_messages(
+            &self.client,
+            keypair,
+            &outbox,
+            key,
+            last_seq,
+            ack,
+            nick,
+            Some(&Presence {
+                last_active: now,
+                typing,
+            }),
+            self.profile.as_ref(),
+            self.padding,
+        )
+        .await?;
+
+        Ok((kept, sent))
+    }
+
+    pub async fn recv(
+        &self,
+        seed: Option<&SecretSeed>,
+        peer_pubkey: &str,
+        shared_key: &SharedKey,
+    ) -> Result<RecvOutput, String> {
+        self.recv_if_newer(seed, peer_pubkey, shared_key, 0).await
+    }
+
+    /// Without `seed` only v0/v1 payloads open; v2 ones are keyed to the receiver's pubkey.
+    pub async fn recv_if_newer(
+        &self,
+        seed: Option<&SecretSeed>,
+        peer_pubkey: &str,
+        shared_key: &SharedKey,
+        if_newer_than: i64,
+    ) -> Result<RecvOutput, String> {
+        let key = match seed {
+            Some(seed) => PayloadKey::directed(shared_key, peer_pubkey, &pubkey_from_seed(seed)),
+            None => PayloadKey::shared(shared_key),
+        };
+
+        let batch =
+            resolve_messages_if_newer(&self.client, peer_pubkey, &key, if_newer_than).await?;
 
         match batch {
             Some(b) => Ok(RecvOutput {
                 messages: b.messages,
                 peer_ack: b.peer_ack,
+                peer_ack_id: b.peer_ack_id,
+                peer_ack_seq: b.peer_ack_seq,
                 latest_ts: b.latest_timestamp,
                 message_count: b.message_count,
+                clock_skew_ms: b.clock_skew_ms,
+                sender_authenticated: b.sender_authenticated,
+                packet_ts: b.packet_timestamp,
+                unchanged: false,
+                presence: b.presence,
+                profile: b.profile,
+                capabilities: b.capabilities,
+                record_status: b.record_status,
             }),
             None => Ok(RecvOutput {
                 messages: vec![],
                 peer_ack: 0,
+                peer_ack_id: None,
+                peer_ack_seq: None,
                 latest_ts: 0,
                 message_count: 0,
+                clock_skew_ms: None,
+                sender_authenticated: false,
+                packet_ts: 0,
+                unchanged: if_newer_than > 0,
+                presence: None,
+                profile: None,
+                capabilities: None,
+                record_status: Default::default(),
             }),
         }
     }
@@ -85,38 +341,47 @@ impl Default for GhostClient {
     }
 }
 
-pub fn generate_invite(seed: &str, shared_key: &str) -> Result<InviteOutput, String> {
-    let pubkey = pubkey_from_seed(seed)?;
-    let invite_url = format!("ghost://{}#{}", pubkey, shared_key);
-    Ok(InviteOutput { invite_url, pubkey })
-}
-
-pub fn parse_invite(invite_url: &str) -> Result<ParsedInvite, String> {
-    let url = invite_url
-        .strip_prefix("ghost://")
-        .ok_or("Invalid invite URL: must start with ghost://")?;
-
-    let parts: Vec<&str> = url.split('#').collect();
-    if parts.len() != 2 {
-        return Err("Invalid invite URL: missing # separator".to_string());
+pub fn generate_invite(seed: &SecretSeed, shared_key: &SharedKey) -> InviteOutput {
+    InviteUrl {
+        pubkey: pubkey_from_seed(seed),
+        key: shared_key.to_base64_url(),
+        token: None,
+        expires_at: None,
+        single_use: false,
+        password_protected: false,
     }
+    .to_output()
+}
 
-    let peer_pubkey = parts[0].to_string();
-    let shared_key = parts[1].to_string();
+/// Parses a `ghost://` invite or compact invite code; password-protected
+/// invites need `passphrase` to unwrap the shared key.
+pub fn parse_invite(invite_url: &str, passphrase: Option<&str>) -> Result<ParsedInvite, String> {
+    let invite = InviteUrl::parse(invite_url)?;
+    let shared_key = match (invite.password_protected, passphrase) {
+        (false, _) => SharedKey::from_base64_url(&invite.key)?,
+        (true, Some(passphrase)) => unwrap_key(&invite.key, passphrase, &invite.pubkey)?,
+        (true, None) => return Err("Invite is password-protected: passphrase required".to_string()),
+    };
+    let expired = invite.expires_at.is_some_and(|exp| invite::now_ms() > exp);
 
     let (_, my_seed, my_pubkey) = create_keypair();
 
     Ok(ParsedInvite {
-        peer_pubkey,
+        peer_pubkey: invite.pubkey,
         shared_key,
         my_seed,
         my_pubkey,
+        token: invite.token,
+        expires_at: invite.expires_at,
+        expired,
+        single_use: invite.single_use,
+        password_protected: invite.password_protected,
     })
 }
 
 pub fn new_identity() -> IdentityOutput {
     let (_, seed, pubkey) = create_keypair();
-    let shared_key = to_base64_url(&generate_key());
+    let shared_key = generate_key();
     IdentityOutput {
         seed,
         pubkey,
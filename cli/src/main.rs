@@ -1,6 +1,15 @@
 use clap::{Parser, Subcommand};
-use ghostly::{generate_invite, new_identity, parse_invite, ErrorOutput, GhostClient, WatchEvent};
+use ghostly::{
+    attachment_file_name, auth_failure, generate_invite, generate_limited_invite,
+    invite_needs_passphrase, invite_qr, new_group_invite, new_identity, parse_group_invite,
+    parse_invite, parse_ttl, protect_invite, pubkey_from_seed, safety_number, spawn_watch,
+    Attachment, ErrorOutput, FingerprintOutput, GhostClient, Group, GroupInfo, MessageKind,
+    MessageRef, Padding, PeerProfile, SecretSeed, SendOutput, Session, SessionInfo, SessionStore,
+    SharedKey, WarningOutput, WatchItem, WatchOptions, WatchTarget,
+};
 use std::io::{self, Write};
+use std::path::PathBuf;
+use zeroize::Zeroizing;
 
 #[derive(Parser)]
 #[command(name = "ghostly-cli")]
@@ -18,6 +27,14 @@ struct Cli {
     /// Suppress extra output
     #[arg(long, short, global = true)]
     quiet: bool,
+
+    /// Pad published payloads to hide their size: off, bucketed or full
+    #[arg(long, default_value = "bucketed", global = true)]
+    padding: Padding,
+
+    /// Exit non-zero when the peer's records fail to decrypt (wrong key or tampering)
+    #[arg(long, global = true)]
+    strict: bool,
 }
 
 #[derive(Subcommand)]
@@ -35,16 +52,16 @@ enum Commands {
     /// Send a message
     Send {
         /// Your seed (base64url)
-        #[arg(long)]
-        seed: String,
+        #[arg(long, value_parser = SecretSeed::from_base64_url)]
+        seed: SecretSeed,
 
         /// Peer's public key (z32)
         #[arg(long)]
         peer: String,
 
         /// Shared encryption key (base64url)
-        #[arg(long)]
-        key: String,
+        #[arg(long, value_parser = SharedKey::from_base64_url)]
+        key: SharedKey,
 
         /// Your nickname (optional)
         #[arg(long)]
@@ -54,45 +71,360 @@ enum Commands {
         #[arg(long)]
         stdin: bool,
 
+        /// Reply to this message (id, or timestamp for messages without one)
+        #[arg(long, value_parser = MessageRef::parse, conflicts_with_all = ["react_to", "edit", "delete"])]
+        reply_to: Option<MessageRef>,
+
+        /// React to this message; the message text is the emoji
+        #[arg(long, value_parser = MessageRef::parse, conflicts_with_all = ["edit", "delete"])]
+        react_to: Option<MessageRef>,
+
+        /// Replace the text of one of your messages
+        #[arg(long, value_parser = MessageRef::parse, conflicts_with = "delete")]
+        edit: Option<MessageRef>,
+
+        /// Delete one of your messages for everyone (no message text)
+        #[arg(long, value_parser = MessageRef::parse, conflicts_with_all = ["stdin", "message"])]
+        delete: Option<MessageRef>,
+
         /// Message text (if not using --stdin)
         message: Option<String>,
     },
-    /// Receive messages (single poll)
-    Recv {
+    /// Show or clear a typing indicator for the peer
+    Typing {
+        /// Your seed (base64url)
+        #[arg(long, value_parser = SecretSeed::from_base64_url)]
+        seed: SecretSeed,
+
         /// Peer's public key (z32)
         #[arg(long)]
         peer: String,
 
         /// Shared encryption key (base64url)
+        #[arg(long, value_parser = SharedKey::from_base64_url)]
+        key: SharedKey,
+
+        /// Your nickname (optional)
+        #[arg(long)]
+        nick: Option<String>,
+
+        /// Clear the indicator instead of setting it
         #[arg(long)]
-        key: String,
+        stop: bool,
     },
-    /// Watch for new messages (streaming mode for bots)
-    Watch {
+    /// Send a file as an encrypted, chunked attachment
+    SendFile {
         /// Your seed (base64url)
+        #[arg(long, value_parser = SecretSeed::from_base64_url)]
+        seed: SecretSeed,
+
+        /// Peer's public key (z32)
         #[arg(long)]
-        seed: String,
+        peer: String,
+
+        /// Shared encryption key (base64url)
+        #[arg(long, value_parser = SharedKey::from_base64_url)]
+        key: SharedKey,
+
+        /// Your nickname (optional)
+        #[arg(long)]
+        nick: Option<String>,
+
+        /// File to send
+        path: PathBuf,
+    },
+    /// Download an attachment, resuming an interrupted fetch
+    FetchFile {
+        /// The message's `attachment` JSON (from recv or watch)
+        manifest: String,
+
+        /// Where to save the file (default: the attachment's name)
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Receive messages (single poll)
+    Recv {
+        /// Your seed (base64url); needed to read v2 (per-direction key) payloads
+        #[arg(long, value_parser = SecretSeed::from_base64_url)]
+        seed: Option<SecretSeed>,
 
         /// Peer's public key (z32)
         #[arg(long)]
         peer: String,
 
         /// Shared encryption key (base64url)
+        #[arg(long, value_parser = SharedKey::from_base64_url)]
+        key: SharedKey,
+
+        /// Skip decryption unless the packet is newer than this timestamp (ms)
+        #[arg(long, default_value = "0")]
+        if_newer_than: i64,
+    },
+    /// Dump a peer's raw packet: records, sizes, signature and decryption results
+    Inspect {
+        /// Your seed (base64url); needed to decrypt v2 (per-direction key) payloads
+        #[arg(long, value_parser = SecretSeed::from_base64_url, requires = "key")]
+        seed: Option<SecretSeed>,
+
+        /// Peer's public key (z32)
         #[arg(long)]
-        key: String,
+        peer: String,
+
+        /// Shared encryption key (base64url); reports per-record decryption when given
+        #[arg(long, value_parser = SharedKey::from_base64_url)]
+        key: Option<SharedKey>,
+    },
+    /// Watch for new messages (streaming mode for bots)
+    Watch {
+        /// Your seed (base64url)
+        #[arg(
+            long,
+            value_parser = SecretSeed::from_base64_url,
+            requires_all = ["peer", "key"],
+            conflicts_with_all = ["sessions", "all"]
+        )]
+        seed: Option<SecretSeed>,
+
+        /// Peer's public key (z32)
+        #[arg(long, requires_all = ["seed", "key"])]
+        peer: Option<String>,
+
+        /// Shared encryption key (base64url)
+        #[arg(long, value_parser = SharedKey::from_base64_url, requires_all = ["seed", "peer"])]
+        key: Option<SharedKey>,
 
         /// Your nickname (optional)
         #[arg(long)]
         nick: Option<String>,
 
-        /// Poll interval in milliseconds
+        /// Watch these saved sessions (comma-separated names)
+        #[arg(long, value_delimiter = ',', conflicts_with = "all")]
+        sessions: Vec<String>,
+
+        /// Watch every saved session
+        #[arg(long)]
+        all: bool,
+
+        /// Maximum number of concurrent resolves
+        #[arg(long, default_value = "8")]
+        concurrency: usize,
+
+        /// Poll interval in milliseconds right after activity
         #[arg(long, default_value = "2000")]
         poll_interval: u64,
 
+        /// Ceiling for the idle/error backoff in milliseconds
+        #[arg(long, default_value = "60000")]
+        max_poll_interval: u64,
+
+        /// Emit a heartbeat event per peer at most every N milliseconds
+        #[arg(long)]
+        heartbeat: Option<u64>,
+
         /// ACK received messages automatically
         #[arg(long, default_value = "true")]
         ack: bool,
     },
+    /// Manage saved sessions
+    Session {
+        #[command(subcommand)]
+        action: SessionAction,
+    },
+    /// Group chats with more than two participants
+    Group {
+        #[command(subcommand)]
+        action: GroupAction,
+    },
+    /// Broadcast channels: one publisher, many read-only followers
+    Channel {
+        #[command(subcommand)]
+        action: ChannelAction,
+    },
+    /// Manage the profile published to your peers
+    Profile {
+        #[command(subcommand)]
+        action: ProfileAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum ProfileAction {
+    /// Update your profile; an empty value clears that field
+    Set {
+        /// Display name
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Status text
+        #[arg(long)]
+        status: Option<String>,
+
+        /// Avatar placeholder as a blurhash string
+        #[arg(long)]
+        blurhash: Option<String>,
+
+        /// Avatar as an attachment manifest JSON (from send-file)
+        #[arg(long)]
+        avatar: Option<String>,
+    },
+    /// Show your profile
+    Show,
+    /// Stop publishing a profile
+    Clear,
+}
+
+#[derive(Subcommand)]
+enum ChannelAction {
+    /// Create a channel you publish to
+    Create {
+        /// Channel name
+        name: String,
+
+        /// Number of most recent posts kept in the packet
+        #[arg(long, default_value_t = ghostly::DEFAULT_CHANNEL_WINDOW)]
+        window: usize,
+    },
+    /// Post to a channel you created
+    Post {
+        /// Channel name
+        name: String,
+
+        /// Read message from stdin instead of argument
+        #[arg(long)]
+        stdin: bool,
+
+        /// Message text (if not using --stdin)
+        message: Option<String>,
+    },
+    /// Stream posts from a channel (saved name or ghost-channel:// URL)
+    Follow {
+        /// Channel name or URL
+        channel: String,
+
+        /// Save the followed URL under this name
+        #[arg(long)]
+        save: Option<String>,
+
+        /// Poll interval in milliseconds right after activity
+        #[arg(long, default_value = "2000")]
+        poll_interval: u64,
+
+        /// Ceiling for the idle/error backoff in milliseconds
+        #[arg(long, default_value = "60000")]
+        max_poll_interval: u64,
+    },
+    /// List saved channels
+    List,
+}
+
+#[derive(Subcommand)]
+enum GroupAction {
+    /// Create a new group
+    Create {
+        /// Group name
+        name: String,
+
+        /// Your nickname (optional)
+        #[arg(long)]
+        nick: Option<String>,
+    },
+    /// Invite a new member; they join with their own keypair
+    Invite {
+        /// Group name
+        name: String,
+    },
+    /// Join a group from a ghost-group:// invite
+    Join {
+        /// Local name for the group
+        name: String,
+
+        /// The invite URL (ghost-group://...)
+        url: String,
+
+        /// Your nickname (optional)
+        #[arg(long)]
+        nick: Option<String>,
+    },
+    /// Leave a group
+    Leave {
+        /// Group name
+        name: String,
+    },
+    /// List group members
+    Members {
+        /// Group name
+        name: String,
+    },
+    /// List saved groups
+    List,
+    /// Send a message to the group
+    Send {
+        /// Group name
+        name: String,
+
+        /// Message text
+        message: String,
+    },
+    /// Receive messages from every member (single poll)
+    Recv {
+        /// Group name
+        name: String,
+
+        /// Don't publish acks for received messages
+        #[arg(long)]
+        no_ack: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SessionAction {
+    /// Save a session under a name
+    Add {
+        /// Session name
+        name: String,
+
+        /// Your seed (base64url)
+        #[arg(long, value_parser = SecretSeed::from_base64_url)]
+        seed: SecretSeed,
+
+        /// Peer's public key (z32)
+        #[arg(long)]
+        peer: String,
+
+        /// Shared encryption key (base64url)
+        #[arg(long, value_parser = SharedKey::from_base64_url)]
+        key: SharedKey,
+
+        /// Your nickname (optional)
+        #[arg(long)]
+        nick: Option<String>,
+    },
+    /// List saved sessions
+    List,
+    /// Show the safety number to compare with the peer out of band
+    Fingerprint {
+        /// Session name
+        name: String,
+
+        /// Mark the session as verified; pass the digits the peer read out
+        #[arg(long, value_name = "DIGITS")]
+        verify: Option<String>,
+    },
+    /// Switch a saved session to a fresh shared key, announced in-band
+    RotateKey {
+        /// Session name
+        name: String,
+    },
+    /// Move a saved session to a new keypair; the old one points peers at it
+    Migrate {
+        /// Session name
+        name: String,
+    },
+    /// Remove a saved session
+    Remove {
+        /// Session name
+        name: String,
+    },
 }
 
 #[derive(Subcommand)]
@@ -106,27 +438,250 @@ enum InviteAction {
     /// Generate an invite URL
     New {
         /// Your seed (base64url)
-        #[arg(long)]
-        seed: String,
+        #[arg(long, value_parser = SecretSeed::from_base64_url)]
+        seed: SecretSeed,
 
         /// Shared key (optional, generates new if not provided)
+        #[arg(long, value_parser = SharedKey::from_base64_url)]
+        key: Option<SharedKey>,
+
+        /// Expire the invite after this long (e.g. 30m, 1h, 7d)
+        #[arg(long, value_parser = parse_ttl)]
+        ttl: Option<i64>,
+
+        /// Accept only the first join
+        #[arg(long)]
+        single_use: bool,
+
+        /// Wrap the shared key under a passphrase (prompted, or GHOSTLY_INVITE_PASSPHRASE)
+        #[arg(long)]
+        password: bool,
+
+        /// Also print the invite code as a QR code (to stderr)
         #[arg(long)]
-        key: Option<String>,
+        qr: bool,
     },
     /// Parse an invite URL
     Parse {
-        /// The invite URL (ghost://...)
+        /// The invite URL (ghost://...) or invite code (ghost1...)
         url: String,
     },
+    /// Join an invite that has a token and save the session
+    Join {
+        /// The invite URL (ghost://...) or invite code (ghost1...)
+        url: String,
+
+        /// Session name
+        #[arg(long)]
+        name: String,
+
+        /// Your nickname (optional)
+        #[arg(long)]
+        nick: Option<String>,
+    },
+    /// Accept the join on an invite created with --ttl or --single-use
+    Accept {
+        /// Invite token (from `invite new`)
+        token: String,
+
+        /// Session name
+        #[arg(long)]
+        name: String,
+
+        /// Your nickname (optional)
+        #[arg(long)]
+        nick: Option<String>,
+    },
 }
 
 fn output_json<T: serde::Serialize>(value: &T) {
     println!("{}", serde_json::to_string(value).unwrap());
 }
 
+fn fail(msg: &str) -> ! {
+    output_error(msg);
+    std::process::exit(1);
+}
+
+fn open_store() -> SessionStore {
+    SessionStore::open_default().unwrap_or_else(|e| fail(&e))
+}
+
+/// Client for commands that publish, carrying our saved profile.
+fn publishing_client(padding: Padding) -> GhostClient {
+    let profile = SessionStore::open_default()
+        .ok()
+        .and_then(|store| store.profile().cloned());
+    GhostClient::new()
+        .with_padding(padding)
+        .with_profile(profile)
+}
+
+/// `Some("")` clears a field, `None` keeps its current value.
+fn update_field(field: &mut Option<String>, value: Option<String>) {
+    if let Some(value) = value {
+        *field = Some(value).filter(|v| !v.is_empty());
+    }
+}
+
+fn open_group(store: &SessionStore, name: &str) -> Group {
+    store
+        .group(name)
+        .cloned()
+        .unwrap_or_else(|| fail(&format!("Unknown group: {}", name)))
+}
+
+fn save_group(mut store: SessionStore, group: Group) {
+    store.upsert_group(group);
+    store.save().unwrap_or_else(|e| fail(&e));
+}
+
+fn group_info(group: &Group) -> GroupInfo {
+    GroupInfo {
+        name: group.name.clone(),
+        pubkey: ghostly::pubkey_from_seed(&group.seed),
+        members: group.members.clone(),
+    }
+}
+
+fn read_message(stdin: bool, message: Option<String>) -> String {
+    let msg = if stdin {
+        let mut input = String::new();
+        io::stdin()
+            .read_line(&mut input)
+            .expect("Failed to read from stdin");
+        input.trim().to_string()
+    } else {
+        message.unwrap_or_else(|| fail("Message required (provide as argument or use --stdin)"))
+    };
+
+    if msg.is_empty() {
+        fail("Message cannot be empty");
+    }
+    msg
+}
+
+fn save_target(name: &str, target: WatchTarget) -> Result<(), String> {
+    let mut store = SessionStore::open_default()?;
+    let mut session = store
+        .get(name)
+        .cloned()
+        .ok_or_else(|| format!("Unknown session: {}", name))?;
+    if session.peer != target.peer {
+        warn_peer_changed(name, &target.peer);
+        session.verified = false;
+    }
+    session.peer = target.peer;
+    session.key = target.key;
+    session.rotation = target.rotation;
+    session.migration = target.migration;
+    store.upsert(session);
+    store.save()
+}
+
+async fn stream_watch(
+    client: GhostClient,
+    targets: Vec<WatchTarget>,
+    options: WatchOptions,
+    quiet: bool,
+    strict: bool,
+) {
+    let mut events = spawn_watch(client, targets, options);
+
+    while let Some(item) = events.recv().await {
+        match item {
+            WatchItem::Message(event) => {
+                output_json(&event);
+                io::stdout().flush().ok();
+            }
+            WatchItem::Heartbeat(event) => {
+                output_json(&event);
+                io::stdout().flush().ok();
+            }
+            WatchItem::Presence(event) => {
+                output_json(&event);
+                io::stdout().flush().ok();
+            }
+            WatchItem::Rotation { event, target } => {
+                if let Some(name) = &event.session {
+                    if let Err(e) = save_target(name, target) {
+                        output_error(&e);
+                    }
+                }
+                output_json(&event);
+                io::stdout().flush().ok();
+            }
+            WatchItem::Moved { event, target } => {
+                if let Some(name) = &event.session {
+                    if let Err(e) = save_target(name, target) {
+                        output_error(&e);
+                    }
+                }
+                output_json(&event);
+                io::stdout().flush().ok();
+            }
+            WatchItem::AuthFailed(err) => {
+                if !quiet || strict {
+                    eprintln!("{}", serde_json::to_string(&err).unwrap());
+                }
+                if strict {
+                    std::process::exit(1);
+                }
+            }
+            WatchItem::Error(err) => {
+                if !quiet {
+                    eprintln!("{}", serde_json::to_string(&err).unwrap());
+                }
+            }
+        }
+    }
+}
+
+fn read_passphrase(confirm: bool) -> Zeroizing<String> {
+    if let Ok(passphrase) = std::env::var("GHOSTLY_INVITE_PASSPHRASE") {
+        let passphrase = Zeroizing::new(passphrase);
+        if passphrase.is_empty() {
+            fail("GHOSTLY_INVITE_PASSPHRASE must not be empty");
+        }
+        return passphrase;
+    }
+    let passphrase = Zeroizing::new(
+        rpassword::prompt_password("Invite passphrase: ")
+            .unwrap_or_else(|e| fail(&format!("Passphrase prompt error: {}", e))),
+    );
+    if passphrase.is_empty() {
+        fail("Passphrase must not be empty");
+    }
+    if confirm {
+        let again = Zeroizing::new(
+            rpassword::prompt_password("Repeat passphrase: ")
+                .unwrap_or_else(|e| fail(&format!("Passphrase prompt error: {}", e))),
+        );
+        if again != passphrase {
+            fail("Passphrases do not match");
+        }
+    }
+    passphrase
+}
+
+fn invite_passphrase(url: &str) -> Option<Zeroizing<String>> {
+    invite_needs_passphrase(url).then(|| read_passphrase(false))
+}
+
+fn warn_peer_changed(session: &str, peer: &str) {
+    let warning = WarningOutput {
+        warning: "Peer pubkey changed; compare the safety number again".to_string(),
+        session: Some(session.to_string()),
+        peer: Some(peer.to_string()),
+    };
+    eprintln!("{}", serde_json::to_string(&warning).unwrap());
+}
+
 fn output_error(msg: &str) {
     let err = ErrorOutput {
         error: msg.to_string(),
+        session: None,
+        peer: None,
     };
     eprintln!("{}", serde_json::to_string(&err).unwrap());
 }
@@ -144,24 +699,99 @@ async fn main() {
         },
 
         Commands::Invite { action } => match action {
-            InviteAction::New { seed, key } => {
-                let shared_key =
-                    key.unwrap_or_else(|| ghostly::to_base64_url(&ghostly::generate_key()));
-                match generate_invite(&seed, &shared_key) {
-                    Ok(invite) => output_json(&invite),
+            InviteAction::New {
+                seed,
+                key,
+                ttl,
+                single_use,
+                password,
+                qr,
+            } => {
+                let shared_key = key.unwrap_or_else(ghostly::generate_key);
+                let mut output = if ttl.is_none() && !single_use {
+                    generate_invite(&seed, &shared_key)
+                } else {
+                    let (output, pending) =
+                        generate_limited_invite(&seed, &shared_key, ttl, single_use);
+                    let mut store = open_store();
+                    store.upsert_invite(pending);
+                    store.save().unwrap_or_else(|e| fail(&e));
+                    output
+                };
+                if password {
+                    protect_invite(&mut output, &shared_key, &read_passphrase(true))
+                        .unwrap_or_else(|e| fail(&e));
+                }
+                if qr {
+                    eprintln!(
+                        "{}",
+                        invite_qr(&output.invite_code).unwrap_or_else(|e| fail(&e))
+                    );
+                }
+                output_json(&output);
+            }
+            InviteAction::Parse { url } => {
+                match parse_invite(&url, invite_passphrase(&url).as_deref().map(String::as_str)) {
+                    Ok(parsed) => output_json(&parsed),
                     Err(e) => {
                         output_error(&e);
                         std::process::exit(1);
                     }
                 }
             }
-            InviteAction::Parse { url } => match parse_invite(&url) {
-                Ok(parsed) => output_json(&parsed),
-                Err(e) => {
-                    output_error(&e);
-                    std::process::exit(1);
-                }
-            },
+            InviteAction::Join { url, name, nick } => {
+                let parsed =
+                    parse_invite(&url, invite_passphrase(&url).as_deref().map(String::as_str))
+                        .unwrap_or_else(|e| fail(&e));
+                let client = publishing_client(cli.padding);
+                client
+                    .join_invite(&parsed)
+                    .await
+                    .unwrap_or_else(|e| fail(&e));
+                let mut store = open_store();
+                store.upsert(Session {
+                    name,
+                    seed: parsed.my_seed.clone(),
+                    peer: parsed.peer_pubkey.clone(),
+                    key: parsed.shared_key.clone(),
+                    nick,
+                    rotation: None,
+                    migration: None,
+                    verified: false,
+                });
+                store.save().unwrap_or_else(|e| fail(&e));
+                output_json(&parsed);
+            }
+            InviteAction::Accept { token, name, nick } => {
+                let mut store = open_store();
+                let mut invite = store
+                    .invite(&token)
+                    .cloned()
+                    .unwrap_or_else(|| fail(&format!("Unknown invite: {}", token)));
+                let client = publishing_client(cli.padding);
+                let peer = client
+                    .accept_invite(&mut invite)
+                    .await
+                    .unwrap_or_else(|e| fail(&e));
+                store.upsert(Session {
+                    name: name.clone(),
+                    seed: invite.seed.clone(),
+                    peer: peer.clone(),
+                    key: invite.key.clone(),
+                    nick: nick.clone(),
+                    rotation: None,
+                    migration: None,
+                    verified: false,
+                });
+                store.upsert_invite(invite);
+                store.save().unwrap_or_else(|e| fail(&e));
+                output_json(&SessionInfo {
+                    name,
+                    peer,
+                    nick,
+                    verified: false,
+                });
+            }
         },
 
         Commands::Send {
@@ -170,31 +800,37 @@ async fn main() {
             key,
             nick,
             stdin,
+            reply_to,
+            react_to,
+            edit,
+            delete,
             message,
         } => {
-            let msg = if stdin {
-                let mut input = String::new();
-                io::stdin()
-                    .read_line(&mut input)
-                    .expect("Failed to read from stdin");
-                input.trim().to_string()
-            } else {
-                match message {
-                    Some(m) => m,
-                    None => {
-                        output_error("Message required (provide as argument or use --stdin)");
-                        std::process::exit(1);
-                    }
-                }
+            let kind = match (reply_to, react_to, edit, delete) {
+                (Some(to), ..) => Some(MessageKind::Reply { to }),
+                (_, Some(to), ..) => Some(MessageKind::React {
+                    to,
+                    emoji: read_message(stdin, message.clone()),
+                }),
+                (_, _, Some(to), _) => Some(MessageKind::Edit { to }),
+                (.., Some(to)) => Some(MessageKind::Delete { to }),
+                _ => None,
+            };
+            let msg = match kind {
+                Some(MessageKind::React { .. } | MessageKind::Delete { .. }) => String::new(),
+                _ => read_message(stdin, message),
             };
 
-            if msg.is_empty() {
-                output_error("Message cannot be empty");
-                std::process::exit(1);
-            }
-
-            let client = GhostClient::new();
-            match client.send(&seed, &peer, &key, &msg, nick.as_deref()).await {
+            let client = publishing_client(cli.padding);
+            let sent = match kind {
+                Some(kind) => {
+                    client
+                        .send_kind(&seed, &peer, &key, &msg, kind, nick.as_deref())
+                        .await
+                }
+                None => client.send(&seed, &peer, &key, &msg, nick.as_deref()).await,
+            };
+            match sent {
                 Ok(result) => output_json(&result),
                 Err(e) => {
                     output_error(&e);
@@ -203,9 +839,18 @@ async fn main() {
             }
         }
 
-        Commands::Recv { peer, key } => {
-            let client = GhostClient::new();
-            match client.recv(&peer, &key).await {
+        Commands::Typing {
+            seed,
+            peer,
+            key,
+            nick,
+            stop,
+        } => {
+            let client = publishing_client(cli.padding);
+            match client
+                .set_typing(&seed, &peer, &key, !stop, nick.as_deref())
+                .await
+            {
                 Ok(result) => output_json(&result),
                 Err(e) => {
                     output_error(&e);
@@ -214,79 +859,474 @@ async fn main() {
             }
         }
 
-        Commands::Watch {
+        Commands::SendFile {
             seed,
             peer,
             key,
             nick,
-            poll_interval,
-            ack,
+            path,
         } => {
+            let client = publishing_client(cli.padding);
+            match client
+                .send_file(&seed, &peer, &key, &path, nick.as_deref())
+                .await
+            {
+                Ok(result) => output_json(&result),
+                Err(e) => {
+                    output_error(&e);
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        Commands::FetchFile { manifest, output } => {
+            let attachment: Attachment = serde_json::from_str(&manifest)
+                .unwrap_or_else(|e| fail(&format!("Invalid attachment manifest: {}", e)));
+            let output = output.unwrap_or_else(|| PathBuf::from(attachment_file_name(&attachment)));
             let client = GhostClient::new();
-            let mut last_seen_ts: i64 = 0;
-            let key_bytes = match ghostly::from_base64_url(&key) {
-                Ok(k) => k,
+            match client.fetch_file(&attachment, &output).await {
+                Ok(result) => output_json(&result),
                 Err(e) => {
                     output_error(&e);
                     std::process::exit(1);
                 }
-            };
-            let keypair = match ghostly::keypair_from_seed(&seed) {
-                Ok(k) => k,
+            }
+        }
+
+        Commands::Recv {
+            seed,
+            peer,
+            key,
+            if_newer_than,
+        } => {
+            let client = publishing_client(cli.padding);
+            match client
+                .recv_if_newer(seed.as_ref(), &peer, &key, if_newer_than)
+                .await
+            {
+                Ok(result) => match auth_failure(&result.record_status, cli.strict) {
+                    Some(e) if cli.strict => fail(&e),
+                    _ => output_json(&result),
+                },
                 Err(e) => {
                     output_error(&e);
                     std::process::exit(1);
                 }
+            }
+        }
+
+        Commands::Inspect { seed, peer, key } => {
+            let client = GhostClient::new();
+            match client.inspect(seed.as_ref(), &peer, key.as_ref()).await {
+                Ok(result) => output_json(&result),
+                Err(e) => fail(&e),
+            }
+        }
+
+        Commands::Watch {
+            seed,
+            peer,
+            key,
+            nick,
+            sessions,
+            all,
+            concurrency,
+            poll_interval,
+            max_poll_interval,
+            heartbeat,
+            ack,
+        } => {
+            let targets: Vec<WatchTarget> = match (seed, peer, key) {
+                (Some(seed), Some(peer), Some(key)) => vec![WatchTarget {
+                    session: None,
+                    seed: Some(seed),
+                    peer,
+                    key,
+                    nick,
+                    rotation: None,
+                    migration: None,
+                }],
+                _ if all => open_store()
+                    .sessions()
+                    .iter()
+                    .cloned()
+                    .map(Into::into)
+                    .collect(),
+                _ if !sessions.is_empty() => open_store()
+                    .select(&sessions)
+                    .unwrap_or_else(|e| fail(&e))
+                    .into_iter()
+                    .map(Into::into)
+                    .collect(),
+                _ => fail("Provide --seed/--peer/--key, --sessions or --all"),
             };
 
-            loop {
-                match client.recv(&peer, &key).await {
-                    Ok(batch) => {
-                        let new_messages: Vec<_> = batch
-                            .messages
-                            .into_iter()
-                            .filter(|m| m.timestamp > last_seen_ts)
-                            .collect();
-
-                        for msg in &new_messages {
-                            let event = WatchEvent {
-                                from: "peer".to_string(),
-                                text: msg.text.clone(),
-                                timestamp: msg.timestamp,
-                                nick: msg.nick.clone(),
-                            };
-                            output_json(&event);
-                            io::stdout().flush().ok();
-
-                            if msg.timestamp > last_seen_ts {
-                                last_seen_ts = msg.timestamp;
-                            }
-                        }
-
-                        if ack && !new_messages.is_empty() {
-                            let pkarr_client = pkarr::Client::builder()
-                                .build()
-                                .expect("Failed to create client");
-                            let _ = ghostly::publish_messages(
-                                &pkarr_client,
-                                &keypair,
-                                &[],
-                                &key_bytes,
-                                last_seen_ts,
-                                nick.as_deref(),
-                            )
-                            .await;
-                        }
+            if targets.is_empty() {
+                fail("No sessions to watch");
+            }
+
+            let options = WatchOptions {
+                poll_interval,
+                max_poll_interval,
+                heartbeat,
+                ack,
+                concurrency,
+                strict: cli.strict,
+            };
+            stream_watch(
+                publishing_client(cli.padding),
+                targets,
+                options,
+                cli.quiet,
+                cli.strict,
+            )
+            .await;
+        }
+
+        Commands::Session { action } => match action {
+            SessionAction::Add {
+                name,
+                seed,
+                peer,
+                key,
+                nick,
+            } => {
+                let mut store = open_store();
+                let verified = match store.get(&name) {
+                    Some(existing) if existing.peer != peer => {
+                        warn_peer_changed(&name, &peer);
+                        false
                     }
-                    Err(e) => {
-                        if !cli.quiet {
-                            output_error(&e);
-                        }
+                    Some(existing) => existing.verified,
+                    None => false,
+                };
+                store.upsert(Session {
+                    name: name.clone(),
+                    seed,
+                    peer: peer.clone(),
+                    key,
+                    nick: nick.clone(),
+                    rotation: None,
+                    migration: None,
+                    verified,
+                });
+                store.save().unwrap_or_else(|e| fail(&e));
+                output_json(&SessionInfo {
+                    name,
+                    peer,
+                    nick,
+                    verified,
+                });
+            }
+            SessionAction::List => {
+                let infos: Vec<SessionInfo> = open_store()
+                    .sessions()
+                    .iter()
+                    .map(|s| SessionInfo {
+                        name: s.name.clone(),
+                        peer: s.peer.clone(),
+                        nick: s.nick.clone(),
+                        verified: s.verified,
+                    })
+                    .collect();
+                output_json(&infos);
+            }
+            SessionAction::Fingerprint { name, verify } => {
+                let mut store = open_store();
+                let mut session = store
+                    .get(&name)
+                    .cloned()
+                    .unwrap_or_else(|| fail(&format!("Unknown session: {}", name)));
+                let my_pubkey = pubkey_from_seed(&session.seed);
+                let (digits, emoji) = safety_number(&session.key, &my_pubkey, &session.peer);
+                if let Some(claimed) = verify {
+                    let normalize = |s: &str| s.split_whitespace().collect::<String>();
+                    if normalize(&claimed) != normalize(&digits) {
+                        fail("Safety number does not match; session not verified");
                     }
+                    if !session.verified {
+                        session.verified = true;
+                        store.upsert(session.clone());
+                        store.save().unwrap_or_else(|e| fail(&e));
+                    }
+                }
+                output_json(&FingerprintOutput {
+                    session: session.name,
+                    my_pubkey,
+                    peer: session.peer,
+                    digits,
+                    emoji,
+                    verified: session.verified,
+                });
+            }
+            SessionAction::RotateKey { name } => {
+                let mut store = open_store();
+                let mut session = store
+                    .get(&name)
+                    .cloned()
+                    .unwrap_or_else(|| fail(&format!("Unknown session: {}", name)));
+                let client = publishing_client(cli.padding);
+                let output = client
+                    .rotate_key(&mut session)
+                    .await
+                    .unwrap_or_else(|e| fail(&e));
+                store.upsert(session);
+                store.save().unwrap_or_else(|e| fail(&e));
+                output_json(&output);
+            }
+            SessionAction::Migrate { name } => {
+                let mut store = open_store();
+                let mut session = store
+                    .get(&name)
+                    .cloned()
+                    .unwrap_or_else(|| fail(&format!("Unknown session: {}", name)));
+                let client = publishing_client(cli.padding);
+                let output = client
+                    .migrate_identity(&mut session)
+                    .await
+                    .unwrap_or_else(|e| fail(&e));
+                store.upsert(session);
+                store.save().unwrap_or_else(|e| fail(&e));
+                output_json(&output);
+            }
+            SessionAction::Remove { name } => {
+                let mut store = open_store();
+                if !store.remove(&name) {
+                    fail(&format!("Unknown session: {}", name));
                 }
+                store.save().unwrap_or_else(|e| fail(&e));
+                output_json(&serde_json::json!({ "ok": true, "removed": name }));
+            }
+        },
 
-                tokio::time::sleep(tokio::time::Duration::from_millis(poll_interval)).await;
+        Commands::Group { action } => match action {
+            GroupAction::Create { name, nick } => {
+                let mut store = open_store();
+                if store.group(&name).is_some() {
+                    fail(&format!("Group already exists: {}", name));
+                }
+                let (_, seed, pubkey) = ghostly::create_keypair();
+                let group = Group {
+                    name,
+                    seed,
+                    key: ghostly::generate_key(),
+                    nick,
+                    members: vec![pubkey],
+                    invited: Vec::new(),
+                    pending_joins: Vec::new(),
+                };
+                output_json(&group_info(&group));
+                store.upsert_group(group);
+                store.save().unwrap_or_else(|e| fail(&e));
             }
-        }
+            GroupAction::Invite { name } => {
+                let store = open_store();
+                let mut group = open_group(&store, &name);
+                output_json(&new_group_invite(&mut group));
+                save_group(store, group);
+            }
+            GroupAction::Join { name, url, nick } => {
+                let store = open_store();
+                if store.group(&name).is_some() {
+                    fail(&format!("Group already exists: {}", name));
+                }
+                let (mut group, token) =
+                    parse_group_invite(&name, &url).unwrap_or_else(|e| fail(&e));
+                group.nick = nick;
+
+                let client = publishing_client(cli.padding);
+                client
+                    .group_join(&mut group, &token)
+                    .await
+                    .unwrap_or_else(|e| fail(&e));
+
+                output_json(&group_info(&group));
+                save_group(store, group);
+            }
+            GroupAction::Leave { name } => {
+                let mut store = open_store();
+                let mut group = open_group(&store, &name);
+                let client = publishing_client(cli.padding);
+                client
+                    .group_leave(&mut group)
+                    .await
+                    .unwrap_or_else(|e| fail(&e));
+                store.remove_group(&name);
+                store.save().unwrap_or_else(|e| fail(&e));
+                output_json(&serde_json::json!({ "ok": true, "left": name }));
+            }
+            GroupAction::Members { name } => {
+                let store = open_store();
+                let mut group = open_group(&store, &name);
+                let client = publishing_client(cli.padding);
+                let members = client
+                    .group_members(&mut group)
+                    .await
+                    .unwrap_or_else(|e| fail(&e));
+                output_json(&members);
+                save_group(store, group);
+            }
+            GroupAction::List => {
+                let infos: Vec<GroupInfo> = open_store().groups().iter().map(group_info).collect();
+                output_json(&infos);
+            }
+            GroupAction::Send { name, message } => {
+                if message.is_empty() {
+                    fail("Message cannot be empty");
+                }
+                let store = open_store();
+                let mut group = open_group(&store, &name);
+                let client = publishing_client(cli.padding);
+                let (kept, sent) = client
+                    .group_send(&mut group, &message)
+                    .await
+                    .unwrap_or_else(|e| fail(&e));
+                output_json(&SendOutput {
+                    ok: true,
+                    timestamp: sent.t,
+                    id: sent.i.unwrap_or_default(),
+                    seq: sent.s.unwrap_or_default(),
+                    messages_kept: kept,
+                });
+                save_group(store, group);
+            }
+            GroupAction::Recv { name, no_ack } => {
+                let store = open_store();
+                let mut group = open_group(&store, &name);
+                let client = publishing_client(cli.padding);
+                let output = client
+                    .group_recv(&mut group, !no_ack)
+                    .await
+                    .unwrap_or_else(|e| fail(&e));
+                output_json(&output);
+                save_group(store, group);
+            }
+        },
+
+        Commands::Profile { action } => match action {
+            ProfileAction::Set {
+                name,
+                status,
+                blurhash,
+                avatar,
+            } => {
+                let mut store = open_store();
+                let current = store.profile().cloned();
+                let mut display_name = current.as_ref().and_then(|p| p.display_name.clone());
+                let mut status_text = current.as_ref().and_then(|p| p.status.clone());
+                let mut avatar_blurhash = current.as_ref().and_then(|p| p.avatar_blurhash.clone());
+                update_field(&mut display_name, name);
+                update_field(&mut status_text, status);
+                update_field(&mut avatar_blurhash, blurhash);
+                let avatar = match avatar {
+                    Some(json) if json.is_empty() => None,
+                    Some(json) => Some(
+                        serde_json::from_str::<Attachment>(&json).unwrap_or_else(|e| {
+                            fail(&format!("Invalid attachment manifest: {}", e))
+                        }),
+                    ),
+                    None => current.and_then(|p| p.avatar),
+                };
+                let profile = PeerProfile::new(display_name, status_text, avatar_blurhash, avatar)
+                    .unwrap_or_else(|e| fail(&e));
+                store.set_profile(Some(profile.clone()));
+                store.save().unwrap_or_else(|e| fail(&e));
+                output_json(&profile);
+            }
+            ProfileAction::Show => {
+                output_json(&open_store().profile());
+            }
+            ProfileAction::Clear => {
+                let mut store = open_store();
+                store.set_profile(None);
+                store.save().unwrap_or_else(|e| fail(&e));
+                output_json(&serde_json::json!({ "ok": true }));
+            }
+        },
+
+        Commands::Channel { action } => match action {
+            ChannelAction::Create { name, window } => {
+                let mut store = open_store();
+                if store.channel(&name).is_some() {
+                    fail(&format!("Channel already exists: {}", name));
+                }
+                let channel = ghostly::new_channel(&name, window);
+                output_json(&ghostly::channel_info(&channel));
+                store.upsert_channel(channel);
+                store.save().unwrap_or_else(|e| fail(&e));
+            }
+            ChannelAction::Post {
+                name,
+                stdin,
+                message,
+            } => {
+                let msg = read_message(stdin, message);
+                let mut store = open_store();
+                let mut channel = store
+                    .channel(&name)
+                    .cloned()
+                    .unwrap_or_else(|| fail(&format!("Unknown channel: {}", name)));
+                let client = publishing_client(cli.padding);
+                let (kept, post) = client
+                    .channel_post(&mut channel, &msg)
+                    .await
+                    .unwrap_or_else(|e| fail(&e));
+                store.upsert_channel(channel);
+                store.save().unwrap_or_else(|e| fail(&e));
+                output_json(&SendOutput {
+                    ok: true,
+                    timestamp: post.t,
+                    id: post.i.unwrap_or_default(),
+                    seq: post.s.unwrap_or_default(),
+                    messages_kept: kept,
+                });
+            }
+            ChannelAction::Follow {
+                channel,
+                save,
+                poll_interval,
+                max_poll_interval,
+            } => {
+                let mut store = open_store();
+                let channel = if channel.starts_with("ghost-channel://") {
+                    let name = save.clone().unwrap_or_else(|| "channel".to_string());
+                    ghostly::parse_channel_url(&name, &channel).unwrap_or_else(|e| fail(&e))
+                } else {
+                    store
+                        .channel(&channel)
+                        .cloned()
+                        .unwrap_or_else(|| fail(&format!("Unknown channel: {}", channel)))
+                };
+                if save.is_some() {
+                    store.upsert_channel(channel.clone());
+                    store.save().unwrap_or_else(|e| fail(&e));
+                }
+
+                let options = WatchOptions {
+                    poll_interval,
+                    max_poll_interval,
+                    heartbeat: None,
+                    ack: false,
+                    concurrency: 1,
+                    strict: cli.strict,
+                };
+                stream_watch(
+                    publishing_client(cli.padding),
+                    vec![(&channel).into()],
+                    options,
+                    cli.quiet,
+                    cli.strict,
+                )
+                .await;
+            }
+            ChannelAction::List => {
+                let infos: Vec<_> = open_store()
+                    .channels()
+                    .iter()
+                    .map(ghostly::channel_info)
+                    .collect();
+                output_json(&infos);
+            }
+        },
     }
 }
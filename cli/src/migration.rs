@@ -0,0 +1,202 @@
+use pkarr::{Client, Keypair, PublicKey, SignedPacket};
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{from_base64_url, to_base64_url, PayloadKey, SharedKey};
+use crate::pkarr::{
+    ack_for, add_txt, create_keypair, keypair_from_seed, open_record, outbox_from, peer_ack,
+    pubkey_from_seed, publish_messages, resolve_messages, seal_record, unacked_messages,
+};
+use crate::rotation::conversation_keys;
+use crate::types::{
+    MigrateOutput, PendingMigration, PendingRotation, Presence, RecordStatus, ResolvedBatch,
+    Session,
+};
+use crate::GhostClient;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MovedRecord {
+    p: String,
+    t: i64,
+    s: String,
+}
+
+fn moved_statement(old_z32: &str, new_z32: &str, moved_at: i64) -> Vec<u8> {
+    format!("ghostly/moved/{}/{}/{}", old_z32, new_z32, moved_at).into_bytes()
+}
+
+/// Opens a `_moved` record, returning the new pubkey only when the new key
+/// signed off on the move (the old key signs the packet itself).
+pub(crate) fn open_moved(
+    encoded: &str,
+    key: &PayloadKey,
+    sender_z32: &str,
+) -> Result<String, RecordStatus> {
+    let decrypted =
+        open_record("_moved", encoded, key, sender_z32).map_err(|_| RecordStatus::AuthFailed)?;
+    if !decrypted.bound {
+        return Err(RecordStatus::AuthFailed);
+    }
+    let record: MovedRecord =
+        serde_json::from_str(&decrypted.plaintext).map_err(|_| RecordStatus::Malformed)?;
+    let new_key: PublicKey = record
+        .p
+        .as_str()
+        .try_into()
+        .map_err(|_| RecordStatus::Malformed)?;
+    let signature = from_base64_url(&record.s).map_err(|_| RecordStatus::Malformed)?;
+    let signature = signature
+        .as_slice()
+        .try_into()
+        .map_err(|_| RecordStatus::Malformed)?;
+    new_key
+        .verify(
+            &moved_statement(sender_z32, &record.p, record.t),
+            &signature,
+        )
+        .map_err(|_| RecordStatus::AuthFailed)?;
+    Ok(record.p)
+}
+
+/// Replaces the packet under `old` with a single `_moved` record naming `new`.
+pub async fn publish_moved(
+    client: &Client,
+    old: &Keypair,
+    new: &Keypair,
+    key: &PayloadKey,
+    moved_at: i64,
+) -> Result<(), String> {
+    let old_z32 = old.to_z32();
+    let new_z32 = new.to_z32();
+    let signature = new.sign(&moved_statement(&old_z32, &new_z32, moved_at));
+    let record = MovedRecord {
+        p: new_z32,
+        t: moved_at,
+        s: to_base64_url(&signature.to_bytes()),
+    };
+    let json = serde_json::to_string(&record).map_err(|e| format!("JSON serialize: {}", e))?;
+
+    let builder = add_txt(SignedPacket::builder(), "_ts", &moved_at.to_string())?;
+    let builder = add_txt(
+        builder,
+        "_moved",
+        &seal_record("_moved", &json, key, &old_z32)?,
+    )?;
+    let signed_packet = builder
+        .sign(old)
+        .map_err(|e| format!("Sign error: {}", e))?;
+
+    client
+        .publish(&signed_packet, None)
+        .await
+        .map_err(|e| format!("Publish error: {}", e))
+}
+
+/// Like [`conversation_keys`], but while a migration is pending the receive
+/// key also opens payloads the peer still addresses to our old pubkey.
+pub fn session_keys(
+    key: &SharedKey,
+    rotation: Option<&PendingRotation>,
+    migration: Option<&PendingMigration>,
+    my_z32: &str,
+    peer_z32: &str,
+) -> (PayloadKey, PayloadKey) {
+    let (send_key, recv_key) = conversation_keys(key, rotation, my_z32, peer_z32);
+    match migration {
+        Some(migration) => {
+            let old_z32 = pubkey_from_seed(&migration.old_seed);
+            let (_, old_recv_key) = conversation_keys(key, rotation, &old_z32, peer_z32);
+            (send_key, recv_key.with_fallback(old_recv_key))
+        }
+        None => (send_key, recv_key),
+    }
+}
+
+/// Follows a peer that moved to a new pubkey, and completes our own pending
+/// migration once the peer addresses the new identity. Returns the watch
+/// event name for whatever changed.
+pub fn apply_migration(
+    peer: &mut String,
+    migration: &mut Option<PendingMigration>,
+    batch: &ResolvedBatch,
+) -> Option<&'static str> {
+    if let Some(moved_to) = &batch.moved_to {
+        if moved_to != peer {
+            *peer = moved_to.clone();
+            return Some("peer_moved");
+        }
+    }
+
+    if migration.is_some() && batch.sender_authenticated && !batch.fallback_key_used {
+        *migration = None;
+        return Some("migration_complete");
+    }
+
+    None
+}
+
+impl GhostClient {
+    /// Moves the session to a fresh keypair: the outbox is republished under
+    /// the new pubkey and the old one is left pointing at it.
+    pub async fn migrate_identity(&self, session: &mut Session) -> Result<MigrateOutput, String> {
+        if session.rotation.is_some() || session.migration.is_some() {
+            return Err(format!(
+                "Key rotation or migration still pending for session: {}",
+                session.name
+            ));
+        }
+
+        let old = keypair_from_seed(&session.seed);
+        let old_z32 = old.to_z32();
+        let (old_send_key, recv_key) =
+            conversation_keys(&session.key, None, &old_z32, &session.peer);
+
+        let peer_batch = resolve_messages(&self.client, &session.peer, &recv_key).await?;
+        let own_batch = resolve_messages(&self.client, &old_z32, &old_send_key).await?;
+
+        let mut outbox = own_batch.as_ref().map(outbox_from).unwrap_or_default();
+        if let Some(peer_batch) = &peer_batch {
+            outbox = unacked_messages(&outbox, &peer_ack(peer_batch));
+        }
+        let last_seq = own_batch.map(|b| b.last_seq).unwrap_or(0);
+        let ack = peer_batch.as_ref().map(ack_for).unwrap_or_default();
+
+        let moved_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| format!("Time error: {}", e))?
+            .as_millis() as i64;
+        let (new, new_seed, new_z32) = create_keypair();
+        let (new_send_key, _) = conversation_keys(&session.key, None, &new_z32, &session.peer);
+
+        let kept = publish_messages(
+            &self.client,
+            &new,
+            &outbox,
+            &new_send_key,
+            last_seq,
+            &ack,
+            session.nick.as_deref(),
+            Some(&Presence {
+                last_active: moved_at,
+                typing: false,
+            }),
+            self.profile.as_ref(),
+            self.padding,
+        )
+        .await?;
+        publish_moved(&self.client, &old, &new, &old_send_key, moved_at).await?;
+
+        session.migration = Some(PendingMigration {
+            old_seed: std::mem::replace(&mut session.seed, new_seed),
+            moved_at,
+        });
+
+        Ok(MigrateOutput {
+            ok: true,
+            session: session.name.clone(),
+            old_pubkey: old_z32,
+            new_pubkey: new_z32,
+            moved_at,
+            messages_kept: kept,
+        })
+    }
+}
@@ -1,26 +1,112 @@
-use pkarr::{Client, Keypair, PublicKey, SignedPacket};
+use pkarr::{Client, Keypair, PublicKey, SignedPacket, SignedPacketBuilder};
 use simple_dns::rdata::RData;
 
-use crate::crypto;
-use crate::types::{CompactMessage, PkarrMessage, ResolvedBatch};
+use crate::crypto::{self, PayloadKey, SecretSeed};
+use crate::migration;
+use crate::profile::{open_profile, seal_profile};
+use crate::types::{
+    Ack, CompactMessage, Padding, PeerCapabilities, PeerProfile, PkarrMessage, Presence,
+    RecordStatus, ResolvedBatch,
+};
+use std::collections::BTreeMap;
+
+pub(crate) const MAX_MSGS_PAYLOAD_B64: usize = 800;
+const CLOCK_SKEW_TOLERANCE_MS: i64 = 30_000;
+pub(crate) const PACKET_BUDGET: usize = 920;
+pub(crate) const RECORD_OVERHEAD: usize = 20;
+const MIN_PAD_BUCKET: usize = 32;
+pub(crate) const NICK_PAD_LENGTH: usize = 32;
+const PRESENCE_PAD_LENGTH: usize = 32;
+
+/// Largest plaintext whose sealed, base64-encoded form fits in `max_payload`.
+fn max_plaintext(max_payload: usize) -> usize {
+    (max_payload / 4 * 3).saturating_sub(crypto::SEAL_OVERHEAD)
+}
+
+/// Pads with trailing spaces, which JSON parsers ignore and nicks are trimmed of.
+pub(crate) fn pad(plaintext: &str, padding: Padding, max_len: usize) -> String {
+    let target = match padding {
+        Padding::Off => 0,
+        Padding::Bucketed => plaintext
+            .len()
+            .max(MIN_PAD_BUCKET)
+            .next_power_of_two()
+            .min(max_len),
+        Padding::Full => max_len,
+    };
+    let mut padded = plaintext.to_string();
+    padded.extend(std::iter::repeat_n(
+        ' ',
+        target.saturating_sub(plaintext.len()),
+    ));
+    padded
+}
+
+pub(crate) fn seal_record(
+    label: &str,
+    plaintext: &str,
+    key: &PayloadKey,
+    sender_z32: &str,
+) -> Result<String, String> {
+    key.encrypt(plaintext, &crypto::record_ad(label, sender_z32))
+}
+
+pub(crate) fn open_record(
+    label: &str,
+    encoded: &str,
+    key: &PayloadKey,
+    sender_z32: &str,
+) -> Result<crypto::Decrypted, String> {
+    key.decrypt(encoded, &crypto::record_ad(label, sender_z32))
+}
 
-const MAX_MSGS_PAYLOAD_B64: usize = 800;
+/// Like [`open_record`] for records pre-v1 clients published without
+/// associated data (`_msgs`, `_nick`, `_call`). The unbound form is only
+/// accepted from `legacy_peer`s, which predate `_v`.
+pub(crate) fn open_legacy_record(
+    label: &str,
+    encoded: &str,
+    key: &PayloadKey,
+    sender_z32: &str,
+    legacy_peer: bool,
+) -> Result<crypto::Decrypted, RecordStatus> {
+    let ad = crypto::record_ad(label, sender_z32);
+    let opened = if legacy_peer {
+        key.decrypt_allowing_unbound(encoded, &ad)
+    } else {
+        key.decrypt(encoded, &ad)
+    };
+    opened.map_err(|_| RecordStatus::AuthFailed)
+}
+
+/// `LegacyUnbound` for payloads let through by the pre-v1 fallback.
+fn opened_status(bound: bool) -> RecordStatus {
+    if bound {
+        RecordStatus::Ok
+    } else {
+        RecordStatus::LegacyUnbound
+    }
+}
 
-fn trim_to_fit(
+pub(crate) fn trim_to_fit(
     messages: &[CompactMessage],
-    enc_key: &[u8],
+    key: &PayloadKey,
+    sender_z32: &str,
     max_payload: usize,
+    padding: Padding,
 ) -> Result<(String, usize), String> {
+    let max_len = max_plaintext(max_payload);
+    let seal = |json: &str| seal_record("_msgs", &pad(json, padding, max_len), key, sender_z32);
+
     if messages.is_empty() {
-        let encrypted = crypto::encrypt("[]", enc_key)?;
-        return Ok((encrypted, 0));
+        return Ok((seal("[]")?, 0));
     }
 
     let mut batch: Vec<&CompactMessage> = messages.iter().collect();
 
     while !batch.is_empty() {
         let json = serde_json::to_string(&batch).map_err(|e| format!("JSON serialize: {}", e))?;
-        let encrypted = crypto::encrypt(&json, enc_key)?;
+        let encrypted = seal(&json)?;
         if encrypted.len() <= max_payload {
             return Ok((encrypted, batch.len()));
         }
@@ -30,84 +116,88 @@ fn trim_to_fit(
             let truncated = CompactMessage {
                 t: msg.t,
                 m: max_text,
+                i: msg.i.clone(),
+                s: msg.s,
+                r: msg.r.clone(),
+                a: msg.a.clone(),
+                k: msg.k.clone(),
             };
             let json =
                 serde_json::to_string(&vec![&truncated]).map_err(|e| format!("JSON: {}", e))?;
-            let encrypted = crypto::encrypt(&json, enc_key)?;
-            return Ok((encrypted, 1));
+            return Ok((seal(&json)?, 1));
         }
         batch.remove(0);
     }
 
-    let encrypted = crypto::encrypt("[]", enc_key)?;
-    Ok((encrypted, 0))
+    Ok((seal("[]")?, 0))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn publish_messages(
     client: &Client,
     keypair: &Keypair,
     messages: &[CompactMessage],
-    enc_key: &[u8],
-    ack_timestamp: i64,
+    key: &PayloadKey,
+    last_seq: u64,
+    ack: &Ack,
     nick: Option<&str>,
+    presence: Option<&Presence>,
+    profile: Option<&PeerProfile>,
+    padding: Padding,
 ) -> Result<usize, String> {
     let mut sorted = messages.to_vec();
-    sorted.sort_by_key(|m| m.t);
+    sorted.sort_by_key(|m| (m.s.is_some(), m.s, m.t));
 
-    let (payload, kept) = trim_to_fit(&sorted, enc_key, MAX_MSGS_PAYLOAD_B64)?;
+    let sender_z32 = keypair.to_z32();
+    let mut records: Vec<(&str, String)> = Vec::new();
 
-    let mut builder = SignedPacket::builder();
+    let latest_ts = sorted.last().map(|m| m.t).unwrap_or(0);
+    records.push(("_ts", latest_ts.to_string()));
+    records.push(("_v", PeerCapabilities::ours().to_record()));
+    if last_seq > 0 {
+        records.push(("_seq", last_seq.to_string()));
+    }
+    if ack.ts > 0 {
+        records.push(("_ack", ack.ts.to_string()));
+    }
+    if let Some(id) = &ack.id {
+        records.push(("_acki", id.clone()));
+    }
+    if let Some(seq) = ack.seq {
+        records.push(("_aseq", seq.to_string()));
+    }
+    if let Some(nick_str) = nick {
+        let padded = pad(nick_str, padding, NICK_PAD_LENGTH);
+        records.push(("_nick", seal_record("_nick", &padded, key, &sender_z32)?));
+    }
+    if let Some(presence) = presence {
+        let json = serde_json::to_string(presence).map_err(|e| format!("JSON: {}", e))?;
+        let padded = pad(&json, padding, PRESENCE_PAD_LENGTH);
+        records.push(("_pres", seal_record("_pres", &padded, key, &sender_z32)?));
+    }
 
-    builder = builder.txt(
-        "_msgs"
-            .try_into()
-            .map_err(|e| format!("Name error: {}", e))?,
-        payload
-            .as_str()
-            .try_into()
-            .map_err(|e| format!("TXT error: {}", e))?,
-        300,
-    );
-
-    let latest_ts = sorted
-        .last()
-        .map(|m| m.t.to_string())
-        .unwrap_or_else(|| "0".to_string());
-    builder = builder.txt(
-        "_ts".try_into().map_err(|e| format!("Name error: {}", e))?,
-        latest_ts
-            .as_str()
-            .try_into()
-            .map_err(|e| format!("TXT error: {}", e))?,
-        300,
-    );
-
-    if ack_timestamp > 0 {
-        let ack_str = ack_timestamp.to_string();
-        builder = builder.txt(
-            "_ack"
-                .try_into()
-                .map_err(|e| format!("Name error: {}", e))?,
-            ack_str
-                .as_str()
-                .try_into()
-                .map_err(|e| format!("TXT error: {}", e))?,
-            300,
-        );
+    let used: usize = records
+        .iter()
+        .map(|(name, value)| name.len() + value.len() + RECORD_OVERHEAD)
+        .sum();
+    let max_payload = PACKET_BUDGET
+        .saturating_sub(used + "_msgs".len() + RECORD_OVERHEAD)
+        .min(MAX_MSGS_PAYLOAD_B64);
+    let (payload, kept) = trim_to_fit(&sorted, key, &sender_z32, max_payload, padding)?;
+
+    // Messages come first: the profile only rides along when they leave room
+    // for it, and is back on the next publish once the outbox drains.
+    if let Some(profile) = profile {
+        let sealed = seal_profile(profile, key, &sender_z32, padding)?;
+        let total = used + "_msgs".len() + payload.len() + RECORD_OVERHEAD;
+        if total + "_prof".len() + sealed.len() + RECORD_OVERHEAD <= PACKET_BUDGET {
+            records.push(("_prof", sealed));
+        }
     }
 
-    if let Some(nick_str) = nick {
-        let encrypted_nick = crypto::encrypt(nick_str, enc_key)?;
-        builder = builder.txt(
-            "_nick"
-                .try_into()
-                .map_err(|e| format!("Name error: {}", e))?,
-            encrypted_nick
-                .as_str()
-                .try_into()
-                .map_err(|e| format!("TXT error: {}", e))?,
-            300,
-        );
+    let mut builder = add_txt(SignedPacket::builder(), "_msgs", &payload)?;
+    for (name, value) in &records {
+        builder = add_txt(builder, name, value)?;
     }
 
     let signed_packet = builder
@@ -122,10 +212,78 @@ pub async fn publish_messages(
     Ok(kept)
 }
 
+pub fn unacked_messages(outbox: &[CompactMessage], ack: &Ack) -> Vec<CompactMessage> {
+    if let Some(seq) = ack.seq {
+        return outbox
+            .iter()
+            .filter(|m| m.s.map_or(m.t > ack.ts, |s| s > seq))
+            .cloned()
+            .collect();
+    }
+    if let Some(id) = &ack.id {
+        if let Some(pos) = outbox.iter().position(|m| m.i.as_ref() == Some(id)) {
+            return outbox[pos + 1..].to_vec();
+        }
+    }
+    outbox.iter().filter(|m| m.t > ack.ts).cloned().collect()
+}
+
+pub fn peer_ack(batch: &ResolvedBatch) -> Ack {
+    Ack {
+        ts: batch.peer_ack,
+        id: batch.peer_ack_id.clone(),
+        seq: batch.peer_ack_seq,
+    }
+}
+
+/// Last sequence number we used. Our own packet can expire from the DHT, but
+/// the peer's `_aseq` still covers what it read; restarting below that would
+/// get new messages pruned as already acked.
+pub fn last_sent_seq(own_batch: Option<&ResolvedBatch>, peer_batch: Option<&ResolvedBatch>) -> u64 {
+    let own = own_batch.map_or(0, |b| b.last_seq);
+    let acked = peer_batch.and_then(|b| b.peer_ack_seq).unwrap_or(0);
+    own.max(acked)
+}
+
+pub fn ack_for(batch: &ResolvedBatch) -> Ack {
+    Ack {
+        ts: batch.latest_timestamp,
+        id: batch.messages.last().and_then(|m| m.id.clone()),
+        seq: batch.messages.iter().filter_map(|m| m.seq).max(),
+    }
+}
+
+pub fn outbox_from(batch: &ResolvedBatch) -> Vec<CompactMessage> {
+    batch
+        .messages
+        .iter()
+        .map(|m| CompactMessage {
+            t: m.timestamp,
+            m: m.text.clone(),
+            i: m.id.clone(),
+            s: m.seq,
+            r: m.rotation.clone(),
+            a: m.attachment.clone(),
+            k: m.kind.clone(),
+        })
+        .collect()
+}
+
 pub async fn resolve_messages(
     client: &Client,
     public_key_z32: &str,
-    enc_key: &[u8],
+    key: &PayloadKey,
+) -> Result<Option<ResolvedBatch>, String> {
+    resolve_messages_if_newer(client, public_key_z32, key, 0).await
+}
+
+/// Like [`resolve_messages`], but returns `Ok(None)` without decrypting anything
+/// when the packet timestamp (ms) is not greater than `if_newer_than`.
+pub async fn resolve_messages_if_newer(
+    client: &Client,
+    public_key_z32: &str,
+    key: &PayloadKey,
+    if_newer_than: i64,
 ) -> Result<Option<ResolvedBatch>, String> {
     let public_key: PublicKey = public_key_z32
         .try_into()
@@ -138,19 +296,66 @@ pub async fn resolve_messages(
         None => return Ok(None),
     };
 
-    let packet_timestamp = signed_packet.timestamp().as_u64() as i64 / 1000;
+    if packet_timestamp_ms(&signed_packet) <= if_newer_than {
+        return Ok(None);
+    }
+
+    Ok(Some(decode_packet(&signed_packet, key)))
+}
+
+pub(crate) fn add_txt(
+    builder: SignedPacketBuilder,
+    name: &str,
+    value: &str,
+) -> Result<SignedPacketBuilder, String> {
+    Ok(builder.txt(
+        name.try_into().map_err(|e| format!("Name error: {}", e))?,
+        value.try_into().map_err(|e| format!("TXT error: {}", e))?,
+        300,
+    ))
+}
+
+pub fn txt_value(signed_packet: &SignedPacket, label: &str) -> Option<String> {
+    signed_packet.all_resource_records().find_map(|record| {
+        let name_str = record.name.to_string();
+        let record_label = name_str.trim_end_matches('.').split('.').next()?;
+        match &record.rdata {
+            RData::TXT(txt) if record_label == label => String::try_from(txt.clone()).ok(),
+            _ => None,
+        }
+    })
+}
+
+pub fn packet_timestamp_ms(signed_packet: &SignedPacket) -> i64 {
+    signed_packet.timestamp().as_u64() as i64 / 1000
+}
+
+pub fn decode_packet(signed_packet: &SignedPacket, key: &PayloadKey) -> ResolvedBatch {
+    let packet_timestamp = packet_timestamp_ms(signed_packet);
 
     let mut nick: Option<String> = None;
     let mut encrypted_payload_length: usize = 0;
+    let mut payload_padding: usize = 0;
+    let mut fallback_key_used = false;
+    let mut moved_to: Option<String> = None;
     let mut latest_timestamp: i64 = 0;
     let mut peer_ack: i64 = 0;
+    let mut peer_ack_id: Option<String> = None;
+    let mut peer_ack_seq: Option<u64> = None;
+    let mut last_seq: u64 = 0;
     let mut raw_record_names: Vec<String> = Vec::new();
     let mut messages: Vec<PkarrMessage> = Vec::new();
     let mut call_signal: Option<String> = None;
+    let mut presence: Option<Presence> = None;
+    let mut profile: Option<PeerProfile> = None;
+    let mut capabilities: Option<PeerCapabilities> = None;
+    let mut sender_authenticated = false;
+    let mut record_status: BTreeMap<String, RecordStatus> = BTreeMap::new();
+    let sender_z32 = signed_packet.public_key().to_z32();
+    let legacy_peer = txt_value(signed_packet, "_v").is_none();
 
-    let mut msgs_payload = String::new();
-    let mut legacy_msg = String::new();
-    let legacy_ts: i64 = 0;
+    let mut msgs_payload: Option<String> = None;
+    let mut legacy_payload: Option<String> = None;
 
     for record in signed_packet.all_resource_records() {
         let name_str = record.name.to_string();
@@ -167,14 +372,13 @@ pub async fn resolve_messages(
 
             match label {
                 "_msgs" => {
-                    encrypted_payload_length = value.len();
-                    msgs_payload = value;
+                    msgs_payload = Some(value);
                 }
                 "_msg" => {
-                    encrypted_payload_length = value.len();
-                    if let Ok(decrypted) = crypto::decrypt(&value, enc_key) {
-                        legacy_msg = decrypted;
-                    }
+                    legacy_payload = Some(value);
+                }
+                "_v" => {
+                    capabilities = PeerCapabilities::parse(&value);
                 }
                 "_ts" => {
                     latest_timestamp = value.parse::<i64>().unwrap_or(0);
@@ -182,87 +386,497 @@ pub async fn resolve_messages(
                 "_ack" => {
                     peer_ack = value.parse::<i64>().unwrap_or(0);
                 }
+                "_acki" => {
+                    peer_ack_id = Some(value);
+                }
+                "_aseq" => {
+                    peer_ack_seq = value.parse::<u64>().ok();
+                }
+                "_seq" => {
+                    last_seq = value.parse::<u64>().unwrap_or(0);
+                }
                 "_nick" => {
-                    if let Ok(decrypted) = crypto::decrypt(&value, enc_key) {
-                        nick = Some(decrypted);
-                    }
+                    let opened = open_legacy_record("_nick", &value, key, &sender_z32, legacy_peer);
+                    let ok = opened_status(opened.as_ref().is_ok_and(|d| d.bound));
+                    nick = opened
+                        .map(|d| d.plaintext.trim_end().to_string())
+                        .record_as(&mut record_status, label, ok);
                 }
                 "_call" => {
-                    if let Ok(decrypted) = crypto::decrypt(&value, enc_key) {
-                        call_signal = Some(decrypted);
-                    }
+                    let opened = open_legacy_record("_call", &value, key, &sender_z32, legacy_peer);
+                    let ok = opened_status(opened.as_ref().is_ok_and(|d| d.bound));
+                    call_signal =
+                        opened
+                            .map(|d| d.plaintext)
+                            .record_as(&mut record_status, label, ok);
+                }
+                "_pres" => {
+                    presence = open_record("_pres", &value, key, &sender_z32)
+                        .map_err(|_| RecordStatus::AuthFailed)
+                        .and_then(|d| {
+                            serde_json::from_str(&d.plaintext).map_err(|_| RecordStatus::Malformed)
+                        })
+                        .record(&mut record_status, label);
+                }
+                "_prof" => {
+                    profile =
+                        open_profile(&value, key, &sender_z32).record(&mut record_status, label);
+                }
+                "_moved" => {
+                    moved_to = migration::open_moved(&value, key, &sender_z32)
+                        .record(&mut record_status, label);
                 }
                 _ => {}
             }
         }
     }
 
-    if !msgs_payload.is_empty() {
-        if let Ok(json) = crypto::decrypt(&msgs_payload, enc_key) {
-            if let Ok(batch) = serde_json::from_str::<Vec<CompactMessage>>(&json) {
-                for entry in &batch {
-                    messages.push(PkarrMessage {
-                        text: entry.m.clone(),
-                        timestamp: entry.t,
-                        nick: nick.clone(),
-                    });
-                }
-                if latest_timestamp == 0 && !messages.is_empty() {
-                    latest_timestamp = messages.iter().map(|m| m.timestamp).max().unwrap_or(0);
-                }
+    // `_msgs` wins when a transitional client published both layouts.
+    let payload = match (msgs_payload, legacy_payload) {
+        (Some(encoded), _) => Some((PayloadVersion::Batch, encoded)),
+        (None, Some(encoded)) => Some((PayloadVersion::Legacy, encoded)),
+        (None, None) => None,
+    };
+    if let Some((version, encoded)) = payload {
+        encrypted_payload_length = encoded.len();
+        let decoded = match version {
+            PayloadVersion::Legacy if legacy_peer => {
+                decode_legacy(&encoded, key, latest_timestamp, packet_timestamp)
             }
+            // v0 is unbound by design; peers that advertise `_v` never send it.
+            PayloadVersion::Legacy => Err(RecordStatus::AuthFailed),
+            PayloadVersion::Batch => decode_batch(&encoded, key, &sender_z32, legacy_peer),
+        };
+        let ok = opened_status(decoded.as_ref().is_ok_and(|d| d.sender_authenticated));
+        if let Some(decoded) = decoded.record_as(&mut record_status, version.label(), ok) {
+            sender_authenticated = decoded.sender_authenticated;
+            fallback_key_used = decoded.fallback_key_used;
+            payload_padding = decoded.padding;
+            messages = decoded
+                .messages
+                .into_iter()
+                .map(|mut m| {
+                    m.nick = nick.clone();
+                    m
+                })
+                .collect();
         }
-    } else if !legacy_msg.is_empty() && legacy_ts > 0 {
-        messages.push(PkarrMessage {
-            text: legacy_msg,
-            timestamp: legacy_ts,
-            nick,
-        });
-        latest_timestamp = legacy_ts;
-    } else if !legacy_msg.is_empty() && latest_timestamp > 0 {
-        messages.push(PkarrMessage {
-            text: legacy_msg,
-            timestamp: latest_timestamp,
-            nick,
-        });
+    }
+    if latest_timestamp == 0 {
+        latest_timestamp = messages.iter().map(|m| m.timestamp).max().unwrap_or(0);
     }
 
     let message_count = messages.len();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0);
+    let clock_skew_ms = detect_clock_skew(latest_timestamp, packet_timestamp, now);
 
-    Ok(Some(ResolvedBatch {
+    ResolvedBatch {
         messages,
         latest_timestamp,
         peer_ack,
+        peer_ack_id,
+        peer_ack_seq,
+        last_seq,
+        clock_skew_ms,
+        sender_authenticated,
         raw_record_names,
         encrypted_payload_length,
+        payload_padding,
+        fallback_key_used,
+        moved_to,
         packet_timestamp,
         message_count,
         call_signal,
-    }))
+        presence,
+        profile,
+        capabilities,
+        record_status,
+    }
+}
+
+/// Message payload layouts, oldest first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PayloadVersion {
+    /// v0: one message in `_msg`, sealed without associated data, timestamped by `_ts`.
+    Legacy,
+    /// v1: a JSON batch of [`CompactMessage`]s in `_msgs`.
+    Batch,
+}
+
+impl PayloadVersion {
+    fn label(self) -> &'static str {
+        match self {
+            PayloadVersion::Legacy => "_msg",
+            PayloadVersion::Batch => "_msgs",
+        }
+    }
+}
+
+trait RecordOutcome<T> {
+    /// Notes the outcome under `label`, as `ok` on success, and keeps the
+    /// value on success.
+    fn record_as(
+        self,
+        status: &mut BTreeMap<String, RecordStatus>,
+        label: &str,
+        ok: RecordStatus,
+    ) -> Option<T>;
+
+    fn record(self, status: &mut BTreeMap<String, RecordStatus>, label: &str) -> Option<T>
+    where
+        Self: Sized,
+    {
+        self.record_as(status, label, RecordStatus::Ok)
+    }
+}
+
+impl<T> RecordOutcome<T> for Result<T, RecordStatus> {
+    fn record_as(
+        self,
+        status: &mut BTreeMap<String, RecordStatus>,
+        label: &str,
+        ok: RecordStatus,
+    ) -> Option<T> {
+        status.insert(label.to_string(), *self.as_ref().err().unwrap_or(&ok));
+        self.ok()
+    }
+}
+
+struct DecodedPayload {
+    messages: Vec<PkarrMessage>,
+    sender_authenticated: bool,
+    fallback_key_used: bool,
+    padding: usize,
+}
+
+/// Decodes a v0 `_msg`. Packets without `_ts` fall back to the packet's own
+/// timestamp, which the sender signed alongside the message.
+fn decode_legacy(
+    encoded: &str,
+    key: &PayloadKey,
+    ts: i64,
+    packet_timestamp: i64,
+) -> Result<DecodedPayload, RecordStatus> {
+    let text = crypto::decrypt(encoded, key.shared_key()).map_err(|_| RecordStatus::AuthFailed)?;
+    let timestamp = if ts > 0 { ts } else { packet_timestamp };
+    Ok(DecodedPayload {
+        messages: vec![PkarrMessage {
+            text,
+            timestamp,
+            nick: None,
+            id: None,
+            seq: None,
+            rotation: None,
+            attachment: None,
+            kind: None,
+        }],
+        sender_authenticated: false,
+        fallback_key_used: false,
+        padding: 0,
+    })
+}
+
+fn decode_batch(
+    encoded: &str,
+    key: &PayloadKey,
+    sender_z32: &str,
+    legacy_peer: bool,
+) -> Result<DecodedPayload, RecordStatus> {
+    let decrypted = open_legacy_record("_msgs", encoded, key, sender_z32, legacy_peer)?;
+    let json = decrypted.plaintext;
+    let padding = json.len() - json.trim_end().len();
+    let batch =
+        serde_json::from_str::<Vec<CompactMessage>>(&json).map_err(|_| RecordStatus::Malformed)?;
+    Ok(DecodedPayload {
+        messages: batch
+            .into_iter()
+            .map(|entry| PkarrMessage {
+                text: entry.m,
+                timestamp: entry.t,
+                nick: None,
+                id: entry.i,
+                seq: entry.s,
+                rotation: entry.r,
+                attachment: entry.a,
+                kind: entry.k,
+            })
+            .collect(),
+        sender_authenticated: decrypted.bound,
+        fallback_key_used: decrypted.fallback,
+        padding,
+    })
+}
+
+/// Describes records that failed authentication and, when `strict`, those
+/// only accepted through the unbound legacy fallback.
+pub fn auth_failure(
+    record_status: &BTreeMap<String, RecordStatus>,
+    strict: bool,
+) -> Option<String> {
+    let labels = |wanted: RecordStatus| {
+        record_status
+            .iter()
+            .filter(|(_, status)| **status == wanted)
+            .map(|(label, _)| label.as_str())
+            .collect::<Vec<_>>()
+    };
+    let failed = labels(RecordStatus::AuthFailed);
+    let unbound = if strict {
+        labels(RecordStatus::LegacyUnbound)
+    } else {
+        vec![]
+    };
+    if !failed.is_empty() {
+        Some(format!(
+            "Authentication failed for {}: wrong key or tampered packet",
+            failed.join(", ")
+        ))
+    } else if !unbound.is_empty() {
+        Some(format!(
+            "Unauthenticated legacy payload in {}: peer predates _v",
+            unbound.join(", ")
+        ))
+    } else {
+        None
+    }
+}
+
+/// Signed offset of the sender's clock from ours, judged by the newest of
+/// `_ts` and the packet timestamp against our receive time `now`. Negative
+/// values also cover packets that simply haven't been republished lately.
+fn detect_clock_skew(latest_timestamp: i64, packet_timestamp: i64, now: i64) -> Option<i64> {
+    let skew = latest_timestamp.max(packet_timestamp) - now;
+    (skew.abs() > CLOCK_SKEW_TOLERANCE_MS).then_some(skew)
 }
 
-pub fn create_keypair() -> (Keypair, String, String) {
+pub fn create_keypair() -> (Keypair, SecretSeed, String) {
     let keypair = Keypair::random();
-    let secret = keypair.secret_key();
-    let seed_b64 = crypto::to_base64_url(&secret);
+    let seed = SecretSeed::from_bytes(keypair.secret_key());
     let pub_key_z32 = keypair.to_z32();
-    (keypair, seed_b64, pub_key_z32)
+    (keypair, seed, pub_key_z32)
 }
 
-pub fn keypair_from_seed(seed_b64: &str) -> Result<Keypair, String> {
-    let seed_bytes = crypto::from_base64_url(seed_b64)?;
-    if seed_bytes.len() != 32 {
-        return Err(format!(
-            "Invalid seed length: expected 32 bytes, got {}",
-            seed_bytes.len()
-        ));
-    }
-    let mut arr = [0u8; 32];
-    arr.copy_from_slice(&seed_bytes);
-    Ok(Keypair::from_secret_key(&arr))
+pub fn keypair_from_seed(seed: &SecretSeed) -> Keypair {
+    Keypair::from_secret_key(seed.as_bytes())
 }
 
-pub fn pubkey_from_seed(seed_b64: &str) -> Result<String, String> {
-    let keypair = keypair_from_seed(seed_b64)?;
-    Ok(keypair.to_z32())
+pub fn pubkey_from_seed(seed: &SecretSeed) -> String {
+    keypair_from_seed(seed).to_z32()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{direction_keys, generate_key};
+    use pkarr::Timestamp;
+
+    const PACKET_TS_MS: i64 = 1_700_000_000_000;
+
+    fn sign(builder: SignedPacketBuilder, keypair: &Keypair) -> SignedPacket {
+        builder
+            .timestamp(Timestamp::from(PACKET_TS_MS as u64 * 1000))
+            .sign(keypair)
+            .unwrap()
+    }
+
+    #[test]
+    fn reports_sender_clocks_running_ahead() {
+        let now = PACKET_TS_MS;
+        assert_eq!(detect_clock_skew(now + 60_000, now, now), Some(60_000));
+        assert_eq!(detect_clock_skew(now, now + 45_000, now), Some(45_000));
+        assert_eq!(detect_clock_skew(now + 5_000, now, now), None);
+    }
+
+    #[test]
+    fn reports_sender_clocks_running_behind() {
+        let now = PACKET_TS_MS;
+        assert_eq!(
+            detect_clock_skew(now - 90_000, now - 60_000, now),
+            Some(-60_000)
+        );
+        assert_eq!(detect_clock_skew(now - 5_000, now - 5_000, now), None);
+    }
+
+    #[test]
+    fn decodes_v0_legacy_packet_with_ts() {
+        let keypair = Keypair::random();
+        let key = generate_key();
+        let sealed = crypto::encrypt("hello from v0", &key).unwrap();
+
+        let builder = add_txt(SignedPacket::builder(), "_msg", &sealed).unwrap();
+        let builder = add_txt(builder, "_ts", "1699999990000").unwrap();
+        let batch = decode_packet(&sign(builder, &keypair), &PayloadKey::shared(&key));
+
+        assert_eq!(batch.message_count, 1);
+        assert_eq!(batch.messages[0].text, "hello from v0");
+        assert_eq!(batch.messages[0].timestamp, 1_699_999_990_000);
+        assert_eq!(batch.latest_timestamp, 1_699_999_990_000);
+        assert_eq!(batch.encrypted_payload_length, sealed.len());
+        assert!(!batch.sender_authenticated);
+    }
+
+    #[test]
+    fn decodes_v0_legacy_packet_without_ts() {
+        let keypair = Keypair::random();
+        let key = generate_key();
+        let sealed = crypto::encrypt("no timestamp", &key).unwrap();
+
+        let builder = add_txt(SignedPacket::builder(), "_msg", &sealed).unwrap();
+        let batch = decode_packet(&sign(builder, &keypair), &PayloadKey::shared(&key));
+
+        assert_eq!(batch.message_count, 1);
+        assert_eq!(batch.messages[0].timestamp, PACKET_TS_MS);
+        assert_eq!(batch.latest_timestamp, PACKET_TS_MS);
+    }
+
+    #[test]
+    fn decodes_v1_batch_packet() {
+        let sender = Keypair::random();
+        let receiver = Keypair::random();
+        let key = generate_key();
+        let (send_key, _) = direction_keys(&key, &sender.to_z32(), &receiver.to_z32());
+        let (_, recv_key) = direction_keys(&key, &receiver.to_z32(), &sender.to_z32());
+
+        let messages = vec![
+            CompactMessage {
+                t: 1_699_999_980_000,
+                m: "first".to_string(),
+                s: Some(1),
+                ..Default::default()
+            },
+            CompactMessage {
+                t: 1_699_999_990_000,
+                m: "second".to_string(),
+                s: Some(2),
+                ..Default::default()
+            },
+        ];
+        let (payload, count) = trim_to_fit(
+            &messages,
+            &send_key,
+            &sender.to_z32(),
+            MAX_MSGS_PAYLOAD_B64,
+            Padding::Off,
+        )
+        .unwrap();
+        assert_eq!(count, 2);
+
+        let builder = add_txt(SignedPacket::builder(), "_msgs", &payload).unwrap();
+        let builder = add_txt(builder, "_seq", "2").unwrap();
+        let batch = decode_packet(&sign(builder, &sender), &recv_key);
+
+        assert!(batch.sender_authenticated);
+        assert_eq!(batch.last_seq, 2);
+        let texts: Vec<&str> = batch.messages.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, ["first", "second"]);
+        assert_eq!(batch.messages[1].seq, Some(2));
+        assert_eq!(batch.latest_timestamp, 1_699_999_990_000);
+    }
+
+    #[test]
+    fn prefers_v1_when_both_layouts_present() {
+        let keypair = Keypair::random();
+        let key = generate_key();
+        let shared = PayloadKey::shared(&key);
+        let messages = vec![CompactMessage {
+            t: 1_699_999_990_000,
+            m: "batched".to_string(),
+            ..Default::default()
+        }];
+        let (payload, _) = trim_to_fit(
+            &messages,
+            &shared,
+            &keypair.to_z32(),
+            MAX_MSGS_PAYLOAD_B64,
+            Padding::Off,
+        )
+        .unwrap();
+        let legacy = crypto::encrypt("legacy", &key).unwrap();
+
+        let builder = add_txt(SignedPacket::builder(), "_msg", &legacy).unwrap();
+        let builder = add_txt(builder, "_msgs", &payload).unwrap();
+        let batch = decode_packet(&sign(builder, &keypair), &shared);
+
+        assert_eq!(batch.message_count, 1);
+        assert_eq!(batch.messages[0].text, "batched");
+    }
+
+    #[test]
+    fn reports_auth_failure_for_wrong_key() {
+        let keypair = Keypair::random();
+        let key = PayloadKey::shared(&generate_key());
+        let wrong_key = PayloadKey::shared(&generate_key());
+        let (payload, _) = trim_to_fit(
+            &[],
+            &key,
+            &keypair.to_z32(),
+            MAX_MSGS_PAYLOAD_B64,
+            Padding::Off,
+        )
+        .unwrap();
+        let nick = seal_record("_nick", "ghost", &key, &keypair.to_z32()).unwrap();
+
+        let builder = add_txt(SignedPacket::builder(), "_msgs", &payload).unwrap();
+        let builder = add_txt(builder, "_nick", &nick).unwrap();
+        let packet = sign(builder, &keypair);
+
+        let batch = decode_packet(&packet, &key);
+        assert_eq!(batch.record_status["_msgs"], RecordStatus::Ok);
+        assert_eq!(batch.record_status["_nick"], RecordStatus::Ok);
+        assert!(auth_failure(&batch.record_status, true).is_none());
+
+        let batch = decode_packet(&packet, &wrong_key);
+        assert_eq!(batch.record_status["_msgs"], RecordStatus::AuthFailed);
+        assert_eq!(batch.record_status["_nick"], RecordStatus::AuthFailed);
+        assert!(batch.messages.is_empty() && !batch.sender_authenticated);
+        assert_eq!(
+            auth_failure(&batch.record_status, true).unwrap(),
+            "Authentication failed for _msgs, _nick: wrong key or tampered packet"
+        );
+    }
+
+    #[test]
+    fn unbound_records_only_accepted_from_peers_without_v() {
+        let keypair = Keypair::random();
+        let key = generate_key();
+        let nick = crypto::encrypt("ghost", &key).unwrap();
+
+        let builder = add_txt(SignedPacket::builder(), "_nick", &nick).unwrap();
+        let batch = decode_packet(&sign(builder, &keypair), &PayloadKey::shared(&key));
+        assert_eq!(batch.record_status["_nick"], RecordStatus::LegacyUnbound);
+        assert!(auth_failure(&batch.record_status, false).is_none());
+        assert!(auth_failure(&batch.record_status, true).is_some());
+
+        let builder = add_txt(SignedPacket::builder(), "_nick", &nick).unwrap();
+        let builder = add_txt(builder, "_v", "2").unwrap();
+        let batch = decode_packet(&sign(builder, &keypair), &PayloadKey::shared(&key));
+        assert_eq!(batch.record_status["_nick"], RecordStatus::AuthFailed);
+    }
+
+    #[test]
+    fn seq_continues_past_peer_ack_when_own_packet_expired() {
+        let peer = ResolvedBatch {
+            peer_ack_seq: Some(5),
+            ..Default::default()
+        };
+        let last_seq = last_sent_seq(None, Some(&peer));
+        assert_eq!(last_seq, 5);
+
+        let next = CompactMessage {
+            t: 1,
+            m: "after restart".to_string(),
+            s: Some(last_seq + 1),
+            ..Default::default()
+        };
+        assert_eq!(unacked_messages(&[next], &peer_ack(&peer)).len(), 1);
+
+        let own = ResolvedBatch {
+            last_seq: 9,
+            ..Default::default()
+        };
+        assert_eq!(last_sent_seq(Some(&own), Some(&peer)), 9);
+    }
 }
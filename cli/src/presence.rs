@@ -0,0 +1,48 @@
+use crate::capabilities::negotiated_send_key;
+use crate::crypto::{direction_keys, SecretSeed, SharedKey};
+use crate::pkarr::{ack_for, keypair_from_seed, resolve_messages};
+use crate::types::TypingOutput;
+use crate::GhostClient;
+
+impl GhostClient {
+    /// Republishes our outbox with the typing flag set or cleared. Sending a
+    /// message clears it too.
+    pub async fn set_typing(
+        &self,
+        seed: &SecretSeed,
+        peer_pubkey: &str,
+        shared_key: &SharedKey,
+        typing: bool,
+        nick: Option<&str>,
+    ) -> Result<TypingOutput, String> {
+        let keypair = keypair_from_seed(seed);
+        let (_, recv_key) = direction_keys(shared_key, &keypair.to_z32(), peer_pubkey);
+
+        let peer_batch = resolve_messages(&self.client, peer_pubkey, &recv_key).await?;
+        let ack = peer_batch.as_ref().map(ack_for).unwrap_or_default();
+        let send_key = negotiated_send_key(
+            shared_key,
+            &keypair.to_z32(),
+            peer_pubkey,
+            peer_batch.as_ref(),
+        );
+
+        let (kept, _) = self
+            .publish_outbox_with(
+                &keypair,
+                &send_key,
+                peer_batch.as_ref(),
+                &ack,
+                None,
+                nick,
+                typing,
+            )
+            .await?;
+
+        Ok(TypingOutput {
+            ok: true,
+            typing,
+            messages_kept: kept,
+        })
+    }
+}
@@ -0,0 +1,61 @@
+use crate::capabilities::CLIENT_FEATURES;
+use crate::crypto::PayloadKey;
+use crate::pkarr::{open_record, pad, seal_record};
+use crate::types::{Attachment, Padding, PeerProfile, RecordStatus};
+
+pub const PROFILE_VERSION: u8 = 1;
+/// Largest profile plaintext; with sealing it takes about a third of a packet.
+pub const MAX_PROFILE_LENGTH: usize = 200;
+
+impl PeerProfile {
+    pub fn new(
+        display_name: Option<String>,
+        status: Option<String>,
+        avatar_blurhash: Option<String>,
+        avatar: Option<Attachment>,
+    ) -> Result<Self, String> {
+        let profile = Self {
+            version: PROFILE_VERSION,
+            display_name,
+            status,
+            avatar_blurhash,
+            avatar,
+            capabilities: CLIENT_FEATURES.iter().map(|f| f.to_string()).collect(),
+        };
+        profile.to_json()?;
+        Ok(profile)
+    }
+
+    fn to_json(&self) -> Result<String, String> {
+        let json = serde_json::to_string(self).map_err(|e| format!("JSON: {}", e))?;
+        if json.len() > MAX_PROFILE_LENGTH {
+            return Err(format!(
+                "Profile too large: {} bytes (max {}); shorten the status or drop the avatar",
+                json.len(),
+                MAX_PROFILE_LENGTH
+            ));
+        }
+        Ok(json)
+    }
+}
+
+pub(crate) fn seal_profile(
+    profile: &PeerProfile,
+    key: &PayloadKey,
+    sender_z32: &str,
+    padding: Padding,
+) -> Result<String, String> {
+    let padded = pad(&profile.to_json()?, padding, MAX_PROFILE_LENGTH);
+    seal_record("_prof", &padded, key, sender_z32)
+}
+
+/// Opens a `_prof` record. Fields added by newer versions are ignored.
+pub(crate) fn open_profile(
+    encoded: &str,
+    key: &PayloadKey,
+    sender_z32: &str,
+) -> Result<PeerProfile, RecordStatus> {
+    let decrypted =
+        open_record("_prof", encoded, key, sender_z32).map_err(|_| RecordStatus::AuthFailed)?;
+    serde_json::from_str(&decrypted.plaintext).map_err(|_| RecordStatus::Malformed)
+}
@@ -0,0 +1,202 @@
+use crate::crypto::{direction_keys, generate_key, PayloadKey, SharedKey};
+use crate::pkarr::{ack_for, keypair_from_seed, resolve_messages};
+use crate::types::{
+    CompactMessage, KeyRotation, PendingRotation, ResolvedBatch, RotateOutput, Session,
+};
+use crate::GhostClient;
+
+/// Text carried by rotation messages, shown as-is by clients that predate them.
+pub const ROTATION_TEXT: &str = "🔑 Key rotated";
+
+/// Returns the (send, receive) payload keys for a 1:1 conversation, keeping
+/// the old key around while a rotation is pending.
+pub fn conversation_keys(
+    key: &SharedKey,
+    rotation: Option<&PendingRotation>,
+    my_z32: &str,
+    peer_z32: &str,
+) -> (PayloadKey, PayloadKey) {
+    let (send_key, recv_key) = direction_keys(key, my_z32, peer_z32);
+    let Some(rotation) = rotation else {
+        return (send_key, recv_key);
+    };
+
+    let (old_send_key, old_recv_key) = direction_keys(&rotation.old_key, my_z32, peer_z32);
+    let send_key = if rotation.initiated {
+        old_send_key.with_fallback(send_key)
+    } else {
+        send_key.with_fallback(old_send_key)
+    };
+    (send_key, recv_key.with_fallback(old_recv_key))
+}
+
+/// Advances a conversation's key state from the peer's latest batch: a
+/// pending rotation completes once the peer publishes under the new key, and
+/// a rotation announced by the peer is adopted. Only the announcement with the
+/// latest `effective_from` counts; older ones still in the peer's outbox were
+/// superseded by it. Returns `true` on any change.
+pub fn apply_rotation(
+    key: &mut SharedKey,
+    rotation: &mut Option<PendingRotation>,
+    batch: &ResolvedBatch,
+) -> bool {
+    let mut changed = false;
+
+    if rotation.is_some() && batch.sender_authenticated && !batch.fallback_key_used {
+        *rotation = None;
+        changed = true;
+    }
+
+    let latest = batch
+        .messages
+        .iter()
+        .filter_map(|m| m.rotation.as_ref())
+        .max_by_key(|r| r.effective_from);
+    let Some(announced) = latest else {
+        return changed;
+    };
+    let known = announced.key.as_bytes() == key.as_bytes()
+        || rotation
+            .as_ref()
+            .is_some_and(|r| announced.effective_from <= r.effective_from);
+    if known {
+        return changed;
+    }
+
+    let old_key = std::mem::replace(key, announced.key.clone());
+    *rotation = Some(PendingRotation {
+        old_key,
+        effective_from: announced.effective_from,
+        initiated: false,
+    });
+    true
+}
+
+impl GhostClient {
+    /// Switches the session to a fresh key, announcing it to the peer in a
+    /// message sealed under the current one.
+    pub async fn rotate_key(&self, session: &mut Session) -> Result<RotateOutput, String> {
+        if session.rotation.is_some() || session.migration.is_some() {
+            return Err(format!(
+                "Key rotation or migration still pending for session: {}",
+                session.name
+            ));
+        }
+
+        let keypair = keypair_from_seed(&session.seed);
+        let effective_from = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| format!("Time error: {}", e))?
+            .as_millis() as i64;
+        let announcement = KeyRotation {
+            key: generate_key(),
+            effective_from,
+        };
+
+        let rotation = Some(PendingRotation {
+            old_key: session.key.clone(),
+            effective_from,
+            initiated: true,
+        });
+        let (send_key, recv_key) = conversation_keys(
+            &announcement.key,
+            rotation.as_ref(),
+            &keypair.to_z32(),
+            &session.peer,
+        );
+
+        let peer_batch = resolve_messages(&self.client, &session.peer, &recv_key).await?;
+        let ack = peer_batch.as_ref().map(ack_for).unwrap_or_default();
+
+        let (kept, sent) = self
+            .publish_outbox_with(
+                &keypair,
+                &send_key,
+                peer_batch.as_ref(),
+                &ack,
+                Some(CompactMessage {
+                    m: ROTATION_TEXT.to_string(),
+                    r: Some(announcement.clone()),
+                    ..Default::default()
+                }),
+                session.nick.as_deref(),
+                false,
+            )
+            .await?;
+        let sent = sent.ok_or("Rotation was not queued")?;
+
+        session.key = announcement.key;
+        session.rotation = rotation;
+
+        Ok(RotateOutput {
+            ok: true,
+            session: session.name.clone(),
+            effective_from,
+            seq: sent.s.unwrap_or_default(),
+            messages_kept: kept,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::PkarrMessage;
+
+    fn announcement(key: &SharedKey, effective_from: i64) -> PkarrMessage {
+        PkarrMessage {
+            text: ROTATION_TEXT.to_string(),
+            timestamp: effective_from,
+            nick: None,
+            id: None,
+            seq: None,
+            rotation: Some(KeyRotation {
+                key: key.clone(),
+                effective_from,
+            }),
+            attachment: None,
+            kind: None,
+        }
+    }
+
+    #[test]
+    fn adopts_only_the_latest_announcement() {
+        let (first, second) = (generate_key(), generate_key());
+        let mut key = generate_key();
+        let mut rotation = None;
+        let batch = ResolvedBatch {
+            messages: vec![announcement(&second, 2_000), announcement(&first, 1_000)],
+            sender_authenticated: true,
+            ..Default::default()
+        };
+
+        assert!(apply_rotation(&mut key, &mut rotation, &batch));
+        assert_eq!(key.as_bytes(), second.as_bytes());
+        assert_eq!(rotation.as_ref().map(|r| r.effective_from), Some(2_000));
+
+        // Once the peer publishes under `second`, the stale `first` stays ignored.
+        assert!(apply_rotation(&mut key, &mut rotation, &batch));
+        assert!(rotation.is_none());
+        assert!(!apply_rotation(&mut key, &mut rotation, &batch));
+        assert_eq!(key.as_bytes(), second.as_bytes());
+    }
+
+    #[test]
+    fn ignores_announcements_older_than_a_pending_rotation() {
+        let mut key = generate_key();
+        let mut rotation = Some(PendingRotation {
+            old_key: generate_key(),
+            effective_from: 5_000,
+            initiated: true,
+        });
+        let batch = ResolvedBatch {
+            messages: vec![announcement(&generate_key(), 4_000)],
+            fallback_key_used: true,
+            ..Default::default()
+        };
+
+        let before = key.clone();
+        assert!(!apply_rotation(&mut key, &mut rotation, &batch));
+        assert_eq!(key.as_bytes(), before.as_bytes());
+    }
+}
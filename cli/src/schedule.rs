@@ -0,0 +1,117 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+const IDLE_FACTOR: f64 = 1.5;
+const ERROR_FACTOR: f64 = 2.0;
+const FAST_POLLS_AFTER_ACTIVITY: u32 = 5;
+const JITTER_RATIO: f64 = 0.2;
+
+#[derive(Debug, Clone)]
+pub struct PollSchedule {
+    min_interval: u64,
+    max_interval: u64,
+    current: u64,
+    fast_polls_left: u32,
+}
+
+impl PollSchedule {
+    pub fn new(min_interval: u64, max_interval: u64) -> Self {
+        let min_interval = min_interval.max(1);
+        Self {
+            min_interval,
+            max_interval: max_interval.max(min_interval),
+            current: min_interval,
+            fast_polls_left: FAST_POLLS_AFTER_ACTIVITY,
+        }
+    }
+
+    pub fn interval(&self) -> u64 {
+        self.current
+    }
+
+    pub fn on_activity(&mut self) {
+        self.current = self.min_interval;
+        self.fast_polls_left = FAST_POLLS_AFTER_ACTIVITY;
+    }
+
+    pub fn on_idle(&mut self) {
+        if self.fast_polls_left > 0 {
+            self.fast_polls_left -= 1;
+            return;
+        }
+        self.grow(IDLE_FACTOR);
+    }
+
+    pub fn on_error(&mut self) {
+        self.fast_polls_left = 0;
+        self.grow(ERROR_FACTOR);
+    }
+
+    pub fn next_delay(&self) -> Duration {
+        let spread = (self.current as f64 * JITTER_RATIO) as i64;
+        let jitter = if spread > 0 {
+            rand::thread_rng().gen_range(-spread..=spread)
+        } else {
+            0
+        };
+        Duration::from_millis((self.current as i64 + jitter).max(1) as u64)
+    }
+
+    fn grow(&mut self, factor: f64) {
+        let next = (self.current as f64 * factor) as u64;
+        self.current = next.clamp(self.min_interval, self.max_interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn idle_polls(schedule: &mut PollSchedule, n: u32) {
+        for _ in 0..n {
+            schedule.on_idle();
+        }
+    }
+
+    #[test]
+    fn backs_off_after_the_fast_polls() {
+        let mut schedule = PollSchedule::new(1_000, 60_000);
+        idle_polls(&mut schedule, FAST_POLLS_AFTER_ACTIVITY);
+        assert_eq!(schedule.interval(), 1_000);
+
+        schedule.on_idle();
+        assert_eq!(schedule.interval(), 1_500);
+        schedule.on_idle();
+        assert_eq!(schedule.interval(), 2_250);
+        schedule.on_error();
+        assert_eq!(schedule.interval(), 4_500);
+    }
+
+    #[test]
+    fn caps_the_interval() {
+        let mut schedule = PollSchedule::new(1_000, 10_000);
+        idle_polls(&mut schedule, 50);
+        assert_eq!(schedule.interval(), 10_000);
+        schedule.on_error();
+        assert_eq!(schedule.interval(), 10_000);
+
+        for _ in 0..100 {
+            let delay = schedule.next_delay().as_millis() as u64;
+            assert!((8_000..=12_000).contains(&delay));
+        }
+    }
+
+    #[test]
+    fn activity_resets_to_fast_polling() {
+        let mut schedule = PollSchedule::new(1_000, 60_000);
+        schedule.on_error();
+        idle_polls(&mut schedule, 3);
+        assert!(schedule.interval() > 1_000);
+
+        schedule.on_activity();
+        assert_eq!(schedule.interval(), 1_000);
+        idle_polls(&mut schedule, FAST_POLLS_AFTER_ACTIVITY);
+        assert_eq!(schedule.interval(), 1_000);
+    }
+}
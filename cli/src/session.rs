@@ -0,0 +1,231 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::types::{Channel, Group, PeerProfile, PendingInvite, Session};
+
+const SESSIONS_FILE: &str = "sessions.json";
+
+#[derive(Default, Serialize, Deserialize)]
+struct StoreData {
+    #[serde(default)]
+    sessions: Vec<Session>,
+    #[serde(default)]
+    groups: Vec<Group>,
+    #[serde(default)]
+    channels: Vec<Channel>,
+    #[serde(default)]
+    invites: Vec<PendingInvite>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    profile: Option<PeerProfile>,
+}
+
+pub struct SessionStore {
+    path: PathBuf,
+    data: StoreData,
+}
+
+impl SessionStore {
+    pub fn default_path() -> Result<PathBuf, String> {
+        if let Ok(home) = std::env::var("GHOSTLY_HOME") {
+            return Ok(PathBuf::from(home).join(SESSIONS_FILE));
+        }
+        let home = std::env::var("HOME").map_err(|_| "HOME is not set (use GHOSTLY_HOME)")?;
+        Ok(PathBuf::from(home).join(".ghostly").join(SESSIONS_FILE))
+    }
+
+    pub fn open_default() -> Result<Self, String> {
+        Self::load(&Self::default_path()?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| format!("Invalid session store {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StoreData::default(),
+            Err(e) => return Err(format!("Read {}: {}", path.display(), e)),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            data,
+        })
+    }
+
+    /// Writes the store to a private temp file beside it, then renames it into
+    /// place, so secrets are never world-readable and a crash can't truncate it.
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("Create {}: {}", dir.display(), e))?;
+        }
+        let json = Zeroizing::new(
+            serde_json::to_string_pretty(&self.data)
+                .map_err(|e| format!("JSON serialize: {}", e))?,
+        );
+
+        let mut tmp_name = self.path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(format!(".{}.tmp", std::process::id()));
+        let tmp_path = self.path.with_file_name(tmp_name);
+        let _ = fs::remove_file(&tmp_path);
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let written = options.open(&tmp_path).and_then(|mut file| {
+            file.write_all(json.as_bytes())?;
+            file.sync_all()
+        });
+        if let Err(e) = written.and_then(|_| fs::rename(&tmp_path, &self.path)) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(format!("Write {}: {}", self.path.display(), e));
+        }
+
+        Ok(())
+    }
+
+    pub fn sessions(&self) -> &[Session] {
+        &self.data.sessions
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Session> {
+        self.data.sessions.iter().find(|s| s.name == name)
+    }
+
+    pub fn upsert(&mut self, session: Session) {
+        match self
+            .data
+            .sessions
+            .iter_mut()
+            .find(|s| s.name == session.name)
+        {
+            Some(existing) => *existing = session,
+            None => self.data.sessions.push(session),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        let before = self.data.sessions.len();
+        self.data.sessions.retain(|s| s.name != name);
+        self.data.sessions.len() != before
+    }
+
+    pub fn groups(&self) -> &[Group] {
+        &self.data.groups
+    }
+
+    pub fn group(&self, name: &str) -> Option<&Group> {
+        self.data.groups.iter().find(|g| g.name == name)
+    }
+
+    pub fn upsert_group(&mut self, group: Group) {
+        match self.data.groups.iter_mut().find(|g| g.name == group.name) {
+            Some(existing) => *existing = group,
+            None => self.data.groups.push(group),
+        }
+    }
+
+    pub fn remove_group(&mut self, name: &str) -> bool {
+        let before = self.data.groups.len();
+        self.data.groups.retain(|g| g.name != name);
+        self.data.groups.len() != before
+    }
+
+    pub fn channels(&self) -> &[Channel] {
+        &self.data.channels
+    }
+
+    pub fn channel(&self, name: &str) -> Option<&Channel> {
+        self.data.channels.iter().find(|c| c.name == name)
+    }
+
+    pub fn upsert_channel(&mut self, channel: Channel) {
+        match self
+            .data
+            .channels
+            .iter_mut()
+            .find(|c| c.name == channel.name)
+        {
+            Some(existing) => *existing = channel,
+            None => self.data.channels.push(channel),
+        }
+    }
+
+    pub fn invite(&self, token: &str) -> Option<&PendingInvite> {
+        self.data.invites.iter().find(|i| i.token == token)
+    }
+
+    pub fn upsert_invite(&mut self, invite: PendingInvite) {
+        match self
+            .data
+            .invites
+            .iter_mut()
+            .find(|i| i.token == invite.token)
+        {
+            Some(existing) => *existing = invite,
+            None => self.data.invites.push(invite),
+        }
+    }
+
+    pub fn remove_invite(&mut self, token: &str) -> bool {
+        let before = self.data.invites.len();
+        self.data.invites.retain(|i| i.token != token);
+        self.data.invites.len() != before
+    }
+
+    /// Our own profile, published with every session.
+    pub fn profile(&self) -> Option<&PeerProfile> {
+        self.data.profile.as_ref()
+    }
+
+    pub fn set_profile(&mut self, profile: Option<PeerProfile>) {
+        self.data.profile = profile;
+    }
+
+    pub fn select(&self, names: &[String]) -> Result<Vec<Session>, String> {
+        names
+            .iter()
+            .map(|name| {
+                self.get(name)
+                    .cloned()
+                    .ok_or_else(|| format!("Unknown session: {}", name))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_replaces_store_with_private_file() {
+        let dir = std::env::temp_dir().join(format!("ghostly-store-{}", std::process::id()));
+        let path = dir.join(SESSIONS_FILE);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, "stale").unwrap();
+
+        let store = SessionStore::load(&dir.join("missing.json")).unwrap();
+        let store = SessionStore {
+            path: path.clone(),
+            ..store
+        };
+        store.save().unwrap();
+
+        assert!(SessionStore::load(&path).is_ok());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
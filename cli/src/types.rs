@@ -1,21 +1,234 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+use crate::crypto::{SecretSeed, SharedKey};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct CompactMessage {
     pub t: i64,
     pub m: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub i: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub r: Option<KeyRotation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub a: Option<Attachment>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "lenient_kind"
+    )]
+    pub k: Option<MessageKind>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Kinds this client does not know decode as `None`, leaving the message's
+/// fallback text, instead of failing the whole batch.
+fn lenient_kind<'de, D: Deserializer<'de>>(d: D) -> Result<Option<MessageKind>, D::Error> {
+    let value = serde_json::Value::deserialize(d)?;
+    Ok(serde_json::from_value(value).ok())
+}
+
+/// Marks a message as acting on an earlier one. `m` still carries a readable
+/// fallback for clients that predate kinds.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "y", rename_all = "lowercase")]
+pub enum MessageKind {
+    Reply {
+        to: MessageRef,
+    },
+    React {
+        to: MessageRef,
+        #[serde(rename = "e")]
+        emoji: String,
+    },
+    Edit {
+        to: MessageRef,
+    },
+    Delete {
+        to: MessageRef,
+    },
+}
+
+/// The message a [`MessageKind`] acts on: its id, or its timestamp for
+/// messages from clients that do not set ids.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MessageRef {
+    #[serde(rename = "i", default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "t", default, skip_serializing_if = "Option::is_none")]
+    pub ts: Option<i64>,
+}
+
+impl MessageRef {
+    /// Reads a message id, or a millisecond timestamp when all digits.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("Message reference cannot be empty".to_string());
+        }
+        Ok(match s.parse::<i64>() {
+            Ok(ts) => MessageRef {
+                id: None,
+                ts: Some(ts),
+            },
+            Err(_) => MessageRef {
+                id: Some(s.to_string()),
+                ts: None,
+            },
+        })
+    }
+}
+
+/// Control payload announcing the shared key to switch to.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeyRotation {
+    #[serde(rename = "k")]
+    pub key: SharedKey,
+    #[serde(rename = "f")]
+    pub effective_from: i64,
+}
+
+/// Liveness published in the sealed `_pres` record alongside acks.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Presence {
+    #[serde(rename = "a")]
+    pub last_active: i64,
+    #[serde(rename = "y", default, skip_serializing_if = "std::ops::Not::not")]
+    pub typing: bool,
+}
+
+/// Protocol version and features a peer advertises in its plaintext `_v`
+/// record, so formats can be chosen without trial decryption.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct PeerCapabilities {
+    pub version: u32,
+    pub features: Vec<String>,
+}
+
+/// Sealed `_prof` record: how a peer presents itself beyond `_nick`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PeerProfile {
+    #[serde(rename = "v")]
+    pub version: u8,
+    #[serde(rename = "n", default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(rename = "s", default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(rename = "b", default, skip_serializing_if = "Option::is_none")]
+    pub avatar_blurhash: Option<String>,
+    /// Full-size avatar, published as a file attachment.
+    #[serde(rename = "a", default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<Attachment>,
+    #[serde(rename = "c", default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
+}
+
+/// Manifest of a file published as encrypted chunks, each under a keypair
+/// derived from `key` and the chunk index.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Attachment {
+    #[serde(rename = "n")]
+    pub name: String,
+    #[serde(rename = "z")]
+    pub size: u64,
+    /// SHA-256 of the file contents (base64url).
+    #[serde(rename = "h")]
+    pub hash: String,
+    #[serde(rename = "c")]
+    pub chunks: u32,
+    #[serde(rename = "k")]
+    pub key: SharedKey,
+}
+
+/// A rotation that the other side has not picked up yet. Until it has,
+/// `old_key` stays usable for decryption; the side that `initiated` it also
+/// keeps publishing under `old_key` so the peer can read the announcement.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PendingRotation {
+    pub old_key: SharedKey,
+    pub effective_from: i64,
+    #[serde(default)]
+    pub initiated: bool,
+}
+
+/// Our previous identity, whose inbound payloads stay readable until the
+/// peer starts addressing the new one.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PendingMigration {
+    pub old_seed: SecretSeed,
+    pub moved_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Ack {
+    pub ts: i64,
+    pub id: Option<String>,
+    pub seq: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Padding {
+    Off,
+    #[default]
+    Bucketed,
+    Full,
+}
+
+impl std::str::FromStr for Padding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Padding::Off),
+            "bucketed" => Ok(Padding::Bucketed),
+            "full" => Ok(Padding::Full),
+            _ => Err(format!(
+                "Invalid padding: {} (expected off, bucketed or full)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct ResolvedBatch {
     pub messages: Vec<PkarrMessage>,
     pub latest_timestamp: i64,
     pub peer_ack: i64,
+    pub peer_ack_id: Option<String>,
+    pub peer_ack_seq: Option<u64>,
+    pub last_seq: u64,
+    pub clock_skew_ms: Option<i64>,
+    pub sender_authenticated: bool,
     pub raw_record_names: Vec<String>,
     pub encrypted_payload_length: usize,
+    pub payload_padding: usize,
+    pub fallback_key_used: bool,
+    pub moved_to: Option<String>,
     pub packet_timestamp: i64,
     pub message_count: usize,
     pub call_signal: Option<String>,
+    pub presence: Option<Presence>,
+    pub profile: Option<PeerProfile>,
+    pub capabilities: Option<PeerCapabilities>,
+    /// Decode outcome of each encrypted record, keyed by record name.
+    pub record_status: BTreeMap<String, RecordStatus>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordStatus {
+    Ok,
+    /// Wrong key, or the record was tampered with.
+    AuthFailed,
+    /// Decrypted, but the contents didn't parse.
+    Malformed,
+    /// Opened as a pre-v1 payload without associated data, so it could be
+    /// replayed or reflected. Only accepted from peers that predate `_v`.
+    LegacyUnbound,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -23,53 +236,363 @@ pub struct PkarrMessage {
     pub text: String,
     pub timestamp: i64,
     pub nick: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<KeyRotation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<Attachment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<MessageKind>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct IdentityOutput {
-    pub seed: String,
+    pub seed: SecretSeed,
     pub pubkey: String,
-    pub shared_key: String,
+    pub shared_key: SharedKey,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InviteOutput {
     pub invite_url: String,
+    /// Compact bech32m form of `invite_url`, used for QR codes.
+    pub invite_code: String,
     pub pubkey: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub single_use: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub password_protected: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ParsedInvite {
     pub peer_pubkey: String,
-    pub shared_key: String,
-    pub my_seed: String,
+    pub shared_key: SharedKey,
+    pub my_seed: SecretSeed,
     pub my_pubkey: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub expired: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub single_use: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub password_protected: bool,
+}
+
+/// An invite with an expiry or one-time token, kept by the inviter until it
+/// is used up; joins arrive at the rendezvous key derived from `token`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PendingInvite {
+    pub token: String,
+    pub seed: SecretSeed,
+    pub key: SharedKey,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    #[serde(default)]
+    pub single_use: bool,
+    #[serde(default)]
+    pub joined: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SendOutput {
     pub ok: bool,
     pub timestamp: i64,
+    pub id: String,
+    pub seq: u64,
+    pub messages_kept: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TypingOutput {
+    pub ok: bool,
+    pub typing: bool,
+    pub messages_kept: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SendFileOutput {
+    pub ok: bool,
+    pub timestamp: i64,
+    pub id: String,
+    pub seq: u64,
     pub messages_kept: usize,
+    pub attachment: Attachment,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FetchFileOutput {
+    pub ok: bool,
+    pub path: String,
+    pub size: u64,
+    pub chunks: u32,
+    /// Chunks already on disk from an earlier, interrupted fetch.
+    pub resumed_chunks: u32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RecvOutput {
     pub messages: Vec<PkarrMessage>,
     pub peer_ack: i64,
+    pub peer_ack_id: Option<String>,
+    pub peer_ack_seq: Option<u64>,
     pub latest_ts: i64,
     pub message_count: usize,
+    pub clock_skew_ms: Option<i64>,
+    pub sender_authenticated: bool,
+    pub packet_ts: i64,
+    pub unchanged: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence: Option<Presence>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<PeerProfile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<PeerCapabilities>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub record_status: BTreeMap<String, RecordStatus>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InspectRecord {
+    pub name: String,
+    pub size: usize,
+    pub ttl: u32,
+    /// Plaintext records only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    /// Decode outcome, when a key was given and the record is encrypted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<RecordStatus>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InspectOutput {
+    pub peer: String,
+    pub records: Vec<InspectRecord>,
+    pub encoded_size: usize,
+    pub max_size: usize,
+    pub packet_ts: i64,
+    pub age_ms: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender_authenticated: Option<bool>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WatchEvent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    pub peer: String,
     pub from: String,
     pub text: String,
     pub timestamp: i64,
     pub nick: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_skew_ms: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HeartbeatEvent {
+    pub event: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    pub peer: String,
+    pub interval_ms: u64,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PresenceEvent {
+    pub event: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    pub peer: String,
+    pub last_active: i64,
+    pub typing: bool,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RotationEvent {
+    pub event: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    pub peer: String,
+    pub effective_from: i64,
+    /// The adopted key, for watches without a session to persist it in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<SharedKey>,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RotateOutput {
+    pub ok: bool,
+    pub session: String,
+    pub effective_from: i64,
+    pub seq: u64,
+    pub messages_kept: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MovedEvent {
+    pub event: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    pub peer: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_peer: Option<String>,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MigrateOutput {
+    pub ok: bool,
+    pub session: String,
+    pub old_pubkey: String,
+    pub new_pubkey: String,
+    pub moved_at: i64,
+    pub messages_kept: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WarningOutput {
+    pub warning: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ErrorOutput {
     pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Session {
+    pub name: String,
+    pub seed: SecretSeed,
+    pub peer: String,
+    pub key: SharedKey,
+    pub nick: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<PendingRotation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub migration: Option<PendingMigration>,
+    /// Set once the safety number was compared out of band; cleared whenever
+    /// the peer pubkey changes.
+    #[serde(default)]
+    pub verified: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub name: String,
+    pub peer: String,
+    pub nick: Option<String>,
+    pub verified: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FingerprintOutput {
+    pub session: String,
+    pub my_pubkey: String,
+    pub peer: String,
+    pub digits: String,
+    pub emoji: String,
+    pub verified: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Group {
+    pub name: String,
+    pub seed: SecretSeed,
+    pub key: SharedKey,
+    pub nick: Option<String>,
+    pub members: Vec<String>,
+    #[serde(default)]
+    pub invited: Vec<String>,
+    /// Join tokens of invites no one has used yet.
+    #[serde(default)]
+    pub pending_joins: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GroupMessage {
+    pub from: String,
+    pub text: String,
+    pub timestamp: i64,
+    pub nick: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GroupMember {
+    pub pubkey: String,
+    pub nick: Option<String>,
+    pub is_me: bool,
+    pub packet_ts: i64,
+    pub acked_seq: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroupRecvOutput {
+    pub messages: Vec<GroupMessage>,
+    pub members: Vec<GroupMember>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroupInviteOutput {
+    pub invite_url: String,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroupInfo {
+    pub name: String,
+    pub pubkey: String,
+    pub members: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Channel {
+    pub name: String,
+    pub pubkey: String,
+    pub key: SharedKey,
+    pub seed: Option<SecretSeed>,
+    pub window: usize,
+    /// Highest seq posted, so a stale resolve can't make seqs repeat.
+    #[serde(default)]
+    pub last_seq: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChannelInfo {
+    pub name: String,
+    pub pubkey: String,
+    pub follow_url: String,
+    pub publisher: bool,
 }
@@ -0,0 +1,469 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use pkarr::Keypair;
+use tokio::sync::{mpsc, Semaphore};
+
+use crate::capabilities::negotiated_send_key;
+use crate::crypto::{PayloadKey, SecretSeed, SharedKey};
+use crate::migration::{apply_migration, session_keys};
+use crate::pkarr::{auth_failure, keypair_from_seed};
+use crate::rotation::apply_rotation;
+use crate::schedule::PollSchedule;
+use crate::types::{
+    Ack, ErrorOutput, HeartbeatEvent, MovedEvent, PendingMigration, PendingRotation, PkarrMessage,
+    Presence, PresenceEvent, ResolvedBatch, RotationEvent, Session, WatchEvent,
+};
+use crate::GhostClient;
+
+#[derive(Debug, Clone)]
+pub struct WatchTarget {
+    pub session: Option<String>,
+    pub seed: Option<SecretSeed>,
+    pub peer: String,
+    pub key: SharedKey,
+    pub nick: Option<String>,
+    pub rotation: Option<PendingRotation>,
+    pub migration: Option<PendingMigration>,
+}
+
+impl From<Session> for WatchTarget {
+    fn from(s: Session) -> Self {
+        Self {
+            session: Some(s.name),
+            seed: Some(s.seed),
+            peer: s.peer,
+            key: s.key,
+            nick: s.nick,
+            rotation: s.rotation,
+            migration: s.migration,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    pub poll_interval: u64,
+    pub max_poll_interval: u64,
+    pub heartbeat: Option<u64>,
+    pub ack: bool,
+    pub concurrency: usize,
+    /// Also report payloads accepted through the unbound legacy fallback.
+    pub strict: bool,
+}
+
+#[derive(Debug)]
+pub enum WatchItem {
+    Message(WatchEvent),
+    Heartbeat(HeartbeatEvent),
+    /// The peer's last-active time or typing flag changed.
+    Presence(PresenceEvent),
+    /// The target's key state changed; `target` carries the new state to persist.
+    Rotation {
+        event: RotationEvent,
+        target: WatchTarget,
+    },
+    /// The peer or our own identity moved; `target` carries the new state to persist.
+    Moved {
+        event: MovedEvent,
+        target: WatchTarget,
+    },
+    /// Some of the peer's records failed to decrypt (wrong key or tampering).
+    AuthFailed(ErrorOutput),
+    Error(ErrorOutput),
+}
+
+/// Message ids already emitted. Far larger than any batch that fits in a
+/// packet, so ids outlive the batches that carried them.
+const MAX_SEEN_IDS: usize = 512;
+
+/// Bounded set of emitted message ids; the oldest are forgotten first.
+#[derive(Default)]
+struct SeenIds {
+    ids: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl SeenIds {
+    /// Records `id`, returning `false` if it was already seen.
+    fn insert(&mut self, id: &str) -> bool {
+        if self.ids.contains(id) {
+            return false;
+        }
+        if self.order.len() == MAX_SEEN_IDS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.ids.insert(id.to_string());
+        self.order.push_back(id.to_string());
+        true
+    }
+}
+
+struct PeerWatch {
+    target: WatchTarget,
+    keypair: Option<Keypair>,
+    send_key: PayloadKey,
+    recv_key: PayloadKey,
+    ack: Ack,
+    seen_ids: SeenIds,
+    presence: Option<Presence>,
+    /// An ack that failed to publish. `resolve_changed` won't hand the same
+    /// packet back, so it's retried on the next poll instead.
+    pending_ack: Option<PendingAck>,
+}
+
+struct PendingAck {
+    /// The peer's packet being acked; `None` right after a migration.
+    peer_batch: Option<ResolvedBatch>,
+}
+
+impl PeerWatch {
+    fn new(target: WatchTarget) -> Self {
+        let keypair = target.seed.as_ref().map(keypair_from_seed);
+        let (send_key, recv_key) = Self::keys(&target, keypair.as_ref());
+        Self {
+            target,
+            keypair,
+            send_key,
+            recv_key,
+            ack: Ack::default(),
+            seen_ids: SeenIds::default(),
+            presence: None,
+            pending_ack: None,
+        }
+    }
+
+    fn keys(target: &WatchTarget, keypair: Option<&Keypair>) -> (PayloadKey, PayloadKey) {
+        match keypair {
+            Some(keypair) => session_keys(
+                &target.key,
+                target.rotation.as_ref(),
+                target.migration.as_ref(),
+                &keypair.to_z32(),
+                &target.peer,
+            ),
+            None => (
+                PayloadKey::shared(&target.key),
+                PayloadKey::shared(&target.key),
+            ),
+        }
+    }
+
+    fn rotation(&self, completed_from: Option<i64>, timestamp: i64) -> WatchItem {
+        let (event, effective_from) = match &self.target.rotation {
+            Some(rotation) => ("key_rotated", rotation.effective_from),
+            None => ("key_rotation_complete", completed_from.unwrap_or(0)),
+        };
+        WatchItem::Rotation {
+            event: RotationEvent {
+                event: event.to_string(),
+                session: self.target.session.clone(),
+                peer: self.target.peer.clone(),
+                effective_from,
+                key: match (&self.target.session, &self.target.rotation) {
+                    (None, Some(_)) => Some(self.target.key.clone()),
+                    _ => None,
+                },
+                timestamp,
+            },
+            target: self.target.clone(),
+        }
+    }
+
+    fn moved(&self, event: &str, previous_peer: Option<String>, timestamp: i64) -> WatchItem {
+        WatchItem::Moved {
+            event: MovedEvent {
+                event: event.to_string(),
+                session: self.target.session.clone(),
+                peer: self.target.peer.clone(),
+                previous_peer,
+                timestamp,
+            },
+            target: self.target.clone(),
+        }
+    }
+
+    fn presence(&self, presence: Presence, timestamp: i64) -> WatchItem {
+        WatchItem::Presence(PresenceEvent {
+            event: "presence".to_string(),
+            session: self.target.session.clone(),
+            peer: self.target.peer.clone(),
+            last_active: presence.last_active,
+            typing: presence.typing,
+            timestamp,
+        })
+    }
+
+    fn error_output(&self, error: String) -> ErrorOutput {
+        ErrorOutput {
+            error,
+            session: self.target.session.clone(),
+            peer: Some(self.target.peer.clone()),
+        }
+    }
+
+    fn heartbeat(&self, interval_ms: u64) -> WatchItem {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0);
+        WatchItem::Heartbeat(HeartbeatEvent {
+            event: "heartbeat".to_string(),
+            session: self.target.session.clone(),
+            peer: self.target.peer.clone(),
+            interval_ms,
+            timestamp,
+        })
+    }
+
+    /// Whether `msg` is new, advancing our ack past it if so.
+    fn accept(&mut self, msg: &PkarrMessage) -> bool {
+        let is_new = match (&msg.id, msg.seq) {
+            (Some(id), _) => self.seen_ids.insert(id),
+            (None, Some(seq)) => seq > self.ack.seq.unwrap_or(0),
+            (None, None) => msg.timestamp > self.ack.ts,
+        };
+        if is_new {
+            self.ack.ts = self.ack.ts.max(msg.timestamp);
+            self.ack.seq = self.ack.seq.max(msg.seq);
+            self.ack.id = msg.id.clone();
+        }
+        is_new
+    }
+
+    async fn poll(
+        &mut self,
+        client: &GhostClient,
+        options: &WatchOptions,
+        events: &mpsc::UnboundedSender<WatchItem>,
+    ) -> Result<bool, String> {
+        if let Some(pending) = self.pending_ack.take() {
+            self.publish_ack(client, pending.peer_batch).await?;
+        }
+
+        let batch = client
+            .resolve_changed(&self.target.peer, &self.recv_key)
+            .await?;
+        let batch = match batch {
+            Some(b) => b,
+            None => return Ok(false),
+        };
+
+        if let Some(error) = auth_failure(&batch.record_status, options.strict) {
+            let _ = events.send(WatchItem::AuthFailed(self.error_output(error)));
+        }
+
+        if let Some(keypair) = &self.keypair {
+            let previous_peer = self.target.peer.clone();
+            match apply_migration(&mut self.target.peer, &mut self.target.migration, &batch) {
+                Some(event) if previous_peer != self.target.peer => {
+                    (self.send_key, self.recv_key) = Self::keys(&self.target, Some(keypair));
+                    let _ =
+                        events.send(self.moved(event, Some(previous_peer), batch.packet_timestamp));
+                    // Publish under the new address right away so the peer
+                    // can finish its migration.
+                    if options.ack {
+                        self.publish_ack(client, None).await?;
+                    }
+                    return Ok(true);
+                }
+                Some(event) => {
+                    (self.send_key, self.recv_key) = Self::keys(&self.target, Some(keypair));
+                    let _ = events.send(self.moved(event, None, batch.packet_timestamp));
+                }
+                None => {}
+            }
+        }
+
+        let pending_from = self.target.rotation.as_ref().map(|r| r.effective_from);
+        if self.keypair.is_some()
+            && apply_rotation(&mut self.target.key, &mut self.target.rotation, &batch)
+        {
+            (self.send_key, self.recv_key) = Self::keys(&self.target, self.keypair.as_ref());
+            let _ = events.send(self.rotation(pending_from, batch.packet_timestamp));
+        }
+
+        if let Some(presence) = batch.presence {
+            if self.presence != Some(presence) {
+                self.presence = Some(presence);
+                let _ = events.send(self.presence(presence, batch.packet_timestamp));
+            }
+        }
+
+        let mut got_new = false;
+        for msg in batch.messages.iter().cloned() {
+            if !self.accept(&msg) {
+                continue;
+            }
+            got_new = true;
+
+            let event = WatchEvent {
+                session: self.target.session.clone(),
+                peer: self.target.peer.clone(),
+                from: "peer".to_string(),
+                text: msg.text,
+                timestamp: msg.timestamp,
+                nick: msg.nick,
+                id: msg.id,
+                seq: msg.seq,
+                clock_skew_ms: batch.clock_skew_ms,
+            };
+            let _ = events.send(WatchItem::Message(event));
+        }
+
+        if options.ack && got_new && self.keypair.is_some() {
+            self.publish_ack(client, Some(batch)).await?;
+        }
+
+        Ok(got_new)
+    }
+
+    /// Publishes our outbox with the current ack, keeping it as pending when
+    /// the publish fails.
+    async fn publish_ack(
+        &mut self,
+        client: &GhostClient,
+        peer_batch: Option<ResolvedBatch>,
+    ) -> Result<(), String> {
+        let Some(keypair) = &self.keypair else {
+            return Ok(());
+        };
+        // Rotations and migrations pick their own keys; otherwise seal in
+        // the best version the peer advertises.
+        let send_key = match (&self.target.rotation, &self.target.migration, &peer_batch) {
+            (None, None, Some(batch)) => negotiated_send_key(
+                &self.target.key,
+                &keypair.to_z32(),
+                &self.target.peer,
+                Some(batch),
+            ),
+            _ => self.send_key.clone(),
+        };
+        let result = client
+            .publish_outbox(
+                keypair,
+                &send_key,
+                peer_batch.as_ref(),
+                &self.ack,
+                None,
+                self.target.nick.as_deref(),
+            )
+            .await;
+        if result.is_err() {
+            self.pending_ack = Some(PendingAck { peer_batch });
+        }
+        result.map(|_| ())
+    }
+}
+
+pub fn spawn_watch(
+    client: GhostClient,
+    targets: Vec<WatchTarget>,
+    options: WatchOptions,
+) -> mpsc::UnboundedReceiver<WatchItem> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let limiter = Arc::new(Semaphore::new(options.concurrency.max(1)));
+
+    for target in targets {
+        let client = client.clone();
+        let options = options.clone();
+        let limiter = limiter.clone();
+        let tx = tx.clone();
+
+        tokio::spawn(async move {
+            let mut peer = PeerWatch::new(target);
+            let mut schedule = PollSchedule::new(options.poll_interval, options.max_poll_interval);
+            let mut last_heartbeat = Instant::now();
+
+            loop {
+                let result = {
+                    let _permit = limiter.acquire().await.expect("watch limiter closed");
+                    peer.poll(&client, &options, &tx).await
+                };
+
+                match result {
+                    Ok(true) => schedule.on_activity(),
+                    Ok(false) => schedule.on_idle(),
+                    Err(e) => {
+                        let _ = tx.send(WatchItem::Error(peer.error_output(e)));
+                        schedule.on_error();
+                    }
+                }
+
+                if let Some(every) = options.heartbeat {
+                    if last_heartbeat.elapsed() >= Duration::from_millis(every) {
+                        last_heartbeat = Instant::now();
+                        let _ = tx.send(peer.heartbeat(schedule.interval()));
+                    }
+                }
+
+                if tx.is_closed() {
+                    return;
+                }
+                tokio::time::sleep(schedule.next_delay()).await;
+            }
+        });
+    }
+
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str, seq: u64) -> PkarrMessage {
+        PkarrMessage {
+            text: id.to_string(),
+            timestamp: seq as i64,
+            nick: None,
+            id: Some(id.to_string()),
+            seq: Some(seq),
+            rotation: None,
+            attachment: None,
+            kind: None,
+        }
+    }
+
+    fn new_ids(watch: &mut PeerWatch, batch: &[PkarrMessage]) -> Vec<String> {
+        batch
+            .iter()
+            .filter(|m| watch.accept(m))
+            .filter_map(|m| m.id.clone())
+            .collect()
+    }
+
+    #[test]
+    fn empty_or_failed_batches_do_not_reset_dedup() {
+        let mut watch = PeerWatch::new(WatchTarget {
+            session: None,
+            seed: None,
+            peer: "peer".to_string(),
+            key: crate::crypto::generate_key(),
+            nick: None,
+            rotation: None,
+            migration: None,
+        });
+        let (a, b, c) = (message("a", 1), message("b", 2), message("c", 3));
+
+        assert_eq!(new_ids(&mut watch, &[a.clone(), b.clone()]), ["a", "b"]);
+        // An auth-failed or trimmed packet decodes to fewer messages.
+        assert!(new_ids(&mut watch, &[]).is_empty());
+        assert!(new_ids(&mut watch, std::slice::from_ref(&b)).is_empty());
+        assert_eq!(new_ids(&mut watch, &[a, b, c]), ["c"]);
+    }
+
+    #[test]
+    fn seen_ids_are_bounded() {
+        let mut seen = SeenIds::default();
+        for i in 0..MAX_SEEN_IDS + 10 {
+            assert!(seen.insert(&i.to_string()));
+        }
+        assert_eq!(seen.ids.len(), MAX_SEEN_IDS);
+        assert!(!seen.insert(&(MAX_SEEN_IDS + 9).to_string()));
+        assert!(seen.insert("0"));
+    }
+}
@@ -1,10 +1,15 @@
 use pkarr::{Client, Keypair};
 use std::env;
 use tauri::State;
+use zeroize::Zeroizing;
 
-use crate::crypto;
+use crate::crypto::{self, PayloadKey, SecretSeed, SharedKey};
+use crate::invite_code;
 use crate::pkarr_client;
-use crate::types::{CompactMessage, KeypairResult, ResolvedBatch};
+use crate::types::{
+    CompactMessage, KeyRotation, KeypairResult, Padding, PeerProfile, Presence, ResolvedBatch,
+    SafetyNumber,
+};
 
 pub struct AppState {
     pub pkarr_client: Client,
@@ -18,8 +23,7 @@ pub fn get_profile() -> String {
 #[tauri::command]
 pub fn create_keypair() -> Result<KeypairResult, String> {
     let keypair = Keypair::random();
-    let secret = keypair.secret_key();
-    let seed_b64 = crypto::to_base64_url(secret.as_ref());
+    let seed_b64 = SecretSeed::from_bytes(keypair.secret_key());
     let pub_key_z32 = keypair.to_z32();
 
     Ok(KeypairResult {
@@ -29,59 +33,148 @@ pub fn create_keypair() -> Result<KeypairResult, String> {
 }
 
 #[tauri::command]
-pub fn get_public_key(seed_b64: String) -> Result<String, String> {
-    let seed_bytes = crypto::from_base64_url(&seed_b64)?;
-    let seed: [u8; 32] = seed_bytes
-        .try_into()
-        .map_err(|_| "Seed must be exactly 32 bytes")?;
-    let keypair = Keypair::from_secret_key(&seed);
+pub fn get_public_key(seed_b64: SecretSeed) -> Result<String, String> {
+    let keypair = Keypair::from_secret_key(seed_b64.as_bytes());
     Ok(keypair.to_z32())
 }
 
 #[tauri::command]
 pub fn generate_enc_key() -> String {
-    let key = crypto::generate_key();
-    crypto::to_base64_url(&key)
+    crypto::generate_key().to_base64_url()
 }
 
+/// Fresh key announcement for a chat; the caller sends it as the `r` field of
+/// a message under the current key.
 #[tauri::command]
-pub fn encrypt_text(plaintext: String, key_b64: String) -> Result<String, String> {
-    let key_bytes = crypto::from_base64_url(&key_b64)?;
-    crypto::encrypt(&plaintext, &key_bytes)
+pub fn rotate_key() -> Result<KeyRotation, String> {
+    let effective_from = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| format!("Time error: {}", e))?
+        .as_millis() as i64;
+    Ok(KeyRotation {
+        key: crypto::generate_key(),
+        effective_from,
+    })
+}
+
+/// Moves a chat to a fresh keypair: the old pubkey is left with a `_moved`
+/// record pointing at the new one. The caller republishes its outbox under
+/// the returned seed.
+#[tauri::command]
+pub async fn migrate_identity(
+    state: State<'_, AppState>,
+    seed_b64: SecretSeed,
+    peer_pubkey_z32: String,
+    enc_key_b64: SharedKey,
+) -> Result<KeypairResult, String> {
+    let old = Keypair::from_secret_key(seed_b64.as_bytes());
+    let new = Keypair::random();
+    let key = PayloadKey::directed(&enc_key_b64, &old.to_z32(), &peer_pubkey_z32);
+    let moved_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| format!("Time error: {}", e))?
+        .as_millis() as i64;
+
+    pkarr_client::publish_moved(&state.pkarr_client, &old, &new, &key, moved_at).await?;
+
+    Ok(KeypairResult {
+        seed_b64: SecretSeed::from_bytes(new.secret_key()),
+        pub_key_z32: new.to_z32(),
+    })
+}
+
+#[tauri::command]
+pub fn safety_number(
+    seed_b64: SecretSeed,
+    peer_pubkey_z32: String,
+    enc_key_b64: SharedKey,
+) -> SafetyNumber {
+    let keypair = Keypair::from_secret_key(seed_b64.as_bytes());
+    let (digits, emoji) = crypto::safety_number(&enc_key_b64, &keypair.to_z32(), &peer_pubkey_z32);
+    SafetyNumber { digits, emoji }
 }
 
+/// Wraps a chat key under a passphrase for a password-protected invite code.
 #[tauri::command]
-pub fn decrypt_text(encoded: String, key_b64: String) -> Result<String, String> {
-    let key_bytes = crypto::from_base64_url(&key_b64)?;
-    crypto::decrypt(&encoded, &key_bytes)
+pub fn wrap_invite_key(
+    enc_key_b64: SharedKey,
+    passphrase: String,
+    inviter_pubkey_z32: String,
+) -> Result<String, String> {
+    let passphrase = Zeroizing::new(passphrase);
+    crypto::wrap_key(&enc_key_b64, &passphrase, &inviter_pubkey_z32)
 }
 
 #[tauri::command]
+pub fn unwrap_invite_key(
+    wrapped: String,
+    passphrase: String,
+    inviter_pubkey_z32: String,
+) -> Result<SharedKey, String> {
+    let passphrase = Zeroizing::new(passphrase);
+    crypto::unwrap_key(&wrapped, &passphrase, &inviter_pubkey_z32)
+}
+
+/// Packs an invite code into its compact `ghost1...` form.
+#[tauri::command]
+pub fn encode_invite_code(code: String) -> Result<String, String> {
+    invite_code::encode(&code)
+}
+
+#[tauri::command]
+pub fn decode_invite_code(code: String) -> Result<String, String> {
+    invite_code::decode(&code)
+}
+
+#[tauri::command]
+pub fn invite_qr_svg(code: String) -> Result<String, String> {
+    invite_code::qr_svg(&code)
+}
+
+#[tauri::command]
+pub fn encrypt_text(plaintext: String, key_b64: SharedKey) -> Result<String, String> {
+    crypto::encrypt(&plaintext, &key_b64)
+}
+
+#[tauri::command]
+pub fn decrypt_text(encoded: String, key_b64: SharedKey) -> Result<String, String> {
+    crypto::decrypt(&encoded, &key_b64)
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn publish_messages(
     state: State<'_, AppState>,
-    seed_b64: String,
+    seed_b64: SecretSeed,
+    peer_pubkey_z32: String,
     messages: Vec<CompactMessage>,
-    enc_key_b64: String,
+    enc_key_b64: SharedKey,
     ack_timestamp: i64,
     nick: Option<String>,
     call_signal: Option<String>,
+    presence: Option<Presence>,
+    profile: Option<PeerProfile>,
+    padding: Option<Padding>,
+    peer_version: Option<u32>,
 ) -> Result<usize, String> {
-    let seed_bytes = crypto::from_base64_url(&seed_b64)?;
-    let seed: [u8; 32] = seed_bytes
-        .try_into()
-        .map_err(|_| "Seed must be exactly 32 bytes")?;
-    let keypair = Keypair::from_secret_key(&seed);
-
-    let enc_key = crypto::from_base64_url(&enc_key_b64)?;
+    let keypair = Keypair::from_secret_key(seed_b64.as_bytes());
+    let key = match pkarr_client::negotiated_version(peer_version) {
+        pkarr_client::LEGACY_VERSION => PayloadKey::legacy(&enc_key_b64),
+        pkarr_client::BASELINE_VERSION => PayloadKey::shared(&enc_key_b64),
+        _ => PayloadKey::directed(&enc_key_b64, &keypair.to_z32(), &peer_pubkey_z32),
+    };
 
     pkarr_client::publish_messages(
         &state.pkarr_client,
         &keypair,
         &messages,
-        &enc_key,
+        &key,
         ack_timestamp,
         nick.as_deref(),
         call_signal.as_deref(),
+        presence.as_ref(),
+        profile.as_ref(),
+        padding.unwrap_or_default(),
     )
     .await
 }
@@ -90,9 +183,22 @@ pub async fn publish_messages(
 pub async fn resolve_messages(
     state: State<'_, AppState>,
     public_key_z32: String,
-    enc_key_b64: String,
+    enc_key_b64: SharedKey,
+    receiver_z32: Option<String>,
+    fallback_enc_key_b64: Option<SharedKey>,
+    fallback_receiver_z32: Option<String>,
 ) -> Result<Option<ResolvedBatch>, String> {
-    let enc_key = crypto::from_base64_url(&enc_key_b64)?;
+    let payload_key = |shared: &SharedKey, receiver: Option<&String>| match receiver {
+        Some(receiver) => PayloadKey::directed(shared, &public_key_z32, receiver),
+        None => PayloadKey::shared(shared),
+    };
+    let mut key = payload_key(&enc_key_b64, receiver_z32.as_ref());
+    if let Some(fallback) = &fallback_enc_key_b64 {
+        key = key.with_fallback(payload_key(fallback, receiver_z32.as_ref()));
+    }
+    if let Some(receiver) = &fallback_receiver_z32 {
+        key = key.with_fallback(payload_key(&enc_key_b64, Some(receiver)));
+    }
 
-    pkarr_client::resolve_messages(&state.pkarr_client, &public_key_z32, &enc_key).await
+    pkarr_client::resolve_messages(&state.pkarr_client, &public_key_z32, &key).await
 }
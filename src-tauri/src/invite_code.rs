@@ -0,0 +1,112 @@
+use bech32::primitives::decode::CheckedHrpstring;
+use bech32::{Bech32m, Hrp};
+use pkarr::PublicKey;
+use qrcode::render::svg;
+use qrcode::QrCode;
+use zeroize::Zeroizing;
+
+use crate::crypto::{from_base64_url, to_base64_url, SecretSeed, WRAPPED_KEY_LENGTH};
+
+// Same layout as the CLI's invite codes: version, flags, pubkey (32), key (32,
+// or the wrapped key when FLAG_PASSWORD), then the invitee seed (32). Desktop
+// codes always carry the seed; token and expiry are CLI-only.
+const INVITE_CODE_HRP: &str = "ghost";
+const INVITE_CODE_VERSION: u8 = 1;
+const FLAG_SEED: u8 = 1 << 0;
+const FLAG_TOKEN: u8 = 1 << 1;
+const FLAG_EXPIRY: u8 = 1 << 2;
+const FLAG_PASSWORD: u8 = 1 << 4;
+const PROTECTED_KEY_PREFIX: &str = "pw.";
+
+/// Packs a `seedB/pubKeyA/key` invite code into a `ghost1...` string.
+pub fn encode(invite_code: &str) -> Result<String, String> {
+    let parts: Vec<&str> = invite_code.split('/').collect();
+    let [seed_b64, pubkey_z32, key] = parts[..] else {
+        return Err("Invalid invite code".to_string());
+    };
+    let seed = SecretSeed::from_base64_url(seed_b64)?;
+    let pubkey =
+        PublicKey::try_from(pubkey_z32).map_err(|e| format!("Invalid public key: {}", e))?;
+
+    let mut flags = FLAG_SEED;
+    let key = Zeroizing::new(match key.strip_prefix(PROTECTED_KEY_PREFIX) {
+        Some(wrapped) => {
+            flags |= FLAG_PASSWORD;
+            from_base64_url(wrapped)?
+        }
+        None => from_base64_url(key)?,
+    });
+    let key_length = if flags & FLAG_PASSWORD != 0 {
+        WRAPPED_KEY_LENGTH
+    } else {
+        32
+    };
+    if key.len() != key_length {
+        return Err("Invalid invite key length".to_string());
+    }
+
+    let mut data = Zeroizing::new(vec![INVITE_CODE_VERSION, flags]);
+    data.extend_from_slice(pubkey.as_bytes());
+    data.extend_from_slice(&key);
+    data.extend_from_slice(seed.as_bytes());
+
+    let hrp = Hrp::parse(INVITE_CODE_HRP).map_err(|e| format!("Bech32 error: {}", e))?;
+    bech32::encode::<Bech32m>(hrp, &data).map_err(|e| format!("Bech32 error: {}", e))
+}
+
+/// Unpacks a `ghost1...` string into a `seedB/pubKeyA/key` invite code.
+pub fn decode(code: &str) -> Result<String, String> {
+    let checked = CheckedHrpstring::new::<Bech32m>(code.trim())
+        .map_err(|e| format!("Invalid invite code: {}", e))?;
+    if !checked.hrp().as_str().eq_ignore_ascii_case(INVITE_CODE_HRP) {
+        return Err("Invalid invite code: not a ghostly invite".to_string());
+    }
+    let data = Zeroizing::new(checked.byte_iter().collect::<Vec<u8>>());
+
+    let (&version, rest) = data.split_first().ok_or("Invalid invite code: empty")?;
+    if version != INVITE_CODE_VERSION {
+        return Err(format!("Unsupported invite code version: {}", version));
+    }
+    let (&flags, rest) = rest.split_first().ok_or("Invalid invite code: truncated")?;
+    if flags & (FLAG_TOKEN | FLAG_EXPIRY) != 0 || flags & FLAG_SEED == 0 {
+        return Err("This invite needs ghostly-cli to join".to_string());
+    }
+    let key_length = if flags & FLAG_PASSWORD != 0 {
+        WRAPPED_KEY_LENGTH
+    } else {
+        32
+    };
+    if rest.len() != 32 + key_length + 32 {
+        return Err("Invalid invite code: bad length".to_string());
+    }
+    let (pubkey_bytes, rest) = rest.split_at(32);
+    let (key, seed) = rest.split_at(key_length);
+
+    let pubkey_bytes: [u8; 32] = pubkey_bytes.try_into().unwrap();
+    let pubkey =
+        PublicKey::try_from(&pubkey_bytes).map_err(|e| format!("Invalid public key: {}", e))?;
+    let prefix = if flags & FLAG_PASSWORD != 0 {
+        PROTECTED_KEY_PREFIX
+    } else {
+        ""
+    };
+    Ok(format!(
+        "{}/{}/{}{}",
+        to_base64_url(seed),
+        pubkey.to_z32(),
+        prefix,
+        to_base64_url(key)
+    ))
+}
+
+/// Renders `code` as an SVG QR code.
+pub fn qr_svg(code: &str) -> Result<String, String> {
+    // Upper case fits QR's alphanumeric mode, which is denser than bytes.
+    let qr = QrCode::new(code.to_ascii_uppercase()).map_err(|e| format!("QR error: {}", e))?;
+    Ok(qr
+        .render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .dark_color(svg::Color("#0b0f1a"))
+        .light_color(svg::Color("#ffffff"))
+        .build())
+}
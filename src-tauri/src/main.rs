@@ -2,6 +2,7 @@
 
 mod commands;
 mod crypto;
+mod invite_code;
 mod pkarr_client;
 mod types;
 
@@ -22,6 +23,14 @@ fn main() {
             commands::create_keypair,
             commands::get_public_key,
             commands::generate_enc_key,
+            commands::rotate_key,
+            commands::migrate_identity,
+            commands::safety_number,
+            commands::wrap_invite_key,
+            commands::unwrap_invite_key,
+            commands::encode_invite_code,
+            commands::decode_invite_code,
+            commands::invite_qr_svg,
             commands::encrypt_text,
             commands::decrypt_text,
             commands::publish_messages,
@@ -1,26 +1,200 @@
-use pkarr::{Client, Keypair, PublicKey, SignedPacket};
+use pkarr::{Client, Keypair, PublicKey, SignedPacket, SignedPacketBuilder};
+use serde::{Deserialize, Serialize};
 use simple_dns::rdata::RData;
 
-use crate::crypto;
-use crate::types::{CompactMessage, PkarrMessage, ResolvedBatch};
+use crate::crypto::{self, PayloadKey};
+use crate::types::{
+    CompactMessage, Padding, PeerCapabilities, PeerProfile, PkarrMessage, Presence, RecordStatus,
+    ResolvedBatch,
+};
+use std::collections::BTreeMap;
 
 const MAX_MSGS_PAYLOAD_B64: usize = 800;
+const CLOCK_SKEW_TOLERANCE_MS: i64 = 30_000;
+const PACKET_BUDGET: usize = 920;
+const RECORD_OVERHEAD: usize = 20;
+const MIN_PAD_BUCKET: usize = 32;
+const NICK_PAD_LENGTH: usize = 32;
+const PRESENCE_PAD_LENGTH: usize = 32;
+const MAX_PROFILE_LENGTH: usize = 200;
+/// Advertised in `_v`: v2 sealing and the features the desktop understands.
+const CAPABILITIES_RECORD: &str = "2 kinds presence profile calls";
+/// Highest payload sealing version we write (v2: per-direction keys).
+const PROTOCOL_VERSION: u32 = 2;
+/// Sealing version for peers whose packet has no `_v`: released clients from
+/// before it only open the unbound secretbox.
+pub const LEGACY_VERSION: u32 = 0;
+/// Sealing version for peers we haven't seen a packet from yet. The outbox is
+/// resealed on every publish, so an old client gets v0 once it shows up.
+pub const BASELINE_VERSION: u32 = 1;
+
+/// Sealing version to use with a peer that advertised `peer_version` (0 when
+/// its packet has no `_v`, `None` before we've seen a packet).
+pub fn negotiated_version(peer_version: Option<u32>) -> u32 {
+    peer_version.map_or(BASELINE_VERSION, |v| v.min(PROTOCOL_VERSION))
+}
+
+/// Largest plaintext whose sealed, base64-encoded form fits in `max_payload`.
+fn max_plaintext(max_payload: usize) -> usize {
+    (max_payload / 4 * 3).saturating_sub(crypto::SEAL_OVERHEAD)
+}
+
+/// Pads with trailing spaces, which JSON parsers ignore and nicks are trimmed of.
+fn pad(plaintext: &str, padding: Padding, max_len: usize) -> String {
+    let target = match padding {
+        Padding::Off => 0,
+        Padding::Bucketed => plaintext
+            .len()
+            .max(MIN_PAD_BUCKET)
+            .next_power_of_two()
+            .min(max_len),
+        Padding::Full => max_len,
+    };
+    let mut padded = plaintext.to_string();
+    padded.extend(std::iter::repeat_n(
+        ' ',
+        target.saturating_sub(plaintext.len()),
+    ));
+    padded
+}
+
+fn add_txt(
+    builder: SignedPacketBuilder,
+    name: &str,
+    value: &str,
+) -> Result<SignedPacketBuilder, String> {
+    Ok(builder.txt(
+        name.try_into().map_err(|e| format!("Name error: {}", e))?,
+        value.try_into().map_err(|e| format!("TXT error: {}", e))?,
+        300,
+    ))
+}
+
+fn seal_record(
+    label: &str,
+    plaintext: &str,
+    key: &PayloadKey,
+    sender_z32: &str,
+) -> Result<String, String> {
+    key.encrypt(plaintext, &crypto::record_ad(label, sender_z32))
+}
+
+fn open_record(
+    label: &str,
+    encoded: &str,
+    key: &PayloadKey,
+    sender_z32: &str,
+) -> Result<crypto::Decrypted, String> {
+    key.decrypt(encoded, &crypto::record_ad(label, sender_z32))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MovedRecord {
+    p: String,
+    t: i64,
+    s: String,
+}
+
+fn moved_statement(old_z32: &str, new_z32: &str, moved_at: i64) -> Vec<u8> {
+    format!("ghostly/moved/{}/{}/{}", old_z32, new_z32, moved_at).into_bytes()
+}
+
+/// Like [`open_record`] for records pre-v1 clients published without
+/// associated data (`_msgs`, `_nick`, `_call`). The unbound form is only
+/// accepted from `legacy_peer`s, which predate `_v`.
+fn open_legacy_record(
+    label: &str,
+    encoded: &str,
+    key: &PayloadKey,
+    sender_z32: &str,
+    legacy_peer: bool,
+) -> Result<crypto::Decrypted, RecordStatus> {
+    let ad = crypto::record_ad(label, sender_z32);
+    let opened = if legacy_peer {
+        key.decrypt_allowing_unbound(encoded, &ad)
+    } else {
+        key.decrypt(encoded, &ad)
+    };
+    opened.map_err(|_| RecordStatus::AuthFailed)
+}
+
+/// `LegacyUnbound` for payloads let through by the pre-v1 fallback.
+fn opened_status(bound: bool) -> RecordStatus {
+    if bound {
+        RecordStatus::Ok
+    } else {
+        RecordStatus::LegacyUnbound
+    }
+}
+
+/// Opens a `_moved` record, returning the new pubkey only when the new key
+/// signed off on the move (the old key signs the packet itself).
+fn open_moved(encoded: &str, key: &PayloadKey, sender_z32: &str) -> Result<String, RecordStatus> {
+    let decrypted =
+        open_record("_moved", encoded, key, sender_z32).map_err(|_| RecordStatus::AuthFailed)?;
+    if !decrypted.bound {
+        return Err(RecordStatus::AuthFailed);
+    }
+    let record: MovedRecord =
+        serde_json::from_str(&decrypted.plaintext).map_err(|_| RecordStatus::Malformed)?;
+    let new_key: PublicKey = record
+        .p
+        .as_str()
+        .try_into()
+        .map_err(|_| RecordStatus::Malformed)?;
+    let signature = crypto::from_base64_url(&record.s).map_err(|_| RecordStatus::Malformed)?;
+    let signature = signature
+        .as_slice()
+        .try_into()
+        .map_err(|_| RecordStatus::Malformed)?;
+    new_key
+        .verify(
+            &moved_statement(sender_z32, &record.p, record.t),
+            &signature,
+        )
+        .map_err(|_| RecordStatus::AuthFailed)?;
+    Ok(record.p)
+}
+
+fn txt_value(signed_packet: &SignedPacket, label: &str) -> Option<String> {
+    signed_packet.all_resource_records().find_map(|record| {
+        let name_str = record.name.to_string();
+        let record_label = name_str.trim_end_matches('.').split('.').next()?;
+        match &record.rdata {
+            RData::TXT(txt) if record_label == label => String::try_from(txt.clone()).ok(),
+            _ => None,
+        }
+    })
+}
+
+fn parse_capabilities(value: &str) -> Option<PeerCapabilities> {
+    let mut parts = value.split_whitespace();
+    let version = parts.next()?.parse().ok()?;
+    Some(PeerCapabilities {
+        version,
+        features: parts.map(str::to_string).collect(),
+    })
+}
 
 fn trim_to_fit(
     messages: &[CompactMessage],
-    enc_key: &[u8],
+    key: &PayloadKey,
+    sender_z32: &str,
     max_payload: usize,
+    padding: Padding,
 ) -> Result<(String, usize), String> {
+    let max_len = max_plaintext(max_payload);
+    let seal = |json: &str| seal_record("_msgs", &pad(json, padding, max_len), key, sender_z32);
+
     if messages.is_empty() {
-        let encrypted = crypto::encrypt("[]", enc_key)?;
-        return Ok((encrypted, 0));
+        return Ok((seal("[]")?, 0));
     }
 
     let mut batch: Vec<&CompactMessage> = messages.iter().collect();
 
     while !batch.is_empty() {
         let json = serde_json::to_string(&batch).map_err(|e| format!("JSON serialize: {}", e))?;
-        let encrypted = crypto::encrypt(&json, enc_key)?;
+        let encrypted = seal(&json)?;
         if encrypted.len() <= max_payload {
             return Ok((encrypted, batch.len()));
         }
@@ -30,100 +204,89 @@ fn trim_to_fit(
             let truncated = CompactMessage {
                 t: msg.t,
                 m: max_text,
+                i: msg.i.clone(),
+                s: msg.s,
+                r: msg.r.clone(),
+                k: msg.k.clone(),
             };
             let json =
                 serde_json::to_string(&vec![&truncated]).map_err(|e| format!("JSON: {}", e))?;
-            let encrypted = crypto::encrypt(&json, enc_key)?;
-            return Ok((encrypted, 1));
+            return Ok((seal(&json)?, 1));
         }
         batch.remove(0);
     }
 
-    let encrypted = crypto::encrypt("[]", enc_key)?;
-    Ok((encrypted, 0))
+    Ok((seal("[]")?, 0))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn publish_messages(
     client: &Client,
     keypair: &Keypair,
     messages: &[CompactMessage],
-    enc_key: &[u8],
+    key: &PayloadKey,
     ack_timestamp: i64,
     nick: Option<&str>,
     call_signal: Option<&str>,
+    presence: Option<&Presence>,
+    profile: Option<&PeerProfile>,
+    padding: Padding,
 ) -> Result<usize, String> {
     let mut sorted = messages.to_vec();
-    sorted.sort_by_key(|m| m.t);
+    sorted.sort_by_key(|m| (m.s.is_some(), m.s, m.t));
 
-    let (payload, kept) = trim_to_fit(&sorted, enc_key, MAX_MSGS_PAYLOAD_B64)?;
-
-    let mut builder = SignedPacket::builder();
-
-    builder = builder.txt(
-        "_msgs"
-            .try_into()
-            .map_err(|e| format!("Name error: {}", e))?,
-        payload
-            .as_str()
-            .try_into()
-            .map_err(|e| format!("TXT error: {}", e))?,
-        300,
-    );
-
-    let latest_ts = sorted
-        .last()
-        .map(|m| m.t.to_string())
-        .unwrap_or_else(|| "0".to_string());
-    builder = builder.txt(
-        "_ts".try_into().map_err(|e| format!("Name error: {}", e))?,
-        latest_ts
-            .as_str()
-            .try_into()
-            .map_err(|e| format!("TXT error: {}", e))?,
-        300,
-    );
+    let sender_z32 = keypair.to_z32();
+    let mut records: Vec<(&str, String)> = Vec::new();
 
+    let latest_ts = sorted.last().map(|m| m.t).unwrap_or(0);
+    records.push(("_ts", latest_ts.to_string()));
+    records.push(("_v", CAPABILITIES_RECORD.to_string()));
     if ack_timestamp > 0 {
-        let ack_str = ack_timestamp.to_string();
-        builder = builder.txt(
-            "_ack"
-                .try_into()
-                .map_err(|e| format!("Name error: {}", e))?,
-            ack_str
-                .as_str()
-                .try_into()
-                .map_err(|e| format!("TXT error: {}", e))?,
-            300,
-        );
+        records.push(("_ack", ack_timestamp.to_string()));
     }
-
     if let Some(nick_str) = nick {
-        let encrypted_nick = crypto::encrypt(nick_str, enc_key)?;
-        builder = builder.txt(
-            "_nick"
-                .try_into()
-                .map_err(|e| format!("Name error: {}", e))?,
-            encrypted_nick
-                .as_str()
-                .try_into()
-                .map_err(|e| format!("TXT error: {}", e))?,
-            300,
-        );
+        let padded = pad(nick_str, padding, NICK_PAD_LENGTH);
+        records.push(("_nick", seal_record("_nick", &padded, key, &sender_z32)?));
     }
-
     if let Some(signal) = call_signal {
-        let encrypted_signal = crypto::encrypt(signal, enc_key)?;
-
-        builder = builder.txt(
-            "_call"
-                .try_into()
-                .map_err(|e| format!("Name error: {}", e))?,
-            encrypted_signal
-                .as_str()
-                .try_into()
-                .map_err(|e| format!("TXT error: {}", e))?,
-            300,
-        );
+        records.push(("_call", seal_record("_call", signal, key, &sender_z32)?));
+    }
+    if let Some(presence) = presence {
+        let json = serde_json::to_string(presence).map_err(|e| format!("JSON: {}", e))?;
+        let padded = pad(&json, padding, PRESENCE_PAD_LENGTH);
+        records.push(("_pres", seal_record("_pres", &padded, key, &sender_z32)?));
+    }
+
+    let used: usize = records
+        .iter()
+        .map(|(name, value)| name.len() + value.len() + RECORD_OVERHEAD)
+        .sum();
+    let max_payload = PACKET_BUDGET
+        .saturating_sub(used + "_msgs".len() + RECORD_OVERHEAD)
+        .min(MAX_MSGS_PAYLOAD_B64);
+    let (payload, kept) = trim_to_fit(&sorted, key, &sender_z32, max_payload, padding)?;
+
+    // Messages come first: the profile only rides along when they leave room.
+    if let Some(profile) = profile {
+        let json = serde_json::to_string(profile).map_err(|e| format!("JSON: {}", e))?;
+        if json.len() > MAX_PROFILE_LENGTH {
+            return Err(format!(
+                "Profile too large: {} bytes (max {})",
+                json.len(),
+                MAX_PROFILE_LENGTH
+            ));
+        }
+        let padded = pad(&json, padding, MAX_PROFILE_LENGTH);
+        let sealed = seal_record("_prof", &padded, key, &sender_z32)?;
+        let total = used + "_msgs".len() + payload.len() + RECORD_OVERHEAD;
+        if total + "_prof".len() + sealed.len() + RECORD_OVERHEAD <= PACKET_BUDGET {
+            records.push(("_prof", sealed));
+        }
+    }
+
+    let mut builder = add_txt(SignedPacket::builder(), "_msgs", &payload)?;
+    for (name, value) in &records {
+        builder = add_txt(builder, name, value)?;
     }
 
     let signed_packet = builder
@@ -138,10 +301,44 @@ pub async fn publish_messages(
     Ok(kept)
 }
 
+/// Replaces the packet under `old` with a single `_moved` record naming `new`.
+pub async fn publish_moved(
+    client: &Client,
+    old: &Keypair,
+    new: &Keypair,
+    key: &PayloadKey,
+    moved_at: i64,
+) -> Result<(), String> {
+    let old_z32 = old.to_z32();
+    let new_z32 = new.to_z32();
+    let signature = new.sign(&moved_statement(&old_z32, &new_z32, moved_at));
+    let record = MovedRecord {
+        p: new_z32,
+        t: moved_at,
+        s: crypto::to_base64_url(&signature.to_bytes()),
+    };
+    let json = serde_json::to_string(&record).map_err(|e| format!("JSON serialize: {}", e))?;
+
+    let builder = add_txt(SignedPacket::builder(), "_ts", &moved_at.to_string())?;
+    let builder = add_txt(
+        builder,
+        "_moved",
+        &seal_record("_moved", &json, key, &old_z32)?,
+    )?;
+    let signed_packet = builder
+        .sign(old)
+        .map_err(|e| format!("Sign error: {}", e))?;
+
+    client
+        .publish(&signed_packet, None)
+        .await
+        .map_err(|e| format!("Publish error: {}", e))
+}
+
 pub async fn resolve_messages(
     client: &Client,
     public_key_z32: &str,
-    enc_key: &[u8],
+    key: &PayloadKey,
 ) -> Result<Option<ResolvedBatch>, String> {
     let public_key: PublicKey = public_key_z32
         .try_into()
@@ -158,15 +355,27 @@ pub async fn resolve_messages(
 
     let mut nick: Option<String> = None;
     let mut encrypted_payload_length: usize = 0;
+    let mut payload_padding: usize = 0;
+    let mut fallback_key_used = false;
+    let mut moved_to: Option<String> = None;
     let mut latest_timestamp: i64 = 0;
     let mut peer_ack: i64 = 0;
+    let mut peer_ack_id: Option<String> = None;
+    let mut peer_ack_seq: Option<u64> = None;
+    let mut last_seq: u64 = 0;
     let mut raw_record_names: Vec<String> = Vec::new();
     let mut messages: Vec<PkarrMessage> = Vec::new();
     let mut call_signal: Option<String> = None;
+    let mut presence: Option<Presence> = None;
+    let mut profile: Option<PeerProfile> = None;
+    let mut capabilities: Option<PeerCapabilities> = None;
+    let mut sender_authenticated = false;
+    let mut record_status: BTreeMap<String, RecordStatus> = BTreeMap::new();
+    let sender_z32 = signed_packet.public_key().to_z32();
+    let legacy_peer = txt_value(&signed_packet, "_v").is_none();
 
-    let mut msgs_payload = String::new();
-    let mut legacy_msg = String::new();
-    let legacy_ts: i64 = 0;
+    let mut msgs_payload: Option<String> = None;
+    let mut legacy_payload: Option<String> = None;
 
     for record in signed_packet.all_resource_records() {
         let name_str = record.name.to_string();
@@ -183,14 +392,10 @@ pub async fn resolve_messages(
 
             match label {
                 "_msgs" => {
-                    encrypted_payload_length = value.len();
-                    msgs_payload = value;
+                    msgs_payload = Some(value);
                 }
                 "_msg" => {
-                    encrypted_payload_length = value.len();
-                    if let Ok(decrypted) = crypto::decrypt(&value, enc_key) {
-                        legacy_msg = decrypted;
-                    }
+                    legacy_payload = Some(value);
                 }
                 "_ts" => {
                     latest_timestamp = value.parse::<i64>().unwrap_or(0);
@@ -198,61 +403,242 @@ pub async fn resolve_messages(
                 "_ack" => {
                     peer_ack = value.parse::<i64>().unwrap_or(0);
                 }
+                "_acki" => {
+                    peer_ack_id = Some(value);
+                }
+                "_aseq" => {
+                    peer_ack_seq = value.parse::<u64>().ok();
+                }
+                "_seq" => {
+                    last_seq = value.parse::<u64>().unwrap_or(0);
+                }
                 "_nick" => {
-                    if let Ok(decrypted) = crypto::decrypt(&value, enc_key) {
-                        nick = Some(decrypted);
-                    }
+                    let opened = open_legacy_record("_nick", &value, key, &sender_z32, legacy_peer);
+                    let ok = opened_status(opened.as_ref().is_ok_and(|d| d.bound));
+                    nick = opened
+                        .map(|d| d.plaintext.trim_end().to_string())
+                        .record_as(&mut record_status, label, ok);
                 }
                 "_call" => {
-                    if let Ok(decrypted) = crypto::decrypt(&value, enc_key) {
-                        call_signal = Some(decrypted);
-                    }
+                    let opened = open_legacy_record("_call", &value, key, &sender_z32, legacy_peer);
+                    let ok = opened_status(opened.as_ref().is_ok_and(|d| d.bound));
+                    call_signal =
+                        opened
+                            .map(|d| d.plaintext)
+                            .record_as(&mut record_status, label, ok);
+                }
+                "_v" => {
+                    capabilities = parse_capabilities(&value);
+                }
+                "_prof" => {
+                    profile = open_json("_prof", &value, key, &sender_z32)
+                        .record(&mut record_status, label);
+                }
+                "_pres" => {
+                    presence = open_json("_pres", &value, key, &sender_z32)
+                        .record(&mut record_status, label);
+                }
+                "_moved" => {
+                    moved_to =
+                        open_moved(&value, key, &sender_z32).record(&mut record_status, label);
                 }
                 _ => {}
             }
         }
     }
 
-    if !msgs_payload.is_empty() {
-        if let Ok(json) = crypto::decrypt(&msgs_payload, enc_key) {
-            if let Ok(batch) = serde_json::from_str::<Vec<CompactMessage>>(&json) {
-                for entry in &batch {
-                    messages.push(PkarrMessage {
-                        text: entry.m.clone(),
-                        timestamp: entry.t,
-                        nick: nick.clone(),
-                    });
-                }
-                if latest_timestamp == 0 && !messages.is_empty() {
-                    latest_timestamp = messages.iter().map(|m| m.timestamp).max().unwrap_or(0);
-                }
+    // `_msgs` wins when a transitional client published both layouts.
+    let payload = match (msgs_payload, legacy_payload) {
+        (Some(encoded), _) => Some((PayloadVersion::Batch, encoded)),
+        (None, Some(encoded)) => Some((PayloadVersion::Legacy, encoded)),
+        (None, None) => None,
+    };
+    if let Some((version, encoded)) = payload {
+        encrypted_payload_length = encoded.len();
+        let decoded = match version {
+            PayloadVersion::Legacy if legacy_peer => {
+                decode_legacy(&encoded, key, latest_timestamp, packet_timestamp)
             }
+            // v0 is unbound by design; peers that advertise `_v` never send it.
+            PayloadVersion::Legacy => Err(RecordStatus::AuthFailed),
+            PayloadVersion::Batch => decode_batch(&encoded, key, &sender_z32, legacy_peer),
+        };
+        let ok = opened_status(decoded.as_ref().is_ok_and(|d| d.sender_authenticated));
+        if let Some(decoded) = decoded.record_as(&mut record_status, version.label(), ok) {
+            sender_authenticated = decoded.sender_authenticated;
+            fallback_key_used = decoded.fallback_key_used;
+            payload_padding = decoded.padding;
+            messages = decoded
+                .messages
+                .into_iter()
+                .map(|mut m| {
+                    m.nick = nick.clone();
+                    m
+                })
+                .collect();
         }
-    } else if !legacy_msg.is_empty() && legacy_ts > 0 {
-        messages.push(PkarrMessage {
-            text: legacy_msg,
-            timestamp: legacy_ts,
-            nick,
-        });
-        latest_timestamp = legacy_ts;
-    } else if !legacy_msg.is_empty() && latest_timestamp > 0 {
-        messages.push(PkarrMessage {
-            text: legacy_msg,
-            timestamp: latest_timestamp,
-            nick,
-        });
+    }
+    if latest_timestamp == 0 {
+        latest_timestamp = messages.iter().map(|m| m.timestamp).max().unwrap_or(0);
     }
 
     let message_count = messages.len();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0);
+    let clock_skew_ms = detect_clock_skew(latest_timestamp, packet_timestamp, now);
 
     Ok(Some(ResolvedBatch {
         messages,
         latest_timestamp,
         peer_ack,
+        peer_ack_id,
+        peer_ack_seq,
+        last_seq,
+        clock_skew_ms,
+        sender_authenticated,
         raw_record_names,
         encrypted_payload_length,
+        payload_padding,
+        fallback_key_used,
+        moved_to,
         packet_timestamp,
         message_count,
         call_signal,
+        presence,
+        profile,
+        capabilities,
+        record_status,
     }))
 }
+
+/// Message payload layouts, oldest first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PayloadVersion {
+    /// v0: one message in `_msg`, sealed without associated data, timestamped by `_ts`.
+    Legacy,
+    /// v1: a JSON batch of [`CompactMessage`]s in `_msgs`.
+    Batch,
+}
+
+impl PayloadVersion {
+    fn label(self) -> &'static str {
+        match self {
+            PayloadVersion::Legacy => "_msg",
+            PayloadVersion::Batch => "_msgs",
+        }
+    }
+}
+
+trait RecordOutcome<T> {
+    /// Notes the outcome under `label`, as `ok` on success, and keeps the
+    /// value on success.
+    fn record_as(
+        self,
+        status: &mut BTreeMap<String, RecordStatus>,
+        label: &str,
+        ok: RecordStatus,
+    ) -> Option<T>;
+
+    fn record(self, status: &mut BTreeMap<String, RecordStatus>, label: &str) -> Option<T>
+    where
+        Self: Sized,
+    {
+        self.record_as(status, label, RecordStatus::Ok)
+    }
+}
+
+impl<T> RecordOutcome<T> for Result<T, RecordStatus> {
+    fn record_as(
+        self,
+        status: &mut BTreeMap<String, RecordStatus>,
+        label: &str,
+        ok: RecordStatus,
+    ) -> Option<T> {
+        status.insert(label.to_string(), *self.as_ref().err().unwrap_or(&ok));
+        self.ok()
+    }
+}
+
+fn open_json<T: serde::de::DeserializeOwned>(
+    label: &str,
+    encoded: &str,
+    key: &PayloadKey,
+    sender_z32: &str,
+) -> Result<T, RecordStatus> {
+    let decrypted =
+        open_record(label, encoded, key, sender_z32).map_err(|_| RecordStatus::AuthFailed)?;
+    serde_json::from_str(&decrypted.plaintext).map_err(|_| RecordStatus::Malformed)
+}
+
+struct DecodedPayload {
+    messages: Vec<PkarrMessage>,
+    sender_authenticated: bool,
+    fallback_key_used: bool,
+    padding: usize,
+}
+
+/// Decodes a v0 `_msg`. Packets without `_ts` fall back to the packet's own
+/// timestamp, which the sender signed alongside the message.
+fn decode_legacy(
+    encoded: &str,
+    key: &PayloadKey,
+    ts: i64,
+    packet_timestamp: i64,
+) -> Result<DecodedPayload, RecordStatus> {
+    let text = crypto::decrypt(encoded, key.shared_key()).map_err(|_| RecordStatus::AuthFailed)?;
+    let timestamp = if ts > 0 { ts } else { packet_timestamp };
+    Ok(DecodedPayload {
+        messages: vec![PkarrMessage {
+            text,
+            timestamp,
+            nick: None,
+            id: None,
+            seq: None,
+            rotation: None,
+            kind: None,
+        }],
+        sender_authenticated: false,
+        fallback_key_used: false,
+        padding: 0,
+    })
+}
+
+fn decode_batch(
+    encoded: &str,
+    key: &PayloadKey,
+    sender_z32: &str,
+    legacy_peer: bool,
+) -> Result<DecodedPayload, RecordStatus> {
+    let decrypted = open_legacy_record("_msgs", encoded, key, sender_z32, legacy_peer)?;
+    let json = decrypted.plaintext;
+    let padding = json.len() - json.trim_end().len();
+    let batch =
+        serde_json::from_str::<Vec<CompactMessage>>(&json).map_err(|_| RecordStatus::Malformed)?;
+    Ok(DecodedPayload {
+        messages: batch
+            .into_iter()
+            .map(|entry| PkarrMessage {
+                text: entry.m,
+                timestamp: entry.t,
+                nick: None,
+                id: entry.i,
+                seq: entry.s,
+                rotation: entry.r,
+                kind: entry.k,
+            })
+            .collect(),
+        sender_authenticated: decrypted.bound,
+        fallback_key_used: decrypted.fallback,
+        padding,
+    })
+}
+
+/// Signed offset of the sender's clock from ours, judged by the newest of
+/// `_ts` and the packet timestamp against our receive time `now`. Negative
+/// values also cover packets that simply haven't been republished lately.
+fn detect_clock_skew(latest_timestamp: i64, packet_timestamp: i64, now: i64) -> Option<i64> {
+    let skew = latest_timestamp.max(packet_timestamp) - now;
+    (skew.abs() > CLOCK_SKEW_TOLERANCE_MS).then_some(skew)
+}
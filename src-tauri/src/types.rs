@@ -1,15 +1,126 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+
+use crate::crypto::{SecretSeed, SharedKey};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct KeypairResult {
-    pub seed_b64: String,
+    pub seed_b64: SecretSeed,
     pub pub_key_z32: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SafetyNumber {
+    pub digits: String,
+    pub emoji: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CompactMessage {
     pub t: i64,
     pub m: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub i: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub r: Option<KeyRotation>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "lenient_kind"
+    )]
+    pub k: Option<MessageKind>,
+}
+
+/// Marks a message as acting on an earlier one. `m` still carries a readable
+/// fallback for clients that predate kinds.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "y", rename_all = "lowercase")]
+pub enum MessageKind {
+    Reply {
+        to: MessageRef,
+    },
+    React {
+        to: MessageRef,
+        #[serde(rename = "e")]
+        emoji: String,
+    },
+    Edit {
+        to: MessageRef,
+    },
+    Delete {
+        to: MessageRef,
+    },
+}
+
+/// The message a [`MessageKind`] acts on: its id, or its timestamp for
+/// messages without one (as sent by this app).
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MessageRef {
+    #[serde(rename = "i", default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "t", default, skip_serializing_if = "Option::is_none")]
+    pub ts: Option<i64>,
+}
+
+/// Kinds this client does not know decode as `None`, leaving the message's
+/// fallback text, instead of failing the whole batch.
+fn lenient_kind<'de, D: Deserializer<'de>>(d: D) -> Result<Option<MessageKind>, D::Error> {
+    let value = serde_json::Value::deserialize(d)?;
+    Ok(serde_json::from_value(value).ok())
+}
+
+/// Control payload announcing the shared key to switch to.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeyRotation {
+    #[serde(rename = "k")]
+    pub key: SharedKey,
+    #[serde(rename = "f")]
+    pub effective_from: i64,
+}
+
+/// Liveness published in the sealed `_pres` record.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct Presence {
+    #[serde(rename = "a")]
+    pub last_active: i64,
+    #[serde(rename = "y", default, skip_serializing_if = "std::ops::Not::not")]
+    pub typing: bool,
+}
+
+/// Plaintext `_v` record: protocol version and supported features.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PeerCapabilities {
+    pub version: u32,
+    pub features: Vec<String>,
+}
+
+/// Sealed `_prof` record, same layout as the CLI's.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PeerProfile {
+    #[serde(rename = "v")]
+    pub version: u8,
+    #[serde(rename = "n", default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(rename = "s", default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(rename = "b", default, skip_serializing_if = "Option::is_none")]
+    pub avatar_blurhash: Option<String>,
+    /// Attachment manifest; kept as-is since the desktop can't fetch files yet.
+    #[serde(rename = "a", default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<serde_json::Value>,
+    #[serde(rename = "c", default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Padding {
+    Off,
+    #[default]
+    Bucketed,
+    Full,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -17,11 +128,37 @@ pub struct ResolvedBatch {
     pub messages: Vec<PkarrMessage>,
     pub latest_timestamp: i64,
     pub peer_ack: i64,
+    pub peer_ack_id: Option<String>,
+    pub peer_ack_seq: Option<u64>,
+    pub last_seq: u64,
+    pub clock_skew_ms: Option<i64>,
+    pub sender_authenticated: bool,
     pub raw_record_names: Vec<String>,
     pub encrypted_payload_length: usize,
+    pub payload_padding: usize,
+    pub fallback_key_used: bool,
+    pub moved_to: Option<String>,
     pub packet_timestamp: i64,
     pub message_count: usize,
     pub call_signal: Option<String>,
+    pub presence: Option<Presence>,
+    pub profile: Option<PeerProfile>,
+    pub capabilities: Option<PeerCapabilities>,
+    /// Decode outcome of each encrypted record, keyed by record name.
+    pub record_status: BTreeMap<String, RecordStatus>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordStatus {
+    Ok,
+    /// Wrong key, or the record was tampered with.
+    AuthFailed,
+    /// Decrypted, but the contents didn't parse.
+    Malformed,
+    /// Opened as a pre-v1 payload without associated data, so it could be
+    /// replayed or reflected. Only accepted from peers that predate `_v`.
+    LegacyUnbound,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -29,4 +166,12 @@ pub struct PkarrMessage {
     pub text: String,
     pub timestamp: i64,
     pub nick: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<KeyRotation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<MessageKind>,
 }
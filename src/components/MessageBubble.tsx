@@ -249,11 +249,19 @@ export function MessageBubble({ message, peerAck = 0 }: MessageBubbleProps) {
     );
   }
 
-  const contentType = imgError ? "text" : detectContentType(message.text);
-  const bigEmoji = contentType === "text" && isOnlyEmojis(message.text);
+  const contentType =
+    imgError || message.deleted ? "text" : detectContentType(message.text);
+  const bigEmoji =
+    contentType === "text" && !message.deleted && isOnlyEmojis(message.text);
+  const reactions = Object.values(message.reactions ?? {});
 
   const timestampEl = (
     <span className="msg-meta inline-flex items-center gap-[3px] float-right relative top-[4px] ml-[8px] select-none">
+      {message.edited && (
+        <span className="text-[11px] leading-none italic text-[hsla(0,0%,100%,0.45)]">
+          edited
+        </span>
+      )}
       <span className="text-[11px] leading-none text-[hsla(0,0%,100%,0.45)]">
         {time}
       </span>
@@ -263,7 +271,9 @@ export function MessageBubble({ message, peerAck = 0 }: MessageBubbleProps) {
 
   return (
     <div
-      className={`flex ${isMe ? "justify-end" : "justify-start"} mb-[2px] px-[63px]`}
+      className={`flex ${isMe ? "justify-end" : "justify-start"} ${
+        reactions.length > 0 ? "mb-[18px]" : "mb-[2px]"
+      } px-[63px]`}
       onDoubleClick={() => message.meta && setShowTech((v) => !v)}
     >
       <div
@@ -292,6 +302,12 @@ export function MessageBubble({ message, peerAck = 0 }: MessageBubbleProps) {
           </div>
         )}
 
+        {message.replyTo && (
+          <div className="mb-[4px] rounded-[5px] border-l-[4px] border-accent bg-[rgba(11,20,26,0.25)] px-[8px] py-[4px] text-[12.8px] leading-[17px] text-text-secondary line-clamp-2">
+            {message.replyTo.text}
+          </div>
+        )}
+
         {contentType === "image" ? (
           <div className="relative">
             <img
@@ -317,13 +333,27 @@ export function MessageBubble({ message, peerAck = 0 }: MessageBubbleProps) {
           </div>
         ) : (
           <div className="clearfix">
-            <span className="text-[14.2px] leading-[19px] wrap-break-word whitespace-pre-wrap">
-              {renderTextWithLinks(message.text)}
+            <span
+              className={`text-[14.2px] leading-[19px] wrap-break-word whitespace-pre-wrap ${
+                message.deleted ? "italic text-text-muted" : ""
+              }`}
+            >
+              {message.deleted ? message.text : renderTextWithLinks(message.text)}
             </span>
             {timestampEl}
           </div>
         )}
 
+        {reactions.length > 0 && (
+          <div
+            className={`absolute -bottom-[14px] ${isMe ? "right-[8px]" : "left-[8px]"} flex gap-[2px] rounded-full bg-[#202c33] px-[5px] py-[1px] text-[13px] leading-[18px] shadow`}
+          >
+            {reactions.map((emoji, i) => (
+              <span key={i}>{emoji}</span>
+            ))}
+          </div>
+        )}
+
         {showTech && message.meta && (
           <div className="mt-[6px] pt-[6px] border-t border-[hsla(0,0%,100%,0.08)] text-[9px] text-text-secondary space-y-[2px] animate-fade-in font-mono clear-both">
             <div>
@@ -4,6 +4,7 @@ import { GiphyPicker } from "./GiphyPicker";
 
 interface MessageInputProps {
   onSend: (text: string) => Promise<string | null>;
+  onTyping?: (typing: boolean) => void;
   disabled?: boolean;
   maxLength?: number;
 }
@@ -13,6 +14,7 @@ const TOAST_DURATION = 5_000;
 
 export function MessageInput({
   onSend,
+  onTyping,
   disabled,
   maxLength = DEFAULT_MAX,
 }: MessageInputProps) {
@@ -60,6 +62,7 @@ export function MessageInput({
     const value = e.target.value;
     if (value.length <= maxLength) {
       setText(value);
+      onTyping?.(value.trim().length > 0);
     }
     if (textareaRef.current) {
       textareaRef.current.style.height = "auto";
@@ -15,6 +15,12 @@ import {
   getInviteCode,
 } from "../lib/storage";
 import type { ChatSession } from "../lib/types";
+import {
+  expandInviteCode,
+  isCompactInviteCode,
+  isProtectedInvite,
+  unlockChatPath,
+} from "../lib/url";
 
 function playNotificationSound() {
   try {
@@ -53,6 +59,8 @@ export function Sidebar() {
   const [showNewChat, setShowNewChat] = useState(false);
   const [inviteInput, setInviteInput] = useState("");
   const [joinError, setJoinError] = useState("");
+  const [lockedChatPath, setLockedChatPath] = useState<string | null>(null);
+  const [joinPassphrase, setJoinPassphrase] = useState("");
   const [confirmDeleteId, setConfirmDeleteId] = useState<string | null>(null);
   const [confirmDeleteAll, setConfirmDeleteAll] = useState(false);
   const prevTotalMsgsRef = useRef<Record<string, number>>({});
@@ -178,6 +186,7 @@ export function Sidebar() {
         setShowNewChat(false);
         setJoinError("");
         setInviteInput("");
+        setLockedChatPath(null);
       }
     };
     if (showNewChat) {
@@ -195,21 +204,32 @@ export function Sidebar() {
     setTimeout(refreshSessions, 500);
   };
 
-  const handleJoinWithInput = (input: string) => {
+  const handleJoinWithInput = async (input: string) => {
     setJoinError("");
     const trimmed = input.trim();
     if (!trimmed) return;
 
     let chatPath: string | null = null;
 
-    try {
-      const url = new URL(trimmed);
-      const hash = url.hash;
-      if (hash && hash.includes("/chat/")) {
-        chatPath = hash.replace(/^#/, "");
+    if (isCompactInviteCode(trimmed)) {
+      try {
+        chatPath = `/chat/${await expandInviteCode(trimmed)}`;
+      } catch (e) {
+        setJoinError(String(e));
+        return;
+      }
+    }
+
+    if (!chatPath) {
+      try {
+        const url = new URL(trimmed);
+        const hash = url.hash;
+        if (hash && hash.includes("/chat/")) {
+          chatPath = hash.replace(/^#/, "");
+        }
+      } catch {
+        // not a valid URL — try other formats
       }
-    } catch {
-      // not a valid URL — try other formats
     }
 
     if (!chatPath && trimmed.includes("/chat/")) {
@@ -225,7 +245,10 @@ export function Sidebar() {
       }
     }
 
-    if (chatPath) {
+    if (chatPath && isProtectedInvite(chatPath)) {
+      setLockedChatPath(chatPath);
+      setJoinPassphrase("");
+    } else if (chatPath) {
       setShowNewChat(false);
       setInviteInput("");
       navigate(chatPath);
@@ -235,6 +258,22 @@ export function Sidebar() {
     }
   };
 
+  const handleUnlockInvite = async () => {
+    if (!lockedChatPath || !joinPassphrase) return;
+    setJoinError("");
+    try {
+      const chatPath = await unlockChatPath(lockedChatPath, joinPassphrase);
+      setLockedChatPath(null);
+      setJoinPassphrase("");
+      setShowNewChat(false);
+      setInviteInput("");
+      navigate(chatPath);
+      setTimeout(refreshSessions, 500);
+    } catch {
+      setJoinError("Wrong passphrase");
+    }
+  };
+
   const handleDelete = (sessionId: string, e: React.MouseEvent) => {
     e.stopPropagation();
     e.preventDefault();
@@ -310,6 +349,7 @@ export function Sidebar() {
             setShowNewChat(!showNewChat);
             setJoinError("");
             setInviteInput("");
+            setLockedChatPath(null);
           }}
           className="flex items-center gap-1.5 px-3 py-1.5 bg-accent text-[#111b21] rounded-lg hover:bg-accent-hover transition-colors cursor-pointer font-semibold text-sm"
           title={t("sidebar.newChat")}
@@ -343,6 +383,7 @@ export function Sidebar() {
                 setShowNewChat(false);
                 setJoinError("");
                 setInviteInput("");
+                setLockedChatPath(null);
               }}
               className="text-text-muted hover:text-text-primary transition-colors cursor-pointer text-lg leading-none"
             >
@@ -361,6 +402,7 @@ export function Sidebar() {
                 onChange={(e) => {
                   setInviteInput(e.target.value);
                   setJoinError("");
+                  setLockedChatPath(null);
                 }}
                 onPaste={(e) => {
                   e.preventDefault();
@@ -396,6 +438,34 @@ export function Sidebar() {
                 Paste
               </button>
             </div>
+            {lockedChatPath && (
+              <div className="space-y-2">
+                <p className="text-text-muted text-xs">
+                  This invite is protected. Enter the passphrase you were given:
+                </p>
+                <div className="flex items-center bg-input-bg rounded-lg overflow-hidden border border-border focus-within:border-accent transition-colors">
+                  <input
+                    type="password"
+                    autoFocus
+                    value={joinPassphrase}
+                    onChange={(e) => {
+                      setJoinPassphrase(e.target.value);
+                      setJoinError("");
+                    }}
+                    onKeyDown={(e) => e.key === "Enter" && handleUnlockInvite()}
+                    placeholder="Passphrase..."
+                    className="flex-1 bg-transparent border-none px-3 py-2.5 text-xs text-text-primary placeholder-text-muted focus:outline-none"
+                  />
+                  <button
+                    onClick={handleUnlockInvite}
+                    disabled={!joinPassphrase}
+                    className="px-3 py-2.5 bg-accent hover:bg-accent-hover text-[#111b21] font-semibold text-xs transition-colors cursor-pointer disabled:opacity-50 disabled:cursor-not-allowed"
+                  >
+                    Unlock
+                  </button>
+                </div>
+              </div>
+            )}
             {joinError && (
               <p className="text-danger text-xs">{joinError}</p>
             )}
@@ -466,7 +536,7 @@ export function Sidebar() {
         {filtered.map((session) => {
           const lastMsg = session.messages[session.messages.length - 1];
           const path = getSessionPath(session);
-          const isActive = currentChatPath === path;
+          const isActive = activeSessionId === session.id;
           const isConfirming = confirmDeleteId === session.id;
           const peerName = session.label || session.nick;
           const peerKey = session.peerPubKeyB64.slice(0, 12) + "...";
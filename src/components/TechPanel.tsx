@@ -92,6 +92,16 @@ export function TechPanel({
             <TechRow label="Network" value={techInfo.protocol} />
             <TechRow label="Encryption" value={techInfo.encryption} />
             <TechRow label="TTL" value={`${techInfo.messageTtl}s`} />
+            <TechRow label="Padding" value={techInfo.padding} />
+            <TechRow
+              label="Peer Payload"
+              value={
+                techInfo.peerPayloadLength > 0
+                  ? `${techInfo.peerPayloadLength} B padded (${techInfo.peerPayloadPadding} B padding)`
+                  : "—"
+              }
+              mono
+            />
           </div>
           <div>
             <h4 className="text-accent text-[10px] font-bold uppercase tracking-wider mb-1 m-0">
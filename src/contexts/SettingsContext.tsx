@@ -28,6 +28,7 @@ interface SettingsContextValue {
   updateLockScreen: (lockScreen: Partial<LockScreenSettings>) => void;
   updateNotifications: (notifications: Partial<NotificationSettings>) => void;
   updateDefaultNickname: (nickname: string) => void;
+  updateProfileStatus: (status: string) => void;
   randomizeNickname: () => void;
   resetSettings: () => void;
 }
@@ -81,6 +82,10 @@ export function SettingsProvider({ children }: { children: ReactNode }) {
     setSettings((prev) => ({ ...prev, defaultNickname: nickname }));
   }, []);
 
+  const updateProfileStatus = useCallback((status: string) => {
+    setSettings((prev) => ({ ...prev, profileStatus: status }));
+  }, []);
+
   const randomizeNickname = useCallback(() => {
     const randomName = getRandomGhostName();
     setSettings((prev) => ({ ...prev, defaultNickname: randomName }));
@@ -103,6 +108,7 @@ export function SettingsProvider({ children }: { children: ReactNode }) {
         updateLockScreen,
         updateNotifications,
         updateDefaultNickname,
+        updateProfileStatus,
         randomizeNickname,
         resetSettings,
       }}
@@ -1,9 +1,19 @@
 import { useEffect, useRef, useState } from "react";
-import { listSessions, addMessage } from "../lib/storage";
-import { resolveMessages } from "../lib/pkarr";
+import {
+  listSessions,
+  addMessage,
+  applyKeyRotation,
+  applyMessageKind,
+  applyMigration,
+  replyPreview,
+  PEER_KEY_CHANGED_TEXT,
+} from "../lib/storage";
+import { getPublicKeyFromSeed, resolveMessages } from "../lib/pkarr";
+import { PollSchedule } from "../lib/pollSchedule";
 import type { ChatMessage } from "../lib/types";
 
-const BG_POLL_INTERVAL = 20_000;
+const BG_POLL_MIN_INTERVAL = 20_000;
+const BG_POLL_MAX_INTERVAL = 120_000;
 
 export interface BackgroundPollerState {
   syncingSessions: Set<string>;
@@ -25,6 +35,10 @@ export function useBackgroundPoller(
     let cancelled = false;
     let timer: ReturnType<typeof setTimeout> | null = null;
     let isFirstRound = true;
+    const schedule = new PollSchedule(
+      BG_POLL_MIN_INTERVAL,
+      BG_POLL_MAX_INTERVAL,
+    );
 
     const lastSeenMap: Record<string, number> = {};
 
@@ -41,6 +55,8 @@ export function useBackgroundPoller(
       if (cancelled) return;
 
       const sessions = listSessions();
+      let sawActivity = false;
+      let sawError = false;
 
       const toSync = sessions
         .filter((s) => s.id !== activeIdRef.current)
@@ -54,9 +70,13 @@ export function useBackgroundPoller(
         if (session.id === activeIdRef.current) continue;
 
         try {
+          const myPubKey = await getPublicKeyFromSeed(session.mySeedB64);
           const batch = await resolveMessages(
             session.peerPubKeyB64,
             session.encKeyB64,
+            myPubKey,
+            session.keyRotation?.previousKeyB64,
+            session.migration?.previousPubKey,
           );
 
           if (cancelled) break;
@@ -69,26 +89,61 @@ export function useBackgroundPoller(
 
           if (!batch) continue;
 
+          const migrated = applyMigration(session.id, batch);
+          if (migrated) {
+            if (migrated.peerPubKeyB64 !== session.peerPubKeyB64) {
+              addMessage(session.id, {
+                id: `peer_moved_${batch.packetTimestamp}`,
+                text: PEER_KEY_CHANGED_TEXT,
+                sender: "system",
+                timestamp: Date.now(),
+                systemEvent: {
+                  type: "identity_moved",
+                  pubKey: migrated.peerPubKeyB64,
+                },
+              });
+            }
+            window.dispatchEvent(new Event("session-updated"));
+          }
+
+          if (applyKeyRotation(session.id, batch)) {
+            window.dispatchEvent(new Event("session-updated"));
+          }
+
           const lastSeen = lastSeenMap[session.id] ?? 0;
 
           if (batch.latestTimestamp > 0 && batch.latestTimestamp > lastSeen) {
             const newMsgs = batch.messages.filter(
               (m) => m.timestamp > lastSeen,
             );
+            sawActivity ||= newMsgs.length > 0;
 
             for (const resolved of newMsgs) {
+              const kind = resolved.kind;
+              if (kind && kind.y !== "reply") {
+                applyMessageKind(session.id, kind, resolved.text);
+                continue;
+              }
               const newMsg: ChatMessage = {
                 id: `peer_${resolved.timestamp}`,
                 text: resolved.text,
-                sender: "peer",
+                sender: resolved.rotation ? "system" : "peer",
                 timestamp: resolved.timestamp,
                 nick: resolved.nick,
+                wireId: resolved.id,
+                replyTo: kind && replyPreview(session.id, kind.to),
                 meta: {
                   dhtKey: session.peerPubKeyB64,
                   encryptedPayloadLength: batch.encryptedPayloadLength,
                   dnsRecords: batch.rawRecordNames,
                   packetTimestamp: batch.packetTimestamp,
                 },
+                ...(resolved.rotation && {
+                  systemEvent: {
+                    type: "key_rotation" as const,
+                    pubKey: session.peerPubKeyB64,
+                  },
+                }),
               };
               addMessage(session.id, newMsg);
             }
@@ -96,6 +151,7 @@ export function useBackgroundPoller(
             lastSeenMap[session.id] = batch.latestTimestamp;
           }
         } catch (err) {
+          sawError = true;
           setSyncingSessions((prev) => {
             const next = new Set(prev);
             next.delete(session.id);
@@ -113,8 +169,16 @@ export function useBackgroundPoller(
         setInitialSyncComplete(true);
       }
 
+      if (sawActivity) {
+        schedule.onActivity();
+      } else if (sawError) {
+        schedule.onError();
+      } else {
+        schedule.onIdle();
+      }
+
       if (!cancelled) {
-        timer = setTimeout(pollAll, BG_POLL_INTERVAL);
+        timer = setTimeout(pollAll, schedule.nextDelay());
       }
     };
 
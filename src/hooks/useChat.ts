@@ -4,19 +4,43 @@ import type {
   ChatParams,
   ConnectionStatus,
   ChatTechInfo,
+  PendingKeyRotation,
+  PendingMigration,
 } from "../lib/types";
 import {
+  CLIENT_CAPABILITIES,
+  PROFILE_VERSION,
   getPublicKeyFromSeed,
+  getSafetyNumber,
+  migrateIdentity as createMigration,
   publishMessages,
   resolveMessages,
+  rotateKey as createKeyRotation,
+} from "../lib/pkarr";
+import type {
+  CompactMessage,
+  PaddingMode,
+  PeerCapabilities,
+  PeerProfile,
+  Presence,
+  SafetyNumber,
 } from "../lib/pkarr";
-import type { CompactMessage } from "../lib/pkarr";
 import {
   loadSession,
   saveSession,
   addMessage,
   generateSessionId,
   getInviteCode,
+  applyKeyRotation,
+  applyMessageKind,
+  applyMigration,
+  replyPreview,
+  startKeyRotation,
+  startMigration,
+  setSessionVerified,
+  KEY_ROTATION_TEXT,
+  IDENTITY_MOVED_TEXT,
+  PEER_KEY_CHANGED_TEXT,
 } from "../lib/storage";
 
 const POLL_INTERVAL_ACTIVE = 2_000;
@@ -26,6 +50,7 @@ const REPUBLISH_INTERVAL = 30 * 60 * 1000;
 const IDLE_THRESHOLD = 60_000;
 const MESSAGE_TTL = 300;
 const MAX_DHT_TEXT_BYTES = 500;
+const PAYLOAD_PADDING: PaddingMode = "bucketed";
 
 export function useChat(params: ChatParams | null) {
   const [messages, setMessages] = useState<ChatMessage[]>([]);
@@ -33,10 +58,17 @@ export function useChat(params: ChatParams | null) {
   const [lastSync, setLastSync] = useState<number | null>(null);
   const [isSending, setIsSending] = useState(false);
   const [peerAck, setPeerAck] = useState<number>(0);
+  const [peerPayload, setPeerPayload] = useState({ length: 0, padding: 0 });
   const [isBurned, setIsBurned] = useState(false);
+  const [safetyNumber, setSafetyNumber] = useState<SafetyNumber | null>(null);
+  const [verified, setVerifiedState] = useState(false);
   const [incomingCallSignal, setIncomingCallSignal] = useState<string | null>(
     null,
   );
+  const [peerPresence, setPeerPresence] = useState<Presence | null>(null);
+  const [peerProfile, setPeerProfile] = useState<PeerProfile | null>(null);
+  const [peerCapabilities, setPeerCapabilities] =
+    useState<PeerCapabilities | null>(null);
   const [pollCountdown, setPollCountdown] = useState<{
     remaining: number;
     total: number;
@@ -58,17 +90,44 @@ export function useChat(params: ChatParams | null) {
   const seedB64Ref = useRef<string>("");
   const peerPubKeyZ32Ref = useRef<string>("");
   const encKeyB64Ref = useRef<string>("");
+  const keyRotationRef = useRef<PendingKeyRotation | undefined>(undefined);
+  const migrationRef = useRef<PendingMigration | undefined>(undefined);
   const nickRef = useRef<string | undefined>(undefined);
+  const profileRef = useRef<PeerProfile | undefined>(undefined);
+  const peerVersionRef = useRef<number | undefined>(undefined);
   const myPubKeyRef = useRef<string>("");
   const sessionCreatedAtRef = useRef<number>(Date.now());
   const pollFnRef = useRef<(() => Promise<void>) | null>(null);
   const isPollingRef = useRef(false);
   const callSignalOutRef = useRef<string | null>(null);
+  const typingRef = useRef(false);
   const doPublishRef = useRef<(() => Promise<void>) | null>(null);
   const fastPollRef = useRef(false);
   const joinMessageSentRef = useRef(false);
   const welcomeMessageSentRef = useRef(false);
 
+  // The initiator of a rotation keeps publishing under the old key until the
+  // peer has switched, so the peer can still read the announcement.
+  const publishKey = () =>
+    keyRotationRef.current?.initiated
+      ? keyRotationRef.current.previousKeyB64
+      : encKeyB64Ref.current;
+
+  const currentPresence = (): Presence => ({
+    a: Date.now(),
+    ...(typingRef.current && { y: true }),
+  });
+
+  const refreshSafetyNumber = () => {
+    getSafetyNumber(
+      seedB64Ref.current,
+      peerPubKeyZ32Ref.current,
+      encKeyB64Ref.current,
+    )
+      .then(setSafetyNumber)
+      .catch(() => setSafetyNumber(null));
+  };
+
   useEffect(() => {
     if (!params) return;
 
@@ -77,6 +136,8 @@ export function useChat(params: ChatParams | null) {
     seedB64Ref.current = params.seedB64;
     peerPubKeyZ32Ref.current = params.peerPubKeyB64;
     encKeyB64Ref.current = params.encKeyB64;
+    keyRotationRef.current = undefined;
+    migrationRef.current = undefined;
     nickRef.current = params.nick;
     lastActivityRef.current = Date.now();
 
@@ -95,6 +156,17 @@ export function useChat(params: ChatParams | null) {
 
       const existing = loadSession(sessionId);
       if (existing) {
+        // Either side may have moved to a new pubkey since this link was made.
+        if (existing.mySeedB64 !== params.seedB64) {
+          seedB64Ref.current = existing.mySeedB64;
+          myPubKeyRef.current = await getPublicKeyFromSeed(existing.mySeedB64);
+          if (cancelled) return;
+        }
+        peerPubKeyZ32Ref.current = existing.peerPubKeyB64;
+        encKeyB64Ref.current = existing.encKeyB64;
+        keyRotationRef.current = existing.keyRotation;
+        migrationRef.current = existing.migration;
+        setVerifiedState(!!existing.verified);
         setMessages(existing.messages);
         const maxPeerTs = existing.messages.reduce(
           (max, m) =>
@@ -125,6 +197,7 @@ export function useChat(params: ChatParams | null) {
           createdAt: now,
         });
         setMessages([]);
+        setVerifiedState(false);
         lastSeenTimestampRef.current = 0;
         sentBufferRef.current = [];
         myAckRef.current = 0;
@@ -138,8 +211,14 @@ export function useChat(params: ChatParams | null) {
 
       burnedRef.current = false;
       setIsBurned(false);
+      setPeerPresence(null);
+      setPeerProfile(null);
+      setPeerCapabilities(null);
+      peerVersionRef.current = undefined;
+      typingRef.current = false;
       pollCountRef.current = 0;
       setStatus("online");
+      refreshSafetyNumber();
 
       const doPublish = async () => {
         if (burnedRef.current || effectIdRef.current !== currentEffectId)
@@ -147,11 +226,16 @@ export function useChat(params: ChatParams | null) {
         const callSig = callSignalOutRef.current ?? undefined;
         await publishMessages(
           seedB64Ref.current,
+          peerPubKeyZ32Ref.current,
           sentBufferRef.current,
-          encKeyB64Ref.current,
+          publishKey(),
           myAckRef.current,
           nickRef.current,
           callSig,
+          PAYLOAD_PADDING,
+          currentPresence(),
+          profileRef.current,
+          peerVersionRef.current,
         );
       };
       doPublishRef.current = doPublish;
@@ -182,7 +266,7 @@ export function useChat(params: ChatParams | null) {
           nick,
           systemEvent: {
             type: "join",
-            pubKey: myPubKey,
+            pubKey: myPubKeyRef.current,
           },
         };
         
@@ -199,10 +283,16 @@ export function useChat(params: ChatParams | null) {
         try {
           await publishMessages(
             seedB64Ref.current,
+            peerPubKeyZ32Ref.current,
             sentBufferRef.current,
-            encKeyB64Ref.current,
+            publishKey(),
             myAckRef.current,
             nick,
+            undefined,
+            PAYLOAD_PADDING,
+            currentPresence(),
+            profileRef.current,
+            peerVersionRef.current,
           );
         } catch {
           // Join message failed - will retry on next poll
@@ -242,6 +332,9 @@ export function useChat(params: ChatParams | null) {
           const batch = await resolveMessages(
             peerPubKeyZ32Ref.current,
             encKeyB64Ref.current,
+            myPubKeyRef.current,
+            keyRotationRef.current?.previousKeyB64,
+            migrationRef.current?.previousPubKey,
           );
 
           if (effectIdRef.current !== currentEffectId) return;
@@ -249,6 +342,50 @@ export function useChat(params: ChatParams | null) {
           let receivedNew = false;
 
           if (batch) {
+            const previousPeer = peerPubKeyZ32Ref.current;
+            const migrated = applyMigration(sessionIdRef.current, batch);
+            if (migrated) {
+              peerPubKeyZ32Ref.current = migrated.peerPubKeyB64;
+              migrationRef.current = migrated.migration;
+              setVerifiedState(!!migrated.verified);
+              refreshSafetyNumber();
+              window.dispatchEvent(new Event("session-updated"));
+            }
+            if (migrated && migrated.peerPubKeyB64 !== previousPeer) {
+              const movedMsg: ChatMessage = {
+                id: `peer_moved_${batch.packetTimestamp}`,
+                text: PEER_KEY_CHANGED_TEXT,
+                sender: "system",
+                timestamp: Date.now(),
+                systemEvent: {
+                  type: "identity_moved",
+                  pubKey: migrated.peerPubKeyB64,
+                },
+              };
+              const updated = addMessage(sessionIdRef.current, movedMsg);
+              if (updated) {
+                setMessages([...updated.messages]);
+              }
+              // Publish under the new address right away so the peer can
+              // finish its migration.
+              lastActivityRef.current = Date.now();
+              await doPublish();
+            }
+
+
+            const rotated = applyKeyRotation(sessionIdRef.current, batch);
+            if (rotated) {
+              encKeyB64Ref.current = rotated.encKeyB64;
+              keyRotationRef.current = rotated.keyRotation;
+              refreshSafetyNumber();
+              window.dispatchEvent(new Event("session-updated"));
+            }
+
+            setPeerPayload({
+              length: batch.encryptedPayloadLength,
+              padding: batch.payloadPadding,
+            });
+
             if (batch.peerAck > 0) {
               sentBufferRef.current = sentBufferRef.current.filter(
                 (m) => m.t > batch.peerAck,
@@ -271,6 +408,14 @@ export function useChat(params: ChatParams | null) {
               let receivedJoinMessage = false;
               
               for (const resolved of newMsgs) {
+                const kind = resolved.kind;
+                if (kind && kind.y !== "reply") {
+                  latestSession =
+                    applyMessageKind(sessionIdRef.current, kind, resolved.text) ??
+                    latestSession;
+                  continue;
+                }
+
                 const isJoinMessage = resolved.text === "👋 joined" || resolved.text.match(/^👋 .+ joined$/);
                 
                 if (isJoinMessage) {
@@ -280,9 +425,11 @@ export function useChat(params: ChatParams | null) {
                 const newMsg: ChatMessage = {
                   id: `peer_${resolved.timestamp}`,
                   text: resolved.text,
-                  sender: isJoinMessage ? "system" : "peer",
+                  sender: isJoinMessage || resolved.rotation ? "system" : "peer",
                   timestamp: resolved.timestamp,
                   nick: resolved.nick,
+                  wireId: resolved.id,
+                  replyTo: kind && replyPreview(sessionIdRef.current, kind.to),
                   meta: {
                     dhtKey: peerPubKeyZ32Ref.current,
                     encryptedPayloadLength: batch.encryptedPayloadLength,
@@ -295,6 +442,12 @@ export function useChat(params: ChatParams | null) {
                       pubKey: peerPubKeyZ32Ref.current,
                     },
                   }),
+                  ...(resolved.rotation && {
+                    systemEvent: {
+                      type: "key_rotation" as const,
+                      pubKey: peerPubKeyZ32Ref.current,
+                    },
+                  }),
                 };
                 latestSession = addMessage(sessionIdRef.current, newMsg);
               }
@@ -343,6 +496,20 @@ export function useChat(params: ChatParams | null) {
             if (batch.callSignal !== null && batch.callSignal !== undefined) {
               setIncomingCallSignal(batch.callSignal);
             }
+
+            if (batch.presence) {
+              setPeerPresence(batch.presence);
+            }
+
+            if (batch.profile) {
+              setPeerProfile(batch.profile);
+            }
+
+            if (batch.capabilities) {
+              setPeerCapabilities(batch.capabilities);
+            }
+            // No `_v` means a client that only opens v0 payloads.
+            peerVersionRef.current = batch.capabilities?.version ?? 0;
           }
 
           if (receivedNew) {
@@ -463,13 +630,20 @@ export function useChat(params: ChatParams | null) {
         { t: timestamp, m: trimmed },
       ];
 
+      typingRef.current = false;
       try {
         const kept = await publishMessages(
           seedB64Ref.current,
+          peerPubKeyZ32Ref.current,
           sentBufferRef.current,
-          encKeyB64Ref.current,
+          publishKey(),
           myAckRef.current,
           nickRef.current,
+          undefined,
+          PAYLOAD_PADDING,
+          currentPresence(),
+          profileRef.current,
+          peerVersionRef.current,
         );
 
         if (kept === 0) {
@@ -495,6 +669,114 @@ export function useChat(params: ChatParams | null) {
     [],
   );
 
+  const rotateKey = useCallback(async (): Promise<string | null> => {
+    if (burnedRef.current) return "Chat has been burned";
+    if (keyRotationRef.current) return "A key rotation is already in progress";
+
+    const rotation = await createKeyRotation();
+    const session = startKeyRotation(sessionIdRef.current, rotation);
+    if (!session) return "Chat session not found";
+
+    encKeyB64Ref.current = session.encKeyB64;
+    keyRotationRef.current = session.keyRotation;
+    lastActivityRef.current = Date.now();
+    refreshSafetyNumber();
+
+    const timestamp = Date.now();
+    const rotationMsg: ChatMessage = {
+      id: `me_${timestamp}`,
+      text: KEY_ROTATION_TEXT,
+      sender: "system",
+      timestamp,
+      nick: nickRef.current,
+      systemEvent: {
+        type: "key_rotation",
+        pubKey: myPubKeyRef.current,
+      },
+    };
+    const updated = addMessage(sessionIdRef.current, rotationMsg);
+    if (updated) {
+      setMessages([...updated.messages]);
+    }
+    window.dispatchEvent(new Event("session-updated"));
+
+    sentBufferRef.current = [
+      ...sentBufferRef.current,
+      { t: timestamp, m: KEY_ROTATION_TEXT, r: rotation },
+    ];
+
+    try {
+      await doPublishRef.current?.();
+    } catch {
+      setStatus("error");
+      return "Failed to announce the new key. It will be retried on the next publish.";
+    }
+    return null;
+  }, []);
+
+  const migrateIdentity = useCallback(async (): Promise<string | null> => {
+    if (burnedRef.current) return "Chat has been burned";
+    if (keyRotationRef.current || migrationRef.current) {
+      return "A key rotation or migration is already in progress";
+    }
+
+    let next: { seedB64: string; pubKeyZ32: string };
+    try {
+      next = await createMigration(
+        seedB64Ref.current,
+        peerPubKeyZ32Ref.current,
+        encKeyB64Ref.current,
+      );
+    } catch {
+      setStatus("error");
+      return "Failed to publish the move. Check your connection.";
+    }
+
+    const session = startMigration(
+      sessionIdRef.current,
+      next.seedB64,
+      myPubKeyRef.current,
+    );
+    if (!session) return "Chat session not found";
+
+    seedB64Ref.current = session.mySeedB64;
+    myPubKeyRef.current = next.pubKeyZ32;
+    migrationRef.current = session.migration;
+    lastActivityRef.current = Date.now();
+    refreshSafetyNumber();
+
+    const timestamp = Date.now();
+    const movedMsg: ChatMessage = {
+      id: `me_${timestamp}`,
+      text: IDENTITY_MOVED_TEXT,
+      sender: "system",
+      timestamp,
+      systemEvent: {
+        type: "identity_moved",
+        pubKey: next.pubKeyZ32,
+      },
+    };
+    const updated = addMessage(sessionIdRef.current, movedMsg);
+    if (updated) {
+      setMessages([...updated.messages]);
+    }
+    window.dispatchEvent(new Event("session-updated"));
+
+    try {
+      await doPublishRef.current?.();
+    } catch {
+      setStatus("error");
+      return "Failed to publish under the new identity. It will be retried on the next publish.";
+    }
+    return null;
+  }, []);
+
+  const setVerified = useCallback((value: boolean) => {
+    setSessionVerified(sessionIdRef.current, value);
+    setVerifiedState(value);
+    window.dispatchEvent(new Event("session-updated"));
+  }, []);
+
   const burn = useCallback(() => {
     burnedRef.current = true;
     setIsBurned(true);
@@ -513,6 +795,19 @@ export function useChat(params: ChatParams | null) {
     nickRef.current = nick || undefined;
   }, []);
 
+  // Published with our next packet that has room for it.
+  const setProfile = useCallback((displayName: string, status: string) => {
+    profileRef.current =
+      displayName || status
+        ? {
+            v: PROFILE_VERSION,
+            ...(displayName && { n: displayName }),
+            ...(status && { s: status }),
+            c: CLIENT_CAPABILITIES,
+          }
+        : undefined;
+  }, []);
+
   const setCallSignal = useCallback(async (signal: string | null) => {
     callSignalOutRef.current = signal;
     lastActivityRef.current = Date.now();
@@ -532,6 +827,15 @@ export function useChat(params: ChatParams | null) {
     }
   }, []);
 
+  const setTyping = useCallback((typing: boolean) => {
+    if (typingRef.current === typing) return;
+    typingRef.current = typing;
+    lastActivityRef.current = Date.now();
+    doPublishRef.current?.().catch(() => {
+      // Typing indicator publish failed silently
+    });
+  }, []);
+
   const setChatFastPoll = useCallback((fast: boolean) => {
     fastPollRef.current = fast;
     if (fast && pollTimerRef.current) {
@@ -562,8 +866,8 @@ export function useChat(params: ChatParams | null) {
     sessionId: sessionIdRef.current,
     myPubKey: myPubKeyRef.current,
     peerPubKey: peerPubKeyZ32Ref.current,
-    encKeyPreview: params
-      ? params.encKeyB64.slice(0, 8) + "..." + params.encKeyB64.slice(-4)
+    encKeyPreview: encKeyB64Ref.current
+      ? encKeyB64Ref.current.slice(0, 8) + "..." + encKeyB64Ref.current.slice(-4)
       : "",
     pollCount: pollCountRef.current,
     currentPollInterval:
@@ -579,6 +883,9 @@ export function useChat(params: ChatParams | null) {
     myAck: myAckRef.current,
     peerAck,
     sentBufferSize: sentBufferRef.current.length,
+    padding: PAYLOAD_PADDING,
+    peerPayloadLength: peerPayload.length,
+    peerPayloadPadding: peerPayload.padding,
   };
 
   return {
@@ -587,6 +894,11 @@ export function useChat(params: ChatParams | null) {
     lastSync,
     isSending,
     sendMessage,
+    rotateKey,
+    migrateIdentity,
+    safetyNumber,
+    verified,
+    setVerified,
     burn,
     setNick,
     isBurned,
@@ -598,5 +910,10 @@ export function useChat(params: ChatParams | null) {
     setChatFastPoll,
     addSystemMessage,
     pollCountdown,
+    peerPresence,
+    setTyping,
+    peerProfile,
+    setProfile,
+    peerCapabilities,
   };
 }
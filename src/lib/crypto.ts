@@ -4,6 +4,32 @@ export async function generateEncryptionKey(): Promise<string> {
   return invoke<string>("generate_enc_key");
 }
 
+// Wraps a chat key under an Argon2id key derived from a passphrase that is
+// shared out of band; unwrapping with a wrong passphrase fails.
+export async function wrapInviteKey(
+  encKeyB64: string,
+  passphrase: string,
+  inviterPubKeyZ32: string,
+): Promise<string> {
+  return invoke<string>("wrap_invite_key", {
+    encKeyB64,
+    passphrase,
+    inviterPubkeyZ32: inviterPubKeyZ32,
+  });
+}
+
+export async function unwrapInviteKey(
+  wrapped: string,
+  passphrase: string,
+  inviterPubKeyZ32: string,
+): Promise<string> {
+  return invoke<string>("unwrap_invite_key", {
+    wrapped,
+    passphrase,
+    inviterPubkeyZ32: inviterPubKeyZ32,
+  });
+}
+
 export async function encrypt(
   plaintext: string,
   keyB64: string,
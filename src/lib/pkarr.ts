@@ -1,9 +1,67 @@
 import { invoke } from "@tauri-apps/api/core";
 
+export interface KeyRotation {
+  k: string;
+  f: number;
+}
+
+// The message a kind acts on: its id where the sender set one (ghostly-cli
+// does), otherwise its timestamp.
+export interface MessageRef {
+  i?: string;
+  t?: number;
+}
+
+// Replies, reactions, edits and deletes; `m` keeps a readable fallback.
+export type MessageKind =
+  | { y: "reply"; to: MessageRef }
+  | { y: "react"; to: MessageRef; e: string }
+  | { y: "edit"; to: MessageRef }
+  | { y: "delete"; to: MessageRef };
+
 export interface PkarrMessage {
   text: string;
   timestamp: number;
   nick?: string;
+  id?: string;
+  rotation?: KeyRotation;
+  kind?: MessageKind;
+}
+
+// Sealed `_pres` record: when the peer was last active, and whether it is
+// typing.
+export interface Presence {
+  a: number;
+  y?: boolean;
+}
+
+// Sealed `_prof` record. `a` is an attachment manifest for a full avatar,
+// which the desktop doesn't fetch yet.
+export interface PeerProfile {
+  v: number;
+  n?: string;
+  s?: string;
+  b?: string;
+  a?: unknown;
+  c?: string[];
+}
+
+export const PROFILE_VERSION = 1;
+// Keep in sync with CAPABILITIES_RECORD in pkarr_client.rs.
+export const CLIENT_CAPABILITIES = ["kinds", "presence", "profile", "calls"];
+
+// Plaintext `_v` record: protocol version and supported features.
+export interface PeerCapabilities {
+  version: number;
+  features: string[];
+}
+
+// Peers that predate `_v` advertise nothing, so nothing is ruled out.
+export function peerLacks(
+  capabilities: PeerCapabilities | null,
+  feature: string,
+): boolean {
+  return !!capabilities && !capabilities.features.includes(feature);
 }
 
 export interface PkarrResolvedBatch {
@@ -12,25 +70,59 @@ export interface PkarrResolvedBatch {
   peerAck: number;
   rawRecordNames: string[];
   encryptedPayloadLength: number;
+  payloadPadding: number;
+  senderAuthenticated: boolean;
+  fallbackKeyUsed: boolean;
+  movedTo: string | null;
   packetTimestamp: number;
   messageCount: number;
   callSignal: string | null;
+  presence: Presence | null;
+  profile: PeerProfile | null;
+  capabilities: PeerCapabilities | null;
+  recordStatus: Record<string, RecordStatus>;
 }
 
+export type RecordStatus = "ok" | "auth_failed" | "malformed" | "legacy_unbound";
+
+export interface SafetyNumber {
+  digits: string;
+  emoji: string;
+}
+
+export type PaddingMode = "off" | "bucketed" | "full";
+
 export interface CompactMessage {
   t: number;
   m: string;
+  r?: KeyRotation;
+  k?: MessageKind;
 }
 
 interface RustResolvedBatch {
-  messages: { text: string; timestamp: number; nick: string | null }[];
+  messages: {
+    text: string;
+    timestamp: number;
+    nick: string | null;
+    id?: string;
+    rotation?: KeyRotation;
+    kind?: MessageKind;
+  }[];
   latest_timestamp: number;
   peer_ack: number;
   raw_record_names: string[];
   encrypted_payload_length: number;
+  payload_padding: number;
+  sender_authenticated: boolean;
+  fallback_key_used: boolean;
+  moved_to: string | null;
   packet_timestamp: number;
   message_count: number;
   call_signal: string | null;
+  presence: Presence | null;
+  profile: PeerProfile | null;
+  capabilities: PeerCapabilities | null;
+  record_status: Record<string, RecordStatus>;
 }
 
 interface RustKeypairResult {
@@ -52,31 +144,79 @@ export async function getPublicKeyFromSeed(
   return invoke<string>("get_public_key", { seedB64 });
 }
 
+// Same on both sides of a chat; compare it out of band to verify the peer.
+export async function getSafetyNumber(
+  seedB64: string,
+  peerPubKeyZ32: string,
+  encKeyB64: string,
+): Promise<SafetyNumber> {
+  return invoke<SafetyNumber>("safety_number", {
+    seedB64,
+    peerPubkeyZ32: peerPubKeyZ32,
+    encKeyB64,
+  });
+}
+
+export async function rotateKey(): Promise<KeyRotation> {
+  return invoke<KeyRotation>("rotate_key");
+}
+
+// Publishes a signed pointer from the current pubkey to a fresh one and
+// returns the new keypair; the caller republishes its outbox under it.
+export async function migrateIdentity(
+  seedB64: string,
+  peerPubKeyZ32: string,
+  encKeyB64: string,
+): Promise<{ seedB64: string; pubKeyZ32: string }> {
+  const result = await invoke<RustKeypairResult>("migrate_identity", {
+    seedB64,
+    peerPubkeyZ32: peerPubKeyZ32,
+    encKeyB64,
+  });
+  return { seedB64: result.seed_b64, pubKeyZ32: result.pub_key_z32 };
+}
+
 export async function publishMessages(
   seedB64: string,
+  peerPubKeyZ32: string,
   messages: CompactMessage[],
   encKeyB64: string,
   ackTimestamp: number,
   nick?: string,
   callSignal?: string,
+  padding?: PaddingMode,
+  presence?: Presence,
+  profile?: PeerProfile,
+  peerVersion?: number,
 ): Promise<number> {
   return invoke<number>("publish_messages", {
     seedB64,
+    peerPubkeyZ32: peerPubKeyZ32,
     messages,
     encKeyB64,
     ackTimestamp,
     nick: nick ?? null,
     callSignal: callSignal ?? null,
+    padding: padding ?? null,
+    presence: presence ?? null,
+    profile: profile ?? null,
+    peerVersion: peerVersion ?? null,
   });
 }
 
 export async function resolveMessages(
   publicKeyZ32: string,
   encKeyB64: string,
+  receiverZ32?: string,
+  fallbackEncKeyB64?: string,
+  fallbackReceiverZ32?: string,
 ): Promise<PkarrResolvedBatch | null> {
   const result = await invoke<RustResolvedBatch | null>("resolve_messages", {
     publicKeyZ32,
     encKeyB64,
+    receiverZ32: receiverZ32 ?? null,
+    fallbackEncKeyB64: fallbackEncKeyB64 ?? null,
+    fallbackReceiverZ32: fallbackReceiverZ32 ?? null,
   });
 
   if (!result) return null;
@@ -86,14 +226,25 @@ export async function resolveMessages(
       text: m.text,
       timestamp: m.timestamp,
       nick: m.nick ?? undefined,
+      id: m.id,
+      rotation: m.rotation,
+      kind: m.kind,
     })),
     latestTimestamp: result.latest_timestamp,
     peerAck: result.peer_ack,
     rawRecordNames: result.raw_record_names,
     encryptedPayloadLength: result.encrypted_payload_length,
+    payloadPadding: result.payload_padding,
+    senderAuthenticated: result.sender_authenticated,
+    fallbackKeyUsed: result.fallback_key_used,
+    movedTo: result.moved_to,
     packetTimestamp: result.packet_timestamp,
     messageCount: result.message_count,
     callSignal: result.call_signal,
+    presence: result.presence,
+    profile: result.profile,
+    capabilities: result.capabilities,
+    recordStatus: result.record_status,
   };
 }
 
@@ -0,0 +1,56 @@
+// Mirrors the CLI's PollSchedule (cli/src/schedule.rs): fast polls right after
+// activity, backoff to a ceiling while idle or failing, plus jitter.
+
+const IDLE_FACTOR = 1.5;
+const ERROR_FACTOR = 2.0;
+const FAST_POLLS_AFTER_ACTIVITY = 5;
+const JITTER_RATIO = 0.2;
+
+export class PollSchedule {
+  private readonly minInterval: number;
+  private readonly maxInterval: number;
+  private current: number;
+  private fastPollsLeft = FAST_POLLS_AFTER_ACTIVITY;
+
+  constructor(minInterval: number, maxInterval: number) {
+    this.minInterval = Math.max(minInterval, 1);
+    this.maxInterval = Math.max(maxInterval, this.minInterval);
+    this.current = this.minInterval;
+  }
+
+  get interval(): number {
+    return this.current;
+  }
+
+  onActivity(): void {
+    this.current = this.minInterval;
+    this.fastPollsLeft = FAST_POLLS_AFTER_ACTIVITY;
+  }
+
+  onIdle(): void {
+    if (this.fastPollsLeft > 0) {
+      this.fastPollsLeft -= 1;
+      return;
+    }
+    this.grow(IDLE_FACTOR);
+  }
+
+  onError(): void {
+    this.fastPollsLeft = 0;
+    this.grow(ERROR_FACTOR);
+  }
+
+  nextDelay(): number {
+    const spread = Math.floor(this.current * JITTER_RATIO);
+    const jitter = Math.round((Math.random() * 2 - 1) * spread);
+    return Math.max(this.current + jitter, 1);
+  }
+
+  private grow(factor: number): void {
+    const next = Math.floor(this.current * factor);
+    this.current = Math.min(
+      Math.max(next, this.minInterval),
+      this.maxInterval,
+    );
+  }
+}
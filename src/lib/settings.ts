@@ -23,6 +23,7 @@ export interface AppSettings {
   lockScreen: LockScreenSettings;
   notifications: NotificationSettings;
   defaultNickname: string;
+  profileStatus: string;
 }
 
 const SETTINGS_KEY = "ghostly_app_settings";
@@ -41,6 +42,7 @@ const DEFAULT_SETTINGS: AppSettings = {
     soundEnabled: true,
   },
   defaultNickname: "",
+  profileStatus: "",
 };
 
 export function loadSettings(getRandomName?: () => string): AppSettings {
@@ -1,4 +1,16 @@
 import type { ChatMessage, ChatSession } from "./types";
+import type {
+  KeyRotation,
+  MessageKind,
+  MessageRef,
+  PkarrResolvedBatch,
+} from "./pkarr";
+
+export const KEY_ROTATION_TEXT = "🔑 Key rotated";
+export const IDENTITY_MOVED_TEXT = "🪪 Identity moved";
+export const PEER_KEY_CHANGED_TEXT =
+  "⚠️ Peer moved to a new key — compare safety numbers again";
+export const DELETED_TEXT = "🗑️ Message deleted";
 
 export const GHOST_NAMES = [
   "Casper", "Phantom", "Specter", "Shadow", "Wraith",
@@ -90,6 +102,177 @@ export function addMessage(
   return session;
 }
 
+export function startKeyRotation(
+  sessionId: string,
+  rotation: KeyRotation,
+): ChatSession | null {
+  const session = loadSession(sessionId);
+  if (!session) return null;
+
+  session.keyRotation = {
+    previousKeyB64: session.encKeyB64,
+    effectiveFrom: rotation.f,
+    initiated: true,
+  };
+  session.encKeyB64 = rotation.k;
+  saveSession(session);
+  return session;
+}
+
+// Completes a pending rotation once the peer publishes under the new key and
+// adopts a rotation announced by the peer. Returns the session if it changed.
+export function applyKeyRotation(
+  sessionId: string,
+  batch: PkarrResolvedBatch,
+): ChatSession | null {
+  const session = loadSession(sessionId);
+  if (!session) return null;
+
+  let changed = false;
+  if (
+    session.keyRotation &&
+    batch.senderAuthenticated &&
+    !batch.fallbackKeyUsed
+  ) {
+    session.keyRotation = undefined;
+    changed = true;
+  }
+
+  for (const m of batch.messages) {
+    const rotation = m.rotation;
+    if (!rotation || rotation.k === session.encKeyB64) continue;
+    if (
+      session.keyRotation &&
+      rotation.f <= session.keyRotation.effectiveFrom
+    ) {
+      continue;
+    }
+    session.keyRotation = {
+      previousKeyB64: session.encKeyB64,
+      effectiveFrom: rotation.f,
+      initiated: false,
+    };
+    session.encKeyB64 = rotation.k;
+    changed = true;
+  }
+
+  if (!changed) return null;
+  saveSession(session);
+  return session;
+}
+
+function findMessage(
+  session: ChatSession,
+  to: MessageRef,
+  sender?: ChatMessage["sender"],
+): ChatMessage | undefined {
+  return session.messages.find(
+    (m) =>
+      m.sender !== "system" &&
+      (!sender || m.sender === sender) &&
+      ((to.i !== undefined && m.wireId === to.i) ||
+        (to.t !== undefined && m.timestamp === to.t)),
+  );
+}
+
+export function replyPreview(
+  sessionId: string,
+  to: MessageRef,
+): ChatMessage["replyTo"] {
+  const session = loadSession(sessionId);
+  const target = session && findMessage(session, to);
+  if (!target) return undefined;
+  return { timestamp: target.timestamp, text: target.text };
+}
+
+// Applies a peer's reaction, edit or delete to the message it targets rather
+// than adding it to the chat. Edits and deletes only touch the peer's own
+// messages. Replies are regular messages and are left to the caller.
+export function applyMessageKind(
+  sessionId: string,
+  kind: Exclude<MessageKind, { y: "reply" }>,
+  text: string,
+): ChatSession | null {
+  const session = loadSession(sessionId);
+  if (!session) return null;
+
+  const ownOnly = kind.y === "edit" || kind.y === "delete";
+  const target = findMessage(session, kind.to, ownOnly ? "peer" : undefined);
+  if (!target || target.deleted) return session;
+
+  switch (kind.y) {
+    case "react":
+      target.reactions = { ...target.reactions, peer: kind.e };
+      break;
+    case "edit":
+      target.text = text;
+      target.edited = true;
+      break;
+    case "delete":
+      target.text = DELETED_TEXT;
+      target.deleted = true;
+      target.edited = undefined;
+      target.reactions = undefined;
+      break;
+  }
+  saveSession(session);
+  return session;
+}
+
+export function startMigration(
+  sessionId: string,
+  newSeedB64: string,
+  previousPubKey: string,
+): ChatSession | null {
+  const session = loadSession(sessionId);
+  if (!session) return null;
+
+  session.migration = {
+    previousSeedB64: session.mySeedB64,
+    previousPubKey,
+    movedAt: Date.now(),
+  };
+  session.mySeedB64 = newSeedB64;
+  aliasSession(sessionId, session.mySeedB64, session.peerPubKeyB64);
+  saveSession(session);
+  return session;
+}
+
+// Follows a peer that moved to a new pubkey and completes our own pending
+// migration once the peer addresses the new one. Returns the session if it
+// changed.
+export function applyMigration(
+  sessionId: string,
+  batch: PkarrResolvedBatch,
+): ChatSession | null {
+  const session = loadSession(sessionId);
+  if (!session) return null;
+
+  if (batch.movedTo && batch.movedTo !== session.peerPubKeyB64) {
+    session.peerPubKeyB64 = batch.movedTo;
+    session.verified = undefined;
+    aliasSession(sessionId, session.mySeedB64, session.peerPubKeyB64);
+  } else if (
+    session.migration &&
+    batch.senderAuthenticated &&
+    !batch.fallbackKeyUsed
+  ) {
+    session.migration = undefined;
+  } else {
+    return null;
+  }
+
+  saveSession(session);
+  return session;
+}
+
+export function setSessionVerified(sessionId: string, verified: boolean): void {
+  const session = loadSession(sessionId);
+  if (!session) return;
+  session.verified = verified || undefined;
+  saveSession(session);
+}
+
 export function deleteSession(sessionId: string): void {
   try {
     localStorage.removeItem(getKey(sessionId));
@@ -190,10 +373,7 @@ export function getInviteCode(sessionId: string): string | null {
   }
 }
 
-export function generateSessionId(
-  mySeedB64: string,
-  peerPubKeyB64: string,
-): string {
+function hashSessionId(mySeedB64: string, peerPubKeyB64: string): string {
   const combined = mySeedB64.slice(0, 8) + peerPubKeyB64.slice(0, 8);
   let hash = 0;
   for (let i = 0; i < combined.length; i++) {
@@ -203,3 +383,31 @@ export function generateSessionId(
   }
   return Math.abs(hash).toString(36);
 }
+
+// Keeps a session reachable under its original id after either side moved
+// to a new pubkey.
+function aliasSession(
+  sessionId: string,
+  mySeedB64: string,
+  peerPubKeyB64: string,
+): void {
+  const aliasId = hashSessionId(mySeedB64, peerPubKeyB64);
+  if (aliasId === sessionId) return;
+  try {
+    localStorage.setItem(`${getPrefix()}alias_${aliasId}`, sessionId);
+  } catch {
+    // storage full or unavailable
+  }
+}
+
+export function generateSessionId(
+  mySeedB64: string,
+  peerPubKeyB64: string,
+): string {
+  const id = hashSessionId(mySeedB64, peerPubKeyB64);
+  try {
+    return localStorage.getItem(`${getPrefix()}alias_${id}`) ?? id;
+  } catch {
+    return id;
+  }
+}
@@ -15,7 +15,9 @@ export type CallEventType =
 
 export type SystemEventType =
   | "join"
-  | "call";
+  | "call"
+  | "key_rotation"
+  | "identity_moved";
 
 export interface ChatMessage {
   id: string;
@@ -33,6 +35,15 @@ export interface ChatMessage {
     hasVideo?: boolean;
     duration?: number;
   };
+  // Id the sender put on the wire, if any; replies and reactions may use it.
+  wireId?: string;
+  replyTo?: {
+    timestamp: number;
+    text: string;
+  };
+  reactions?: Partial<Record<"me" | "peer", string>>;
+  edited?: boolean;
+  deleted?: boolean;
 }
 
 export interface ChatSession {
@@ -45,6 +56,26 @@ export interface ChatSession {
   lastSyncAt?: number;
   nick?: string;
   label?: string;
+  keyRotation?: PendingKeyRotation;
+  migration?: PendingMigration;
+  // Safety number compared out of band; cleared when the peer pubkey changes.
+  verified?: boolean;
+}
+
+// Kept until the peer publishes under the new key; the side that initiated
+// the rotation keeps publishing under the old key so the peer can read it.
+export interface PendingKeyRotation {
+  previousKeyB64: string;
+  effectiveFrom: number;
+  initiated: boolean;
+}
+
+// Kept until the peer addresses our new pubkey, so payloads it still seals
+// for the old one can be read.
+export interface PendingMigration {
+  previousSeedB64: string;
+  previousPubKey: string;
+  movedAt: number;
 }
 
 export interface ChatParams {
@@ -93,4 +124,7 @@ export interface ChatTechInfo {
   myAck: number;
   peerAck: number;
   sentBufferSize: number;
+  padding: string;
+  peerPayloadLength: number;
+  peerPayloadPadding: number;
 }
@@ -1,6 +1,10 @@
+import { invoke } from "@tauri-apps/api/core";
 import type { ChatParams } from "./types";
+import { unwrapInviteKey, wrapInviteKey } from "./crypto";
 
 const SEPARATOR = "/";
+const PROTECTED_KEY_PREFIX = "pw.";
+const COMPACT_CODE_PREFIX = "ghost1";
 
 export function encodeChatParams(params: ChatParams): string {
   return `${params.seedB64}${SEPARATOR}${params.peerPubKeyB64}${SEPARATOR}${params.encKeyB64}`;
@@ -40,3 +44,53 @@ export function buildInviteUrl(
 ): string {
   return `${origin}/#/chat/${seedB}/${pubKeyA}/${encKey}`;
 }
+
+export function isProtectedInvite(chatPath: string): boolean {
+  const key = chatPath.split(SEPARATOR).pop() ?? "";
+  return key.startsWith(PROTECTED_KEY_PREFIX);
+}
+
+// Replaces the key in an invite code with one wrapped under `passphrase`.
+export async function protectInviteCode(
+  code: string,
+  passphrase: string,
+): Promise<string> {
+  const [seedB, pubKeyA, encKey] = code.split(SEPARATOR);
+  const wrapped = await wrapInviteKey(encKey, passphrase, pubKeyA);
+  return buildInviteCode(seedB, pubKeyA, PROTECTED_KEY_PREFIX + wrapped);
+}
+
+// Turns a protected `/chat/...` path back into a joinable one; rejects when
+// the passphrase is wrong.
+export async function unlockChatPath(
+  chatPath: string,
+  passphrase: string,
+): Promise<string> {
+  const parts = chatPath.split(SEPARATOR);
+  const wrapped = parts[parts.length - 1].slice(PROTECTED_KEY_PREFIX.length);
+  const pubKeyA = parts[parts.length - 2];
+  parts[parts.length - 1] = await unwrapInviteKey(wrapped, passphrase, pubKeyA);
+  return parts.join(SEPARATOR);
+}
+
+export function isCompactInviteCode(input: string): boolean {
+  const trimmed = input.trim();
+  return (
+    trimmed.toLowerCase().startsWith(COMPACT_CODE_PREFIX) &&
+    !trimmed.includes(SEPARATOR)
+  );
+}
+
+// Packs an invite code into a checksummed `ghost1...` string for QR codes.
+export async function compactInviteCode(code: string): Promise<string> {
+  return invoke<string>("encode_invite_code", { code });
+}
+
+// Rejects on a typo (bad checksum) or an invite meant for ghostly-cli.
+export async function expandInviteCode(compact: string): Promise<string> {
+  return invoke<string>("decode_invite_code", { code: compact });
+}
+
+export async function inviteQrSvg(code: string): Promise<string> {
+  return invoke<string>("invite_qr_svg", { code });
+}
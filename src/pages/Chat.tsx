@@ -18,6 +18,11 @@ import {
   addMessage,
 } from "../lib/storage";
 import type { ChatParams, CallSignal, CallEventType, ChatMessage } from "../lib/types";
+import { compactInviteCode, inviteQrSvg, protectInviteCode } from "../lib/url";
+import { peerLacks } from "../lib/pkarr";
+
+const TYPING_WINDOW = 30_000;
+const ONLINE_WINDOW = 2 * 60_000;
 
 export function Chat() {
   const { "*": splat } = useParams();
@@ -44,6 +49,11 @@ export function Chat() {
     lastSync,
     isSending,
     sendMessage,
+    rotateKey,
+    migrateIdentity,
+    safetyNumber,
+    verified,
+    setVerified,
     techInfo,
     peerAck,
     forceRefresh,
@@ -53,6 +63,11 @@ export function Chat() {
     addSystemMessage,
     setNick,
     pollCountdown,
+    peerPresence,
+    setTyping,
+    peerProfile,
+    setProfile,
+    peerCapabilities,
   } = useChat(params);
 
   useEffect(() => {
@@ -61,6 +76,10 @@ export function Chat() {
     }
   }, [settings.defaultNickname, setNick]);
 
+  useEffect(() => {
+    setProfile(settings.defaultNickname, settings.profileStatus);
+  }, [settings.defaultNickname, settings.profileStatus, setProfile]);
+
   const addCallEventMessage = useCallback(
     (type: CallEventType, hasVideo: boolean, duration?: number) => {
       const textMap: Record<CallEventType, string> = {
@@ -108,6 +127,9 @@ export function Chat() {
 
   const [inviteCode, setInviteCode] = useState<string | null>(null);
   const [codeCopied, setCodeCopied] = useState(false);
+  const [invitePassphrase, setInvitePassphrase] = useState("");
+  const [protectedCode, setProtectedCode] = useState<string | null>(null);
+  const [qrSvg, setQrSvg] = useState<string | null>(null);
   const [chatLabel, setChatLabel] = useState<string>("");
   const [peerNick, setPeerNick] = useState<string>("");
   const [isEditingLabel, setIsEditingLabel] = useState(false);
@@ -168,6 +190,20 @@ export function Chat() {
     window.dispatchEvent(new Event("session-updated"));
   };
 
+  const handleToggleQr = async () => {
+    if (qrSvg) {
+      setQrSvg(null);
+      return;
+    }
+    if (!inviteCode) return;
+    let code = inviteCode;
+    if (invitePassphrase) {
+      code = protectedCode ?? (await protectInviteCode(inviteCode, invitePassphrase));
+      setProtectedCode(code);
+    }
+    setQrSvg(await inviteQrSvg(await compactInviteCode(code)));
+  };
+
   const handleCopyCode = async (text: string) => {
     try {
       await navigator.clipboard.writeText(text);
@@ -242,10 +278,24 @@ export function Chat() {
     : "";
 
   const truncatedPeerKey = params.peerPubKeyB64.slice(0, 12) + "...";
-  const displayName = chatLabel || peerNick;
+  const displayName = chatLabel || peerProfile?.n || peerNick;
   const isAnonymous = !displayName;
   const showKeySubtitle = true;
 
+  // Presence older than these windows is stale: the peer closed the app or
+  // stopped typing without publishing again.
+  const presenceAge = peerPresence ? Date.now() - peerPresence.a : Infinity;
+  const presenceLabel =
+    peerPresence?.y && presenceAge < TYPING_WINDOW
+      ? "typing…"
+      : presenceAge < ONLINE_WINDOW
+        ? "online"
+        : null;
+  const subtitle = presenceLabel ?? peerProfile?.s;
+  const callsUnsupported = peerLacks(peerCapabilities, "calls");
+  const callTitle = (label: string) =>
+    callsUnsupported ? "Peer's client can't take calls" : label;
+
   return (
     <div className="flex-1 flex flex-col h-full bg-chat-bg">
       {/* Chat Header */}
@@ -317,6 +367,13 @@ export function Chat() {
                 isPolling={pollCountdown.isPolling}
                 size={14}
               />
+              {subtitle && (
+                <span
+                  className={`text-xs truncate ${presenceLabel ? "text-accent" : "text-text-muted"}`}
+                >
+                  {subtitle}
+                </span>
+              )}
               {showKeySubtitle && (
                 <span className="text-text-muted/60 text-xs font-mono truncate">
                   {truncatedPeerKey}
@@ -329,9 +386,9 @@ export function Chat() {
           {/* Audio call button */}
           <button
             onClick={() => webrtc.startCall(false)}
-            disabled={webrtc.callState !== "idle"}
+            disabled={webrtc.callState !== "idle" || callsUnsupported}
             className="p-2 text-text-secondary hover:text-accent rounded-full hover:bg-surface-hover transition-colors cursor-pointer disabled:opacity-30 disabled:cursor-not-allowed"
-            title="Audio call"
+            title={callTitle("Audio call")}
           >
             <svg
               width="18"
@@ -349,9 +406,9 @@ export function Chat() {
           {/* Video call button */}
           <button
             onClick={() => webrtc.startCall(true)}
-            disabled={webrtc.callState !== "idle"}
+            disabled={webrtc.callState !== "idle" || callsUnsupported}
             className="p-2 text-text-secondary hover:text-accent rounded-full hover:bg-surface-hover transition-colors cursor-pointer disabled:opacity-30 disabled:cursor-not-allowed"
-            title="Video call"
+            title={callTitle("Video call")}
           >
             <svg
               width="18"
@@ -427,6 +484,50 @@ export function Chat() {
                   </svg>
                   Refresh
                 </button>
+                <button
+                  onClick={async () => {
+                    setMenuOpen(false);
+                    const error = await rotateKey();
+                    if (error) {
+                      addSystemMessage({
+                        id: `system_rotate_${Date.now()}`,
+                        text: error,
+                        sender: "system",
+                        timestamp: Date.now(),
+                      });
+                    }
+                  }}
+                  className="w-full px-3 py-2 text-left text-sm text-text-secondary hover:bg-surface-hover hover:text-text-primary flex items-center gap-2 transition-colors"
+                >
+                  <svg width="16" height="16" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2" strokeLinecap="round" strokeLinejoin="round">
+                    <circle cx="7.5" cy="15.5" r="5.5" />
+                    <path d="M21 2l-9.6 9.6" />
+                    <path d="M15.5 7.5l3 3L22 7l-3-3" />
+                  </svg>
+                  Rotate key
+                </button>
+                <button
+                  onClick={async () => {
+                    setMenuOpen(false);
+                    const error = await migrateIdentity();
+                    if (error) {
+                      addSystemMessage({
+                        id: `system_migrate_${Date.now()}`,
+                        text: error,
+                        sender: "system",
+                        timestamp: Date.now(),
+                      });
+                    }
+                  }}
+                  className="w-full px-3 py-2 text-left text-sm text-text-secondary hover:bg-surface-hover hover:text-text-primary flex items-center gap-2 transition-colors"
+                >
+                  <svg width="16" height="16" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2" strokeLinecap="round" strokeLinejoin="round">
+                    <polyline points="15 3 21 3 21 9" />
+                    <line x1="10" y1="14" x2="21" y2="3" />
+                    <path d="M18 13v6a2 2 0 0 1-2 2H5a2 2 0 0 1-2-2V8a2 2 0 0 1 2-2h6" />
+                  </svg>
+                  Migrate identity
+                </button>
                 <button
                   onClick={() => {
                     setShowTechInfo(true);
@@ -491,15 +592,53 @@ export function Chat() {
                 </p>
                 <div className="flex items-center gap-2">
                   <code className="flex-1 bg-input-bg rounded-lg px-3 py-2 text-[10px] text-text-muted font-mono truncate text-left select-all">
-                    {inviteCode}
+                    {invitePassphrase ? (protectedCode ?? "••••••••") : inviteCode}
                   </code>
                   <button
-                    onClick={() => handleCopyCode(inviteCode)}
+                    onClick={async () => {
+                      if (!invitePassphrase) {
+                        handleCopyCode(inviteCode);
+                        return;
+                      }
+                      const code =
+                        protectedCode ??
+                        (await protectInviteCode(inviteCode, invitePassphrase));
+                      setProtectedCode(code);
+                      handleCopyCode(code);
+                    }}
                     className="px-3 py-2 bg-accent text-[#111b21] rounded-lg text-xs font-bold hover:bg-accent-hover transition-colors cursor-pointer shrink-0"
                   >
                     {codeCopied ? "Copied!" : "Copy"}
                   </button>
+                  <button
+                    onClick={handleToggleQr}
+                    className="px-3 py-2 bg-surface-hover text-text-secondary rounded-lg text-xs font-bold hover:text-accent transition-colors cursor-pointer shrink-0"
+                  >
+                    {qrSvg ? "Hide QR" : "QR"}
+                  </button>
                 </div>
+                {qrSvg && (
+                  <div
+                    className="bg-white p-3 rounded-xl inline-block"
+                    dangerouslySetInnerHTML={{ __html: qrSvg }}
+                  />
+                )}
+                <input
+                  type="password"
+                  value={invitePassphrase}
+                  onChange={(e) => {
+                    setInvitePassphrase(e.target.value);
+                    setProtectedCode(null);
+                    setQrSvg(null);
+                  }}
+                  placeholder="Protect with a passphrase (optional)"
+                  className="w-full bg-input-bg rounded-lg px-3 py-2 text-xs text-text-primary placeholder-text-muted border border-border focus:border-accent focus:outline-none"
+                />
+                {invitePassphrase && (
+                  <p className="text-text-muted text-[10px]">
+                    Send the passphrase over a different channel than the code.
+                  </p>
+                )}
               </div>
             </div>
           )}
@@ -520,7 +659,12 @@ export function Chat() {
       </div>
 
       {/* Input */}
-      <MessageInput key={splat} onSend={sendMessage} disabled={isSending} />
+      <MessageInput
+        key={splat}
+        onSend={sendMessage}
+        onTyping={setTyping}
+        disabled={isSending}
+      />
 
       {/* Incoming call notification */}
       {webrtc.callState === "incoming" && (
@@ -582,10 +726,37 @@ export function Chat() {
                 <TechInfoRow label="Peer Key" value={techInfo.peerPubKey} mono copyable />
                 <TechInfoRow label="Enc Key" value={techInfo.encKeyPreview} mono />
               </TechInfoSection>
+              {safetyNumber && (
+                <TechInfoSection title="Safety Number">
+                  <p className="text-xs text-text-muted">
+                    Compare with your peer over another channel. It changes if either key does.
+                  </p>
+                  <div className="text-center text-lg tracking-widest">{safetyNumber.emoji}</div>
+                  <div className="text-center font-mono text-xs text-text-secondary break-words">
+                    {safetyNumber.digits}
+                  </div>
+                  <button
+                    onClick={() => setVerified(!verified)}
+                    className={`w-full mt-1 px-3 py-1.5 rounded-lg text-xs transition-colors ${verified ? "bg-accent/20 text-accent hover:bg-accent/30" : "bg-surface-hover text-text-secondary hover:text-text-primary"}`}
+                  >
+                    {verified ? "✓ Verified — click to clear" : "Mark as verified"}
+                  </button>
+                </TechInfoSection>
+              )}
               <TechInfoSection title="Protocol">
                 <TechInfoRow label="Network" value={techInfo.protocol} />
                 <TechInfoRow label="Encryption" value={techInfo.encryption} />
                 <TechInfoRow label="TTL" value={`${techInfo.messageTtl}s`} />
+                <TechInfoRow label="Padding" value={techInfo.padding} />
+                <TechInfoRow
+                  label="Peer Payload"
+                  value={
+                    techInfo.peerPayloadLength > 0
+                      ? `${techInfo.peerPayloadLength} B padded (${techInfo.peerPayloadPadding} B padding)`
+                      : "—"
+                  }
+                  mono
+                />
                 <TechInfoRow label="Created" value={new Date(techInfo.createdAt).toLocaleString()} />
               </TechInfoSection>
               <TechInfoSection title="Sync">
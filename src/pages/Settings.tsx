@@ -22,7 +22,7 @@ import {
 
 export function Settings() {
   const navigate = useNavigate();
-  const { settings, updateColorScheme, updateColorTheme, updateLanguage, updateLockScreen, updateNotifications, updateDefaultNickname, randomizeNickname } =
+  const { settings, updateColorScheme, updateColorTheme, updateLanguage, updateLockScreen, updateNotifications, updateDefaultNickname, updateProfileStatus, randomizeNickname } =
     useSettings();
   const { t } = useI18n();
   const { lock } = useLockScreen();
@@ -234,6 +234,23 @@ export function Settings() {
                   </button>
                 </div>
               </div>
+
+              <div className="space-y-2">
+                <label className="text-text-primary block font-medium">
+                  {t("settings.profileStatus")}
+                </label>
+                <p className="text-text-muted text-xs">
+                  {t("settings.profileStatusHint")}
+                </p>
+                <input
+                  type="text"
+                  value={settings.profileStatus}
+                  onChange={(e) => updateProfileStatus(e.target.value)}
+                  placeholder={t("settings.profileStatusPlaceholder")}
+                  maxLength={60}
+                  className="w-full px-3 py-2 bg-input-bg border border-border rounded-lg text-text-primary placeholder-text-muted focus:outline-none focus:border-accent transition-colors"
+                />
+              </div>
             </div>
           </section>
 